@@ -0,0 +1,210 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// A minimal libretro frontend to test the core without RetroArch: it loads the core like a frontend does,
+// runs a ROM for some frames and prints the screen, the audio and the options, then checks the save states.
+// cargo build -p rusted-chip8-libretro --release
+// cargo run -p rusted-chip8-libretro --example test_frontend -- <core library> <ROM> [frames] [key=value options...]
+use rusted_chip8_libretro::ffi::libretro_h::{
+    RetroAudioSampleBatchFn, RetroAudioSampleFn, RetroEnvironmentFn, RetroGameInfo, RetroInputPollFn,
+    RetroInputStateFn, RetroSystemAvInfo, RetroSystemInfo, RetroVariable, RetroVideoRefreshFn,
+    RETRO_ENVIRONMENT_GET_VARIABLE, RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE, RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+    RETRO_ENVIRONMENT_SET_VARIABLES, RETRO_PIXEL_FORMAT_XRGB8888 };
+
+use libloading::{ Library, Symbol };
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{ c_uint, c_void, CStr, CString };
+use std::mem::MaybeUninit;
+use std::process;
+use std::ptr;
+use std::sync::Mutex;
+
+// What the core sent through the callbacks.
+struct Received {
+    frame: Vec<u32>,
+    frame_width: usize,
+    frames: u64,
+    audio_frames: u64,
+    loud_audio_frames: u64,
+}
+
+static RECEIVED: Mutex<Received> = Mutex::new(Received {
+    frame: Vec::new(),
+    frame_width: 0,
+    frames: 0,
+    audio_frames: 0,
+    loud_audio_frames: 0,
+});
+
+// The values given to the core options (key=value arguments); kept alive while the core runs.
+static OPTIONS: Mutex<Option<HashMap<CString, CString>>> = Mutex::new(None);
+
+unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match cmd {
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => *(data as *const c_uint) == RETRO_PIXEL_FORMAT_XRGB8888,
+        RETRO_ENVIRONMENT_SET_VARIABLES => {
+            let mut variable = data as *const RetroVariable;
+            while !(*variable).key.is_null() {
+                println!("Option {}: {}",
+                    CStr::from_ptr((*variable).key).to_string_lossy(),
+                    CStr::from_ptr((*variable).value).to_string_lossy());
+                variable = variable.add(1);
+            }
+            true
+        },
+        RETRO_ENVIRONMENT_GET_VARIABLE => {
+            let variable = &mut *(data as *mut RetroVariable);
+            let options = OPTIONS.lock().unwrap();
+            match options.as_ref().and_then(|options| options.get(CStr::from_ptr(variable.key))) {
+                Some(value) => {
+                    variable.value = value.as_ptr();
+                    true
+                },
+                None => false
+            }
+        },
+        RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE => {
+            *(data as *mut bool) = false;
+            true
+        },
+        _ => false
+    }
+}
+
+unsafe extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    let mut received = RECEIVED.lock().unwrap();
+    received.frames += 1;
+    if data.is_null() {
+        return;
+    }
+
+    received.frame.clear();
+    for y in 0..height as usize {
+        let row = (data as *const u8).add(y * pitch) as *const u32;
+        received.frame.extend_from_slice(std::slice::from_raw_parts(row, width as usize));
+    }
+    received.frame_width = width as usize;
+}
+
+unsafe extern "C" fn audio_sample(_left: i16, _right: i16) {}
+
+unsafe extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+    let samples = std::slice::from_raw_parts(data, frames * 2);
+    let mut received = RECEIVED.lock().unwrap();
+    received.audio_frames += frames as u64;
+    received.loud_audio_frames += samples.chunks_exact(2).filter(|frame| frame[0] != 0).count() as u64;
+    frames
+}
+
+unsafe extern "C" fn input_poll() {}
+
+// No keys are pressed.
+unsafe extern "C" fn input_state(_port: c_uint, _device: c_uint, _index: c_uint, _id: c_uint) -> i16 {
+    0
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("Usage: test_frontend <core library> <ROM> [frames] [key=value options...]");
+        process::exit(2);
+    }
+    let frames: u64 = args.get(2).and_then(|frames| frames.parse().ok()).unwrap_or(60);
+    let options = args.iter().skip(3).filter_map(|arg| {
+        let (key, value) = arg.split_once('=')?;
+        Some((CString::new(key).ok()?, CString::new(value).ok()?))
+    });
+    *OPTIONS.lock().unwrap() = Some(options.collect());
+
+    let rom = std::fs::read(&args[1]).unwrap_or_else(|err| {
+        eprintln!("Error: couldn't read the ROM ({err}).");
+        process::exit(1);
+    });
+
+    unsafe {
+        let core = Library::new(&args[0]).unwrap_or_else(|err| {
+            eprintln!("Error: couldn't load the core ({err}).");
+            process::exit(1);
+        });
+
+        let api_version: Symbol<extern "C" fn() -> c_uint> = core.get(b"retro_api_version").unwrap();
+        let get_system_info: Symbol<unsafe extern "C" fn(*mut RetroSystemInfo)> = core.get(b"retro_get_system_info").unwrap();
+        let get_system_av_info: Symbol<unsafe extern "C" fn(*mut RetroSystemAvInfo)> = core.get(b"retro_get_system_av_info").unwrap();
+        let set_environment: Symbol<extern "C" fn(RetroEnvironmentFn)> = core.get(b"retro_set_environment").unwrap();
+        let set_video_refresh: Symbol<extern "C" fn(RetroVideoRefreshFn)> = core.get(b"retro_set_video_refresh").unwrap();
+        let set_audio_sample: Symbol<extern "C" fn(RetroAudioSampleFn)> = core.get(b"retro_set_audio_sample").unwrap();
+        let set_audio_sample_batch: Symbol<extern "C" fn(RetroAudioSampleBatchFn)> = core.get(b"retro_set_audio_sample_batch").unwrap();
+        let set_input_poll: Symbol<extern "C" fn(RetroInputPollFn)> = core.get(b"retro_set_input_poll").unwrap();
+        let set_input_state: Symbol<extern "C" fn(RetroInputStateFn)> = core.get(b"retro_set_input_state").unwrap();
+        let init: Symbol<extern "C" fn()> = core.get(b"retro_init").unwrap();
+        let deinit: Symbol<extern "C" fn()> = core.get(b"retro_deinit").unwrap();
+        let load_game: Symbol<unsafe extern "C" fn(*const RetroGameInfo) -> bool> = core.get(b"retro_load_game").unwrap();
+        let unload_game: Symbol<extern "C" fn()> = core.get(b"retro_unload_game").unwrap();
+        let run: Symbol<extern "C" fn()> = core.get(b"retro_run").unwrap();
+        let serialize_size: Symbol<extern "C" fn() -> usize> = core.get(b"retro_serialize_size").unwrap();
+        let serialize: Symbol<unsafe extern "C" fn(*mut c_void, usize) -> bool> = core.get(b"retro_serialize").unwrap();
+        let unserialize: Symbol<unsafe extern "C" fn(*const c_void, usize) -> bool> = core.get(b"retro_unserialize").unwrap();
+
+        let mut system_info = MaybeUninit::<RetroSystemInfo>::uninit();
+        get_system_info(system_info.as_mut_ptr());
+        let system_info = system_info.assume_init();
+        println!("Core: {} {} (libretro API {}), extensions: {}",
+            CStr::from_ptr(system_info.library_name).to_string_lossy(),
+            CStr::from_ptr(system_info.library_version).to_string_lossy(),
+            api_version(),
+            CStr::from_ptr(system_info.valid_extensions).to_string_lossy());
+
+        set_environment(environment);
+        set_video_refresh(video_refresh);
+        set_audio_sample(audio_sample);
+        set_audio_sample_batch(audio_sample_batch);
+        set_input_poll(input_poll);
+        set_input_state(input_state);
+        init();
+
+        let game = RetroGameInfo { path: ptr::null(), data: rom.as_ptr() as *const c_void, size: rom.len(), meta: ptr::null() };
+        if !load_game(&game) {
+            eprintln!("Error: the core couldn't load the ROM.");
+            process::exit(1);
+        }
+
+        let mut av_info = MaybeUninit::<RetroSystemAvInfo>::uninit();
+        get_system_av_info(av_info.as_mut_ptr());
+        let av_info = av_info.assume_init();
+        println!("Video: {}x{} at {}fps, audio: {}hz",
+            av_info.geometry.base_width, av_info.geometry.base_height, av_info.timing.fps, av_info.timing.sample_rate);
+
+        for _ in 0..frames {
+            run();
+        }
+
+        // Save states: a state loaded back must be saved again as the same bytes.
+        let size = serialize_size();
+        let mut state = vec![0u8;size];
+        let mut reloaded_state = vec![0u8;size];
+        let round_trip = serialize(state.as_mut_ptr() as *mut c_void, size)
+            && unserialize(state.as_ptr() as *const c_void, size)
+            && serialize(reloaded_state.as_mut_ptr() as *mut c_void, size)
+            && state == reloaded_state;
+        let garbage_rejected = !unserialize(vec![0xffu8;size].as_ptr() as *const c_void, size);
+
+        let received = RECEIVED.lock().unwrap();
+        for row in received.frame.chunks(received.frame_width.max(1)) {
+            println!("{}", row.iter().map(|color| if *color != 0 { '#' } else { '.' }).collect::<String>());
+        }
+        println!("{} video frames, {} audio frames ({} with the buzzer on).",
+            received.frames, received.audio_frames, received.loud_audio_frames);
+        println!("Save state of {size} bytes: round trip {}, invalid state {}.",
+            if round_trip { "ok" } else { "FAILED" },
+            if garbage_rejected { "rejected" } else { "ACCEPTED" });
+        drop(received);
+
+        unload_game();
+        deinit();
+        if !round_trip || !garbage_rejected {
+            process::exit(1);
+        }
+    }
+}
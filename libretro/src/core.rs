@@ -0,0 +1,386 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// The libretro API: the functions the frontend calls, exported with their C names.
+// The frontend calls them one at a time, so the callbacks and the loaded game are kept behind a single lock.
+pub mod retro_core {
+    use crate::ffi::libretro_h::{
+        RetroAudioSampleBatchFn, RetroAudioSampleFn, RetroEnvironmentFn, RetroGameGeometry, RetroGameInfo,
+        RetroInputPollFn, RetroInputStateFn, RetroSystemAvInfo, RetroSystemInfo, RetroSystemTiming,
+        RetroVideoRefreshFn, RETRO_API_VERSION, RETRO_DEVICE_ID_JOYPAD_A, RETRO_DEVICE_ID_JOYPAD_B,
+        RETRO_DEVICE_ID_JOYPAD_DOWN, RETRO_DEVICE_ID_JOYPAD_L, RETRO_DEVICE_ID_JOYPAD_L2, RETRO_DEVICE_ID_JOYPAD_L3,
+        RETRO_DEVICE_ID_JOYPAD_LEFT, RETRO_DEVICE_ID_JOYPAD_R, RETRO_DEVICE_ID_JOYPAD_R2, RETRO_DEVICE_ID_JOYPAD_R3,
+        RETRO_DEVICE_ID_JOYPAD_RIGHT, RETRO_DEVICE_ID_JOYPAD_SELECT, RETRO_DEVICE_ID_JOYPAD_START,
+        RETRO_DEVICE_ID_JOYPAD_UP, RETRO_DEVICE_ID_JOYPAD_X, RETRO_DEVICE_ID_JOYPAD_Y, RETRO_DEVICE_JOYPAD,
+        RETRO_DEVICE_KEYBOARD, RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, RETRO_PIXEL_FORMAT_XRGB8888, RETRO_REGION_NTSC };
+    use crate::options::core_options;
+
+    use rusted_chip8::emu::backend::backends::{ BuzzerBackend, DisplayBackend, KeypadBackend };
+    use rusted_chip8::{ Emulator, EmulatorState, SCREEN_H, SCREEN_W, STATE_SIZE };
+
+    use std::ffi::{ c_char, c_uint, c_void };
+    use std::panic::{ self, AssertUnwindSafe };
+    use std::slice;
+    use std::sync::{ Mutex, MutexGuard, PoisonError };
+
+    const FPS: f64 = 60.0;
+    const SAMPLE_RATE: u32 = 44100;
+    const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / FPS as usize;
+    // The buzzer is a 440hz square wave, like the default of the desktop emulator.
+    const BUZZER_FREQUENCY: u32 = 440;
+    const BUZZER_AMPLITUDE: i16 = i16::MAX / 4;
+
+    const PIXEL_ON: u32 = 0x00ffffff;
+    const PIXEL_OFF: u32 = 0x00000000;
+
+    // The joypad buttons of each key, indexed by the hex value of the key (0 to F).
+    // The directions are the keys most games use to move (2, 4, 6 and 8) and A is 5, the usual action key.
+    const JOYPAD_BINDINGS: [c_uint;16] = [
+        RETRO_DEVICE_ID_JOYPAD_B,      RETRO_DEVICE_ID_JOYPAD_Y,     RETRO_DEVICE_ID_JOYPAD_UP,    RETRO_DEVICE_ID_JOYPAD_X,
+        RETRO_DEVICE_ID_JOYPAD_LEFT,   RETRO_DEVICE_ID_JOYPAD_A,     RETRO_DEVICE_ID_JOYPAD_RIGHT, RETRO_DEVICE_ID_JOYPAD_L,
+        RETRO_DEVICE_ID_JOYPAD_DOWN,   RETRO_DEVICE_ID_JOYPAD_R,     RETRO_DEVICE_ID_JOYPAD_SELECT, RETRO_DEVICE_ID_JOYPAD_START,
+        RETRO_DEVICE_ID_JOYPAD_L2,     RETRO_DEVICE_ID_JOYPAD_R2,    RETRO_DEVICE_ID_JOYPAD_L3,    RETRO_DEVICE_ID_JOYPAD_R3];
+
+    // The keyboard keys of each key (same bindings as the desktop emulator), as libretro key codes (ASCII).
+    const KEYBOARD_BINDINGS: [u8;16] = [
+        b'x', b'1', b'2', b'3',
+        b'q', b'w', b'e', b'a',
+        b's', b'd', b'z', b'c',
+        b'4', b'r', b'f', b'v'];
+
+    struct Frontend {
+        environment: Option<RetroEnvironmentFn>,
+        video_refresh: Option<RetroVideoRefreshFn>,
+        audio_sample: Option<RetroAudioSampleFn>,
+        audio_sample_batch: Option<RetroAudioSampleBatchFn>,
+        input_poll: Option<RetroInputPollFn>,
+        input_state: Option<RetroInputStateFn>,
+        // The loaded game (None until retro_load_game()).
+        game: Option<Game>,
+    }
+
+    struct Game {
+        emulator: Emulator,
+        // Position in the wave of the buzzer, in samples (a second of it holds a whole number of periods).
+        buzzer_phase: u32,
+    }
+
+    static FRONTEND: Mutex<Frontend> = Mutex::new(Frontend {
+        environment: None,
+        video_refresh: None,
+        audio_sample: None,
+        audio_sample_batch: None,
+        input_poll: None,
+        input_state: None,
+        game: None,
+    });
+
+    // A panic caught by catch_panic() poisons the lock if it was held, but the game is unloaded then,
+    // so what's left (the callbacks) is still valid and the lock is taken anyway.
+    fn frontend() -> MutexGuard<'static, Frontend> {
+        FRONTEND.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Runs the body of a function called by the frontend, which gets failed_val if it panics:
+    // a panic can't unwind through the extern "C" functions (the process would abort).
+    // The game may be left halfway through a change, so it's unloaded.
+    fn catch_panic<T>(failed_val: T, body: impl FnOnce() -> T) -> T {
+        panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|_| {
+            frontend().game = None;
+            failed_val
+        })
+    }
+
+    // Sends the screen to the frontend as XRGB8888 pixels.
+    struct Video {
+        video_refresh: Option<RetroVideoRefreshFn>,
+    }
+
+    impl DisplayBackend for Video {
+        fn present(&mut self, pixels: &[u8]) {
+            let Some(video_refresh) = self.video_refresh else { return; };
+
+            let mut frame = [PIXEL_OFF;(SCREEN_W * SCREEN_H) as usize];
+            for (color, pixel) in frame.iter_mut().zip(pixels) {
+                if *pixel == 1 {
+                    *color = PIXEL_ON;
+                }
+            }
+            unsafe {
+                video_refresh(frame.as_ptr() as *const c_void, SCREEN_W, SCREEN_H, SCREEN_W as usize * 4);
+            }
+        }
+    }
+
+    // Reads the joypad and the keyboard of the first port.
+    struct Keypad {
+        input_state: Option<RetroInputStateFn>,
+    }
+
+    impl KeypadBackend for Keypad {
+        fn read_keys(&mut self) -> [bool;16] {
+            let mut keys = [false;16];
+            let Some(input_state) = self.input_state else { return keys; };
+
+            for (key, is_down) in keys.iter_mut().enumerate() {
+                *is_down = unsafe {
+                    input_state(0, RETRO_DEVICE_JOYPAD, 0, JOYPAD_BINDINGS[key]) != 0
+                        || input_state(0, RETRO_DEVICE_KEYBOARD, 0, KEYBOARD_BINDINGS[key] as c_uint) != 0
+                };
+            }
+            keys
+        }
+    }
+
+    // Sends a frame worth of audio: the square wave of the buzzer while it's active, otherwise silence.
+    struct Audio<'a> {
+        audio_sample: Option<RetroAudioSampleFn>,
+        audio_sample_batch: Option<RetroAudioSampleBatchFn>,
+        buzzer_phase: &'a mut u32,
+    }
+
+    impl BuzzerBackend for Audio<'_> {
+        fn set_buzzer(&mut self, active: bool) {
+            // Interleaved stereo samples.
+            let mut samples = [0i16;SAMPLES_PER_FRAME * 2];
+            if active {
+                for frame in samples.chunks_exact_mut(2) {
+                    // High during the first half of each period.
+                    let half_periods = *self.buzzer_phase as u64 * BUZZER_FREQUENCY as u64 * 2 / SAMPLE_RATE as u64;
+                    let sample = if half_periods & 1 == 0 { BUZZER_AMPLITUDE } else { -BUZZER_AMPLITUDE };
+                    frame.fill(sample);
+                    *self.buzzer_phase = (*self.buzzer_phase + 1) % SAMPLE_RATE;
+                }
+            }
+
+            if let Some(audio_sample_batch) = self.audio_sample_batch {
+                // The frontend may take fewer frames than given, the rest are sent again.
+                let mut sent = 0;
+                while sent < SAMPLES_PER_FRAME {
+                    let taken = unsafe { audio_sample_batch(samples[sent * 2..].as_ptr(), SAMPLES_PER_FRAME - sent) };
+                    if taken == 0 {
+                        break;
+                    }
+                    sent += taken;
+                }
+            } else if let Some(audio_sample) = self.audio_sample {
+                for frame in samples.chunks_exact(2) {
+                    unsafe { audio_sample(frame[0], frame[1]); }
+                }
+            }
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn retro_api_version() -> c_uint {
+        RETRO_API_VERSION
+    }
+
+    /// # Safety
+    /// `info` must point to a `retro_system_info` the core can write.
+    #[no_mangle]
+    pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+        info.write(RetroSystemInfo {
+            library_name: c"Rusted CHIP-8".as_ptr(),
+            library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+            valid_extensions: c"ch8".as_ptr(),
+            // The ROM is given in memory, so it can be loaded from archives.
+            need_fullpath: false,
+            block_extract: false,
+        });
+    }
+
+    /// # Safety
+    /// `info` must point to a `retro_system_av_info` the core can write.
+    #[no_mangle]
+    pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+        info.write(RetroSystemAvInfo {
+            geometry: RetroGameGeometry {
+                base_width: SCREEN_W,
+                base_height: SCREEN_H,
+                max_width: SCREEN_W,
+                max_height: SCREEN_H,
+                aspect_ratio: SCREEN_W as f32 / SCREEN_H as f32,
+            },
+            timing: RetroSystemTiming { fps: FPS, sample_rate: SAMPLE_RATE as f64 },
+        });
+    }
+
+    #[no_mangle]
+    pub extern "C" fn retro_set_environment(environment: RetroEnvironmentFn) {
+        frontend().environment = Some(environment);
+        core_options::set_variables(environment);
+    }
+
+    #[no_mangle]
+    pub extern "C" fn retro_set_video_refresh(video_refresh: RetroVideoRefreshFn) {
+        frontend().video_refresh = Some(video_refresh);
+    }
+
+    #[no_mangle]
+    pub extern "C" fn retro_set_audio_sample(audio_sample: RetroAudioSampleFn) {
+        frontend().audio_sample = Some(audio_sample);
+    }
+
+    #[no_mangle]
+    pub extern "C" fn retro_set_audio_sample_batch(audio_sample_batch: RetroAudioSampleBatchFn) {
+        frontend().audio_sample_batch = Some(audio_sample_batch);
+    }
+
+    #[no_mangle]
+    pub extern "C" fn retro_set_input_poll(input_poll: RetroInputPollFn) {
+        frontend().input_poll = Some(input_poll);
+    }
+
+    #[no_mangle]
+    pub extern "C" fn retro_set_input_state(input_state: RetroInputStateFn) {
+        frontend().input_state = Some(input_state);
+    }
+
+    #[no_mangle]
+    pub extern "C" fn retro_init() {}
+
+    #[no_mangle]
+    pub extern "C" fn retro_deinit() {
+        frontend().game = None;
+    }
+
+    // The keypad is read from the joypad and the keyboard of the first port, whatever the device.
+    #[no_mangle]
+    pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+    /// # Safety
+    /// `game` must be null or point to a valid `retro_game_info`, whose data holds `size` bytes.
+    #[no_mangle]
+    pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+        catch_panic(false, || {
+            let Some(game) = game.as_ref() else { return false; };
+            if game.data.is_null() {
+                return false;
+            }
+
+            let mut frontend = frontend();
+            let mut emulator = Emulator::new();
+            if emulator.load_rom(slice::from_raw_parts(game.data as *const u8, game.size)).is_err() {
+                return false;
+            }
+
+            if let Some(environment) = frontend.environment {
+                let mut pixel_format = RETRO_PIXEL_FORMAT_XRGB8888;
+                if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut pixel_format as *mut c_uint as *mut c_void) {
+                    return false;
+                }
+                core_options::apply(environment, &mut emulator);
+            }
+
+            frontend.game = Some(Game { emulator, buzzer_phase: 0 });
+            true
+        })
+    }
+
+    #[no_mangle]
+    pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const RetroGameInfo, _num_info: usize) -> bool {
+        false
+    }
+
+    #[no_mangle]
+    pub extern "C" fn retro_unload_game() {
+        frontend().game = None;
+    }
+
+    #[no_mangle]
+    pub extern "C" fn retro_get_region() -> c_uint {
+        RETRO_REGION_NTSC
+    }
+
+    #[no_mangle]
+    pub extern "C" fn retro_reset() {
+        catch_panic((), || {
+            if let Some(game) = frontend().game.as_mut() {
+                game.emulator.reset();
+            }
+        })
+    }
+
+    #[no_mangle]
+    pub extern "C" fn retro_run() {
+        catch_panic((), || {
+            let mut frontend = frontend();
+            let Frontend { environment, video_refresh, audio_sample, audio_sample_batch, input_poll, input_state, game } = &mut *frontend;
+            let Some(game) = game else { return; };
+
+            if let Some(input_poll) = input_poll {
+                unsafe { input_poll(); }
+            }
+            if let Some(environment) = *environment {
+                if core_options::variables_updated(environment) {
+                    core_options::apply(environment, &mut game.emulator);
+                }
+            }
+
+            game.emulator.run_frame_on(
+                &mut Video { video_refresh: *video_refresh },
+                &mut Keypad { input_state: *input_state },
+                &mut Audio { audio_sample: *audio_sample, audio_sample_batch: *audio_sample_batch, buzzer_phase: &mut game.buzzer_phase });
+        })
+    }
+
+    #[no_mangle]
+    pub extern "C" fn retro_serialize_size() -> usize {
+        STATE_SIZE
+    }
+
+    /// # Safety
+    /// `data` must point to `size` writable bytes.
+    #[no_mangle]
+    pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+        catch_panic(false, || {
+            let frontend = frontend();
+            let Some(game) = frontend.game.as_ref() else { return false; };
+            if data.is_null() || size < STATE_SIZE {
+                return false;
+            }
+
+            slice::from_raw_parts_mut(data as *mut u8, STATE_SIZE).copy_from_slice(&game.emulator.snapshot().to_bytes());
+            true
+        })
+    }
+
+    /// # Safety
+    /// `data` must point to `size` readable bytes.
+    #[no_mangle]
+    pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+        catch_panic(false, || {
+            let mut frontend = frontend();
+            let Some(game) = frontend.game.as_mut() else { return false; };
+            if data.is_null() || size < STATE_SIZE {
+                return false;
+            }
+
+            match EmulatorState::from_bytes(slice::from_raw_parts(data as *const u8, STATE_SIZE)) {
+                Some(state) => {
+                    game.emulator.restore(&state);
+                    true
+                },
+                None => false
+            }
+        })
+    }
+
+    #[no_mangle]
+    pub extern "C" fn retro_cheat_reset() {}
+
+    #[no_mangle]
+    pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+    // The RAM isn't exposed to the frontend (there are no cheats or achievements for the core).
+    #[no_mangle]
+    pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+        std::ptr::null_mut()
+    }
+
+    #[no_mangle]
+    pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+        0
+    }
+}
@@ -0,0 +1,12 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+//! Rusted - Chip-8 Emulator/Interpreter as a libretro core.
+//!
+//! Built as a shared library (`cdylib`) that RetroArch and any other libretro frontend can load:
+//! the frontend shows the screen, plays the buzzer, maps its joypad (or keyboard) to the hex keypad,
+//! keeps the save states and sets the speed and the quirks through the core options.
+
+pub mod core;
+pub mod ffi;
+pub mod options;
@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// The core options shown by the frontend: the speed and the quirks of the CPU (the [cpu] section of the settings file).
+pub mod core_options {
+    use crate::ffi::libretro_h::{
+        RetroEnvironmentFn, RetroVariable, RETRO_ENVIRONMENT_GET_VARIABLE,
+        RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE, RETRO_ENVIRONMENT_SET_VARIABLES };
+
+    use rusted_chip8::Emulator;
+
+    use std::ffi::{ c_void, CStr };
+    use std::ptr;
+
+    const CYCLES_PER_FRAME_KEY: &CStr = c"rusted_chip8_cycles_per_frame";
+    const BIT_SHIFT_KEY: &CStr = c"rusted_chip8_bit_shift_instructions_use_vy";
+    const STORE_READ_KEY: &CStr = c"rusted_chip8_store_read_instructions_change_i";
+
+    // Declares the options, as "description; default value|other values...".
+    pub fn set_variables(environment: RetroEnvironmentFn) {
+        let variables = [
+            RetroVariable {
+                key: CYCLES_PER_FRAME_KEY.as_ptr(),
+                value: c"Instruction cycles per frame; 20|5|10|15|30|40|50|100|200|500|1000".as_ptr(),
+            },
+            RetroVariable {
+                key: BIT_SHIFT_KEY.as_ptr(),
+                value: c"Bit shift instructions (8xy6/8xyE) use VY; enabled|disabled".as_ptr(),
+            },
+            RetroVariable {
+                key: STORE_READ_KEY.as_ptr(),
+                value: c"Store/read instructions (Fx55/Fx65) change I; enabled|disabled".as_ptr(),
+            },
+            RetroVariable { key: ptr::null(), value: ptr::null() },
+        ];
+        unsafe {
+            environment(RETRO_ENVIRONMENT_SET_VARIABLES, variables.as_ptr() as *mut c_void);
+        }
+    }
+
+    // Whether the user changed an option since the last call.
+    pub fn variables_updated(environment: RetroEnvironmentFn) -> bool {
+        let mut updated = false;
+        let supported = unsafe {
+            environment(RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut bool as *mut c_void)
+        };
+        supported && updated
+    }
+
+    // Applies the current values of the options (the ones the frontend doesn't know are left as they are).
+    pub fn apply(environment: RetroEnvironmentFn, emulator: &mut Emulator) {
+        if let Some(cycles_per_frame) = get_variable(environment, CYCLES_PER_FRAME_KEY).and_then(|val| val.parse().ok()) {
+            emulator.set_cycles_per_frame(cycles_per_frame);
+        }
+        if let Some(val) = get_variable(environment, BIT_SHIFT_KEY) {
+            emulator.set_bit_shift_instructions_use_vy(val == "enabled");
+        }
+        if let Some(val) = get_variable(environment, STORE_READ_KEY) {
+            emulator.set_store_read_instructions_change_i(val == "enabled");
+        }
+    }
+
+    fn get_variable(environment: RetroEnvironmentFn, key: &CStr) -> Option<String> {
+        let mut variable = RetroVariable { key: key.as_ptr(), value: ptr::null() };
+        let found = unsafe {
+            environment(RETRO_ENVIRONMENT_GET_VARIABLE, &mut variable as *mut RetroVariable as *mut c_void)
+        };
+        if !found || variable.value.is_null() {
+            return None;
+        }
+        Some(unsafe { CStr::from_ptr(variable.value) }.to_string_lossy().into_owned())
+    }
+}
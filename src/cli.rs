@@ -0,0 +1,422 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+pub mod args {
+    use rusted_chip8::emu::config::settings::{ EmuSettings, CYCLES_PER_FRAME_RANGE, PALETTES, SCALE_RANGE };
+    use rusted_chip8::emu::logic::cpu::{ FaultPolicy, JitMode, UnknownOpcodePolicy };
+    use rusted_chip8::emu::sys_call::sys_handler::SysCallMode;
+    use rusted_chip8::emu::watcher::rom_watcher::WatchMode;
+    use rusted_chip8::util::utilities::{ ColorUtil, PathUtil };
+
+    use sdl2::pixels::Color;
+    use std::ops::RangeInclusive;
+    use std::path::{ Path, PathBuf };
+
+    pub const USAGE: &str = "\
+Usage: rusted-chip8 [OPTIONS] [ROM]
+
+Arguments:
+  [ROM]                 Path of the CH8 (ROM) file to run.
+                        When omitted, a ROM is selected from 'default_ch8_folder'.
+
+Options:
+  --config <PATH>       Settings file to use (default: config.toml, looked for in the
+                        current directory, in the user's config directory and next to the executable).
+  --scale <N>           Video resolution scale (1 to 20).
+  --cycles <N>          Instruction cycles per frame (1 to 99999).
+  --on-fault <POLICY>   What happens on a CPU fault (stack overflow/underflow or an out-of-bounds address):
+                        halt (stops the CPU), wrap (wraps the address and goes on)
+                        or debug (stops the CPU and opens the debugger in the terminal).
+  --on-unknown <POLICY> What happens on an unknown instruction: ignore, log (once per address),
+                        halt or debug.
+  --jit[=<MODE>]        Compiles the instructions into native code (x86-64, needs the \"jit\" feature),
+                        unless tracing, profiling or tracking the coverage: native (--jit or --jit=native)
+                        or lockstep (also runs them with the interpreter, reporting any difference).
+  --sys-calls <MODE>    How the legacy 0nnn (SYS) instructions are handled:
+                        unknown (as unknown instructions) or ignore.
+  --quirks <LIST>       Comma separated list of quirk presets (vip, schip)
+                        and/or quirk settings, like bit_shift_instructions_use_vy=false.
+  --compare <LIST>      Opens another window running the same ROM side by side, with other quirks
+                        (like --quirks). It can be given more than once, and all the windows share the input.
+  --palette <PALETTE>   Built-in palette (mono, amber, green, lcd, octo)
+                        or background and pixel colors as hex, like 000000,FFFFFF.
+  --seed <N>            Seed for the random number generator (Cxkk instruction).
+  --headless            Runs without a window, audio or input.
+                        The final state of the screen is printed when it stops.
+  --batch <FOLDER>      Runs every ROM of the folder headless, in parallel, for --frames frames (default: 600),
+                        and prints how each one ran (e.g. to check the compatibility of a ROM collection).
+  --frames <N>          Stops after running N frames.
+  --watch[=<SETTINGS>]  Reloads the ROM whenever its file changes, keeping the current speed and quirks
+                        (--watch or --watch=keep) or applying the settings of the changed ROM (--watch=reload).
+  --trace <PATH>        Writes a line per executed instruction to the file (address, opcode, disassembly
+                        and registers), in a format that can be compared with diff.
+  --trace-range <LIST>  Comma separated list of hex address ranges to trace, like 200-2FF,340.
+  --trace-last <N>      Keeps only the last N instructions, written to the trace file (or printed)
+                        when a CPU fault happens.
+  --profile[=<PATH>]    Profiles the execution (hot spots, instructions, subroutines and waits for a key),
+                        printing the report when the ROM stops or writing it to the file.
+  --coverage[=<PATH>]   Tracks which addresses were executed, read as data and written, printing the report
+                        and an ASCII map when the ROM stops or writing them to the file
+                        (a PNG map if the file ends with .png).
+  -h, --help            Prints this message.
+
+Options given here override the values of the settings file.";
+
+    pub struct CliArgs {
+        rom_path: Option<PathBuf>,
+        config_path: Option<PathBuf>,
+        scale: Option<u32>,
+        cycles_per_frame: Option<u32>,
+        fault_policy: Option<FaultPolicy>,
+        unknown_opcode_policy: Option<UnknownOpcodePolicy>,
+        sys_calls: Option<SysCallMode>,
+        jit: Option<JitMode>,
+        quirks: Quirks,
+        // The quirks of the other windows (given as they were written, to name the windows).
+        compare: Vec<(String, Quirks)>,
+        palette: Option<(Color, Color)>,
+        seed: Option<u64>,
+        headless: bool,
+        batch_folder: Option<PathBuf>,
+        frames: Option<u64>,
+        watch: Option<WatchMode>,
+        trace_path: Option<PathBuf>,
+        trace_ranges: Vec<RangeInclusive<u16>>,
+        trace_last: Option<usize>,
+        profile: bool,
+        profile_path: Option<PathBuf>,
+        coverage: bool,
+        coverage_path: Option<PathBuf>,
+        help: bool,
+    }
+
+    impl CliArgs {
+        // Parses the command-line arguments (without the executable's name).
+        pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+            let mut cli_args = CliArgs {
+                rom_path: None,
+                config_path: None,
+                scale: None,
+                cycles_per_frame: None,
+                fault_policy: None,
+                unknown_opcode_policy: None,
+                sys_calls: None,
+                jit: None,
+                quirks: Quirks::default(),
+                compare: Vec::new(),
+                palette: None,
+                seed: None,
+                headless: false,
+                batch_folder: None,
+                frames: None,
+                watch: None,
+                trace_path: None,
+                trace_ranges: Vec::new(),
+                trace_last: None,
+                profile: false,
+                profile_path: None,
+                coverage: false,
+                coverage_path: None,
+                help: false,
+            };
+
+            while let Some(arg) = args.next() {
+                // Options with values can be written as "--option value" or "--option=value".
+                let (name, inline_val) = match arg.split_once('=') {
+                    Some((name, val)) if name.starts_with("--") => (String::from(name), Some(String::from(val))),
+                    _ => (arg.clone(), None)
+                };
+
+                let mut next_val = || -> Result<String, String> {
+                    match inline_val.clone() {
+                        Some(val) => Ok(val),
+                        None => args.next().ok_or_else(|| format!("'{name}' requires a value"))
+                    }
+                };
+
+                match name.as_str() {
+                    "--config" => cli_args.config_path = Some(PathUtil::expand_home(&next_val()?)),
+                    "--scale" => {
+                        let val = next_val()?;
+                        cli_args.scale = Some(CliArgs::parse_u32_in(&name, &val, SCALE_RANGE)?);
+                    },
+                    "--cycles" => {
+                        let val = next_val()?;
+                        cli_args.cycles_per_frame = Some(CliArgs::parse_u32_in(&name, &val, CYCLES_PER_FRAME_RANGE)?);
+                    },
+                    "--on-fault" => {
+                        let val = next_val()?;
+                        cli_args.fault_policy = Some(FaultPolicy::from_name(&val)
+                            .ok_or_else(|| format!("invalid value '{val}' for '{name}' (halt, wrap or debug)"))?);
+                    },
+                    "--on-unknown" => {
+                        let val = next_val()?;
+                        cli_args.unknown_opcode_policy = Some(UnknownOpcodePolicy::from_name(&val)
+                            .ok_or_else(|| format!("invalid value '{val}' for '{name}' (ignore, log, halt or debug)"))?);
+                    },
+                    "--sys-calls" => {
+                        let val = next_val()?;
+                        cli_args.sys_calls = Some(SysCallMode::from_name(&val)
+                            .ok_or_else(|| format!("invalid value '{val}' for '{name}' (unknown or ignore)"))?);
+                    },
+                    // The value is optional, so it can only be given as "--jit=mode".
+                    "--jit" => cli_args.jit = match inline_val.as_deref() {
+                        None => Some(JitMode::Native),
+                        Some(val) => Some(JitMode::from_name(val)
+                            .ok_or_else(|| format!("invalid value '{val}' for '{name}' (native or lockstep)"))?)
+                    },
+                    "--quirks" => cli_args.quirks.parse(&name, &next_val()?)?,
+                    "--compare" => {
+                        let val = next_val()?;
+                        let mut quirks = Quirks::default();
+                        quirks.parse(&name, &val)?;
+                        cli_args.compare.push((val, quirks));
+                    },
+                    "--palette" => cli_args.palette = Some(CliArgs::parse_palette(&next_val()?)?),
+                    "--seed" => {
+                        let val = next_val()?;
+                        cli_args.seed = Some(val.parse().map_err(|_| format!("invalid value '{val}' for '{name}'"))?);
+                    },
+                    "--frames" => {
+                        let val = next_val()?;
+                        cli_args.frames = Some(val.parse().map_err(|_| format!("invalid value '{val}' for '{name}'"))?);
+                    },
+                    // The value is optional, so it can only be given as "--watch=value".
+                    "--watch" => cli_args.watch = match inline_val.as_deref() {
+                        None | Some("keep") => Some(WatchMode::KeepSettings),
+                        Some("reload") => Some(WatchMode::ReloadSettings),
+                        Some(val) => return Err(format!("invalid value '{val}' for '{name}' (keep or reload)"))
+                    },
+                    "--trace" => cli_args.trace_path = Some(PathUtil::expand_home(&next_val()?)),
+                    "--trace-range" => {
+                        let val = next_val()?;
+                        cli_args.trace_ranges.extend(CliArgs::parse_addr_ranges(&name, &val)?);
+                    },
+                    "--trace-last" => {
+                        let val = next_val()?;
+                        cli_args.trace_last = Some(match val.parse() {
+                            Ok(trace_last) if trace_last > 0 => trace_last,
+                            _ => return Err(format!("invalid value '{val}' for '{name}'"))
+                        });
+                    },
+                    // The value is optional, so it can only be given as "--profile=path".
+                    "--profile" => {
+                        cli_args.profile = true;
+                        cli_args.profile_path = inline_val.as_deref().map(PathUtil::expand_home);
+                    },
+                    // The value is optional, so it can only be given as "--coverage=path".
+                    "--coverage" => {
+                        cli_args.coverage = true;
+                        cli_args.coverage_path = inline_val.as_deref().map(PathUtil::expand_home);
+                    },
+                    "--headless" => cli_args.headless = true,
+                    "--batch" => cli_args.batch_folder = Some(PathUtil::expand_home(&next_val()?)),
+                    "-h" | "--help" => cli_args.help = true,
+                    _ if name.starts_with('-') => return Err(format!("unknown option '{name}'")),
+                    _ => {
+                        if cli_args.rom_path.is_some() {
+                            return Err(format!("unexpected argument '{arg}' (only one ROM can be given)"));
+                        }
+                        cli_args.rom_path = Some(PathUtil::expand_home(&arg));
+                    }
+                }
+            }
+
+            #[cfg(not(feature = "jit"))]
+            if cli_args.jit.is_some() {
+                return Err(String::from("'--jit' needs a build with the \"jit\" feature"));
+            }
+            Ok(cli_args)
+        }
+
+        // Overrides the values of the settings file with the ones given in the command line.
+        pub fn apply_to(&self, emu_settings: &mut EmuSettings) {
+            if let Some(scale) = self.scale {
+                emu_settings.set_scale(scale);
+            }
+            if let Some(cycles_per_frame) = self.cycles_per_frame {
+                emu_settings.set_cycles_per_frame(cycles_per_frame);
+            }
+            if let Some(fault_policy) = self.fault_policy {
+                emu_settings.set_fault_policy(fault_policy);
+            }
+            if let Some(unknown_opcode_policy) = self.unknown_opcode_policy {
+                emu_settings.set_unknown_opcode_policy(unknown_opcode_policy);
+            }
+            if let Some(sys_calls) = self.sys_calls {
+                emu_settings.set_sys_calls(sys_calls);
+            }
+            self.quirks.apply_to(emu_settings);
+            if let Some((bg_color, pixel_color)) = self.palette {
+                emu_settings.set_bg_color(bg_color);
+                emu_settings.set_pixel_color(pixel_color);
+            }
+        }
+
+        pub fn get_rom_path(&self) -> Option<&Path> {
+            self.rom_path.as_deref()
+        }
+
+        pub fn get_config_path(&self) -> Option<&Path> {
+            self.config_path.as_deref()
+        }
+
+        pub fn get_seed(&self) -> Option<u64> {
+            self.seed
+        }
+
+        pub fn get_headless(&self) -> bool {
+            self.headless
+        }
+
+        pub fn get_compare(&self) -> &[(String, Quirks)] {
+            &self.compare
+        }
+
+        pub fn get_batch_folder(&self) -> Option<&Path> {
+            self.batch_folder.as_deref()
+        }
+
+        #[cfg(feature = "jit")]
+        pub fn get_jit(&self) -> Option<JitMode> {
+            self.jit
+        }
+
+        pub fn get_frames(&self) -> Option<u64> {
+            self.frames
+        }
+
+        pub fn get_watch(&self) -> Option<WatchMode> {
+            self.watch
+        }
+
+        pub fn get_trace_path(&self) -> Option<&Path> {
+            self.trace_path.as_deref()
+        }
+
+        pub fn get_trace_ranges(&self) -> &[RangeInclusive<u16>] {
+            &self.trace_ranges
+        }
+
+        pub fn get_trace_last(&self) -> Option<usize> {
+            self.trace_last
+        }
+
+        // Tracing is enabled by a trace file and/or a ring buffer.
+        pub fn get_trace(&self) -> bool {
+            self.trace_path.is_some() || self.trace_last.is_some()
+        }
+
+        pub fn get_profile(&self) -> bool {
+            self.profile
+        }
+
+        pub fn get_profile_path(&self) -> Option<&Path> {
+            self.profile_path.as_deref()
+        }
+
+        pub fn get_coverage(&self) -> bool {
+            self.coverage
+        }
+
+        pub fn get_coverage_path(&self) -> Option<&Path> {
+            self.coverage_path.as_deref()
+        }
+
+        pub fn get_help(&self) -> bool {
+            self.help
+        }
+
+        fn parse_u32_in(name: &str, val: &str, range: RangeInclusive<u32>) -> Result<u32, String> {
+            match val.parse::<u32>() {
+                Ok(parsed_val) if range.contains(&parsed_val) => Ok(parsed_val),
+                _ => Err(format!("invalid value '{val}' for '{name}' (min={}, max={})", range.start(), range.end()))
+            }
+        }
+
+        // Address ranges are given as hex, like "200-2FF,340" (a single address is a range of its own).
+        fn parse_addr_ranges(name: &str, val: &str) -> Result<Vec<RangeInclusive<u16>>, String> {
+            let parse_addr = |addr: &str| -> Result<u16, String> {
+                let digits = addr.trim().trim_start_matches("0x");
+                match u16::from_str_radix(digits, 16) {
+                    Ok(parsed_addr) if parsed_addr <= 0xfff => Ok(parsed_addr),
+                    _ => Err(format!("invalid address '{addr}' for '{name}' (hex, from 0 to FFF)"))
+                }
+            };
+
+            val.split(',')
+                .map(|range| {
+                    let (start, end) = range.split_once('-').unwrap_or((range, range));
+                    let (start, end) = (parse_addr(start)?, parse_addr(end)?);
+                    if start > end {
+                        return Err(format!("invalid range '{range}' for '{name}' (the start is after the end)"));
+                    }
+                    Ok(start..=end)
+                })
+                .collect()
+        }
+
+        // Palettes are given by name or as "background,pixel" hex colors (e.g. "000000,FFFFFF").
+        fn parse_palette(val: &str) -> Result<(Color, Color), String> {
+            if let Some((_, bg_color, pixel_color)) = PALETTES.iter().find(|(name, _, _)| *name == val) {
+                return Ok((*bg_color, *pixel_color));
+            }
+
+            let invalid = || format!("invalid palette '{val}' for '--palette'");
+            let (bg_hex, pixel_hex) = val.split_once(',').ok_or_else(invalid)?;
+            Ok((
+                ColorUtil::parse_hex_color(bg_hex).ok_or_else(invalid)?,
+                ColorUtil::parse_hex_color(pixel_hex).ok_or_else(invalid)?))
+        }
+    }
+
+    // Quirk settings given in the command line (the ones that aren't given keep the value of the settings file).
+    #[derive(Default)]
+    pub struct Quirks {
+        bit_shift_instructions_use_vy: Option<bool>,
+        store_read_instructions_change_i: Option<bool>,
+    }
+
+    impl Quirks {
+        // Quirks are given as presets and/or settings, where the last one wins (e.g. "schip,bit_shift_instructions_use_vy=true").
+        fn parse(&mut self, name: &str, val: &str) -> Result<(), String> {
+            for quirk in val.split(',').map(str::trim) {
+                match quirk.split_once('=') {
+                    None => match quirk {
+                        // Original COSMAC VIP behavior.
+                        "vip" => {
+                            self.bit_shift_instructions_use_vy = Some(true);
+                            self.store_read_instructions_change_i = Some(true);
+                        },
+                        // SUPER-CHIP (HP48) behavior.
+                        "schip" => {
+                            self.bit_shift_instructions_use_vy = Some(false);
+                            self.store_read_instructions_change_i = Some(false);
+                        },
+                        _ => return Err(format!("unknown quirk preset '{quirk}' for '{name}'"))
+                    },
+                    Some((quirk_name, quirk_val)) => {
+                        let quirk_val: bool = quirk_val.trim().parse()
+                            .map_err(|_| format!("expected true or false for quirk '{quirk_name}'"))?;
+
+                        match quirk_name.trim() {
+                            "bit_shift_instructions_use_vy" => self.bit_shift_instructions_use_vy = Some(quirk_val),
+                            "store_read_instructions_change_i" => self.store_read_instructions_change_i = Some(quirk_val),
+                            _ => return Err(format!("unknown quirk '{quirk_name}' for '{name}'"))
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        pub fn apply_to(&self, emu_settings: &mut EmuSettings) {
+            if let Some(use_vy) = self.bit_shift_instructions_use_vy {
+                emu_settings.set_bit_shift_instructions_use_vy(use_vy);
+            }
+            if let Some(change_i) = self.store_read_instructions_change_i {
+                emu_settings.set_store_read_instructions_change_i(change_i);
+            }
+        }
+    }
+}
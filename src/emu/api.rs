@@ -0,0 +1,376 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+//! Embedding API: a CHIP-8 machine that can be driven by any frontend (an editor plugin, a bot, tests...),
+//! without a window, audio or the terminal.
+pub mod emulator_api {
+    use crate::emu::backend::backends::{ BuzzerBackend, DisplayBackend, KeypadBackend, RandomSource };
+    #[cfg(feature = "desktop")]
+    use crate::emu::config::settings::EmuSettings;
+    use crate::emu::display::screen::{ Screen, ScreenController, SCREEN_BYTES };
+    use crate::emu::input::keyboard::{ Keyboard, KeyboardController };
+    #[cfg(feature = "jit")]
+    use crate::emu::jit::jit_compiler::Jit;
+    use crate::emu::logic::cpu::{ CpuController, FaultInfo, FaultPolicy, DEFAULT_CYCLES_PER_FRAME };
+    use crate::emu::memory::memory::{ Memory, MemoryController, MEMORY_BYTES };
+    use crate::error::emu_error::EmuError;
+
+    use rand::rngs::StdRng;
+
+    pub use crate::emu::display::screen::{ SCREEN_H, SCREEN_W };
+
+    // Identifies the bytes of an EmulatorState ("RC8" and the version of the layout).
+    const STATE_MAGIC: [u8;4] = *b"RC8\x01";
+
+    /// Size of an `EmulatorState` as bytes (see `EmulatorState::to_bytes`).
+    pub const STATE_SIZE: usize = STATE_MAGIC.len() + MEMORY_BYTES + SCREEN_BYTES;
+
+    /// A CHIP-8 machine: RAM, registers, timers, screen and keypad.
+    ///
+    /// The frontend loads a ROM, sets the keys and runs a frame 60 times per second,
+    /// then draws the framebuffer and sounds the buzzer while `sound_active()` is true:
+    /// ```no_run
+    /// use rusted_chip8::Emulator;
+    ///
+    /// let mut emulator = Emulator::new();
+    /// emulator.load_rom(&std::fs::read("game.ch8").unwrap()).unwrap();
+    /// loop {
+    ///     emulator.set_keys([false;16]);
+    ///     emulator.run_frame();
+    ///     let pixels = emulator.framebuffer();
+    ///     // Draw the pixels, play a tone if emulator.sound_active()...
+    ///     # break;
+    /// }
+    /// ```
+    ///
+    /// The random numbers of the Cxkk instruction come from `R` (see `Emulator::with_rng`).
+    pub struct Emulator<R: RandomSource = StdRng> {
+        mem_ctrl: MemoryController,
+        cpu_ctrl: CpuController<R>,
+        screen_ctrl: ScreenController,
+        keyboard_ctrl: KeyboardController,
+    }
+
+    /// The whole state of the machine, taken by `Emulator::snapshot()`.
+    #[derive(Clone)]
+    pub struct EmulatorState {
+        mem: Memory,
+        screen: Screen,
+    }
+
+    impl EmulatorState {
+        /// The state as bytes, e.g. for the save states of a frontend.
+        /// The layout doesn't depend on the platform, so a state can be loaded on another machine.
+        pub fn to_bytes(&self) -> [u8;STATE_SIZE] {
+            let mut bytes = [0;STATE_SIZE];
+            let (magic, rest) = bytes.split_at_mut(STATE_MAGIC.len());
+            let (mem, screen) = rest.split_at_mut(MEMORY_BYTES);
+            magic.copy_from_slice(&STATE_MAGIC);
+            mem.copy_from_slice(&self.mem.to_bytes());
+            screen.copy_from_slice(&self.screen.to_bytes());
+            bytes
+        }
+
+        /// Reads a state written by `to_bytes()`.
+        /// Returns `None` if the bytes aren't a state (or are a state of another version).
+        pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+            if bytes.len() != STATE_SIZE || bytes[..STATE_MAGIC.len()] != STATE_MAGIC {
+                return None;
+            }
+
+            let (mem, screen) = bytes[STATE_MAGIC.len()..].split_at(MEMORY_BYTES);
+            Some(Self { mem: Memory::from_bytes(mem)?, screen: Screen::from_bytes(screen)? })
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl Default for Emulator {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl Emulator {
+        /// A machine with the default settings (20 instructions per frame and the original COSMAC VIP quirks),
+        /// with an empty RAM until a ROM is loaded.
+        pub fn new() -> Self {
+            let mem_ctrl = MemoryController::new(Memory::new());
+            let cpu_ctrl = CpuController::new(
+                &mem_ctrl,
+                DEFAULT_CYCLES_PER_FRAME,
+                true,
+                true,
+                FaultPolicy::Halt,
+                None);
+            Self::from_parts(mem_ctrl, cpu_ctrl)
+        }
+
+        /// A machine with the CPU settings (speed and quirks) of a settings file.
+        /// The video, audio and input settings are left to the frontend.
+        #[cfg(feature = "desktop")]
+        pub fn with_settings(emu_settings: &EmuSettings) -> Self {
+            let mem_ctrl = MemoryController::new(Memory::new());
+            let mut cpu_ctrl = CpuController::new(
+                &mem_ctrl,
+                emu_settings.get_cycles_per_frame(),
+                emu_settings.get_bit_shift_instructions_use_vy(),
+                emu_settings.get_store_read_instructions_change_i(),
+                emu_settings.get_fault_policy(),
+                None);
+            cpu_ctrl.set_unknown_opcode_policy(emu_settings.get_unknown_opcode_policy());
+            cpu_ctrl.set_sys_handler(emu_settings.get_sys_calls().create_handler());
+            Self::from_parts(mem_ctrl, cpu_ctrl)
+        }
+    }
+
+    impl Emulator<StdRng> {
+        /// Sets the seed of the random numbers (Cxkk), making the execution reproducible.
+        /// Frontends without an OS (e.g. WebAssembly) must give one, since the machine can't pick a random seed there.
+        pub fn set_seed(&mut self, seed: u64) {
+            self.cpu_ctrl.set_seed(seed);
+        }
+    }
+
+    impl<R: RandomSource> Emulator<R> {
+        /// A machine with the default settings, taking its random numbers from `rng`.
+        /// This is the constructor without the `std` feature, e.g. with a hardware RNG
+        /// (or a `rand::rngs::StdRng` seeded by the frontend).
+        pub fn with_rng(rng: R) -> Self {
+            let mem_ctrl = MemoryController::new(Memory::new());
+            let cpu_ctrl = CpuController::with_rng(
+                &mem_ctrl,
+                DEFAULT_CYCLES_PER_FRAME,
+                true,
+                true,
+                FaultPolicy::Halt,
+                rng);
+            Self::from_parts(mem_ctrl, cpu_ctrl)
+        }
+
+        fn from_parts(mem_ctrl: MemoryController, cpu_ctrl: CpuController<R>) -> Self {
+            Self {
+                mem_ctrl,
+                cpu_ctrl,
+                screen_ctrl: ScreenController::new(Screen::new()),
+                keyboard_ctrl: KeyboardController::new(Keyboard::new()),
+            }
+        }
+
+        /// Loads a ROM (starting at address 0x200) and restarts the machine.
+        /// Fails if the ROM doesn't fit in the RAM, leaving the machine as it was.
+        pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), EmuError> {
+            self.mem_ctrl.init_ram_from_bytes(rom)?;
+            self.cpu_ctrl.reset();
+            self.screen_ctrl.clear_screen();
+            Ok(())
+        }
+
+        /// Restarts the ROM already in RAM, resetting the registers, stack and timers and clearing the screen.
+        pub fn reset(&mut self) {
+            self.mem_ctrl.soft_reset();
+            self.cpu_ctrl.reset();
+            self.screen_ctrl.clear_screen();
+        }
+
+        /// Executes a single instruction. The timers aren't decremented, since they follow the (60hz) frames.
+        pub fn step(&mut self) {
+            self.cpu_ctrl.fetch_exec(&mut self.screen_ctrl, &mut self.mem_ctrl, &mut self.keyboard_ctrl);
+        }
+
+        /// Runs a (60hz) frame: a frame worth of instructions followed by the decrement of the timers.
+        /// Returns how many instructions were executed.
+        pub fn run_frame(&mut self) -> u64 {
+            let instructions = self.cpu_ctrl.run_frame(&mut self.screen_ctrl, &mut self.mem_ctrl, &mut self.keyboard_ctrl);
+            self.mem_ctrl.dec_all_timers();
+            instructions
+        }
+
+        /// Runs a frame on the hardware of a frontend: reads the keypad, runs the frame,
+        /// then shows the screen and turns the buzzer on or off. Returns how many instructions were executed.
+        pub fn run_frame_on(
+            &mut self,
+            display: &mut impl DisplayBackend,
+            keypad: &mut impl KeypadBackend,
+            buzzer: &mut impl BuzzerBackend) -> u64 {
+
+            self.set_keys(keypad.read_keys());
+            let instructions = self.run_frame();
+            display.present(self.framebuffer());
+            buzzer.set_buzzer(self.sound_active());
+            instructions
+        }
+
+        /// Sets which keys of the keypad are held down, indexed by their hex value (0 to F).
+        /// The keys stay as they are until they're set again.
+        pub fn set_keys(&mut self, keys: [bool;16]) {
+            self.keyboard_ctrl.set_keys(keys);
+        }
+
+        /// The screen, as `SCREEN_W * SCREEN_H` (64x32) pixels by rows, where 1 is on and 0 is off.
+        pub fn framebuffer(&self) -> &[u8] {
+            self.screen_ctrl.get_pixels()
+        }
+
+        /// Whether the buzzer must sound, which is while the sound timer is active.
+        pub fn sound_active(&self) -> bool {
+            self.mem_ctrl.get_st() > 0
+        }
+
+        /// A copy of the whole state of the machine (RAM, registers, stack, timers and screen).
+        pub fn snapshot(&self) -> EmulatorState {
+            EmulatorState { mem: self.mem_ctrl.snapshot(), screen: self.screen_ctrl.snapshot() }
+        }
+
+        /// Goes back to a state taken by `snapshot()`.
+        pub fn restore(&mut self, state: &EmulatorState) {
+            self.mem_ctrl.restore(&state.mem);
+            self.screen_ctrl.restore(&state.screen);
+            self.cpu_ctrl.reset();
+        }
+
+        /// Sets how many instructions are executed in a frame.
+        pub fn set_cycles_per_frame(&mut self, cycles_per_frame: u32) {
+            self.cpu_ctrl.set_cycles_per_frame(cycles_per_frame);
+        }
+
+        /// Sets whether 8xy6 and 8xyE shift VY into VX (original COSMAC VIP) or shift VX in place.
+        pub fn set_bit_shift_instructions_use_vy(&mut self, val: bool) {
+            self.cpu_ctrl.set_bit_shift_instructions_use_vy(val);
+        }
+
+        /// Sets whether Fx55 and Fx65 increment I (original COSMAC VIP) or leave it unchanged.
+        pub fn set_store_read_instructions_change_i(&mut self, val: bool) {
+            self.cpu_ctrl.set_store_read_instructions_change_i(val);
+        }
+
+        /// The fault that halted the CPU (a stack overflow, an address out of bounds...), if any.
+        /// Nothing runs until the machine is reset or a ROM is loaded.
+        pub fn fault(&self) -> Option<&FaultInfo> {
+            self.cpu_ctrl.get_fault()
+        }
+
+        /// The unknown instructions executed since the ROM was loaded, formatted for display (if there were any).
+        #[cfg(feature = "std")]
+        pub fn unknown_opcode_summary(&self) -> Option<String> {
+            self.cpu_ctrl.get_unknown_opcode_summary()
+        }
+
+        /// The source of the random numbers (Cxkk), which isn't part of the snapshots.
+        /// Cloning it along with a snapshot makes the execution after a restore reproducible.
+        pub fn rng(&self) -> &R {
+            self.cpu_ctrl.get_rng()
+        }
+
+        /// Replaces the source of the random numbers, e.g. with one cloned by `rng()`.
+        pub fn set_rng(&mut self, rng: R) {
+            self.cpu_ctrl.set_rng(rng);
+        }
+
+        /// Runs the frames through a JIT (`None` goes back to the interpreter).
+        /// It's bit-exact with the interpreter, which `JitMode::Lockstep` checks block by block.
+        #[cfg(feature = "jit")]
+        pub fn set_jit(&mut self, jit: Option<Jit>) {
+            self.cpu_ctrl.set_jit(jit);
+        }
+
+        /// The JIT, e.g. for the report of the lockstep mode.
+        #[cfg(feature = "jit")]
+        pub fn jit(&self) -> Option<&Jit> {
+            self.cpu_ctrl.get_jit()
+        }
+
+        /// The RAM (4KB), e.g. to inspect the variables of a game.
+        pub fn ram(&self) -> &[u8;4096] {
+            self.mem_ctrl.get_ram()
+        }
+
+        /// The registers V0 to VF.
+        pub fn v_registers(&self) -> [u8;16] {
+            self.mem_ctrl.get_v_regs()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{ Emulator, EmulatorState, STATE_SIZE };
+
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+
+        // Sets the timers, calls a subroutine that draws, writes to the RAM and reads a random number,
+        // then counts in V2 forever.
+        const ROM: [u8;28] = [
+            0x60, 0x2a, // 200: LD V0, 2A
+            0xf0, 0x18, // 202: LD ST, V0
+            0xf0, 0x15, // 204: LD DT, V0
+            0x22, 0x10, // 206: CALL 210
+            0x72, 0x01, // 208: ADD V2, 1
+            0x12, 0x08, // 20A: JP 208
+            0x00, 0x00, 0x00, 0x00,
+            0xf1, 0x29, // 210: LD F, V1
+            0xd0, 0x25, // 212: DRW V0, V2, 5
+            0xa4, 0x00, // 214: LD I, 400
+            0xf0, 0x33, // 216: LD B, V0
+            0xc3, 0xff, // 218: RND V3, FF
+            0x00, 0xee, // 21A: RET
+        ];
+
+        fn new_emulator(rom: &[u8]) -> Emulator {
+            let mut emulator = Emulator::with_rng(StdRng::seed_from_u64(1));
+            emulator.load_rom(rom).unwrap();
+            emulator
+        }
+
+        fn assert_same_machine(emulator: &Emulator, other: &Emulator) {
+            assert_eq!(emulator.ram()[..], other.ram()[..]);
+            assert_eq!(emulator.v_registers(), other.v_registers());
+            assert_eq!(emulator.framebuffer(), other.framebuffer());
+            assert_eq!(emulator.sound_active(), other.sound_active());
+        }
+
+        #[test]
+        fn restores_a_state_from_its_bytes() {
+            // Stops inside the subroutine, so the stack isn't empty.
+            let mut emulator = new_emulator(&ROM);
+            for _ in 0..9 {
+                emulator.step();
+            }
+            assert_eq!(emulator.ram()[0x400..0x403], [0, 4, 2]);
+
+            let bytes = emulator.snapshot().to_bytes();
+            let state = EmulatorState::from_bytes(&bytes).unwrap();
+            assert_eq!(state.to_bytes(), bytes);
+
+            let mut other = new_emulator(&[0x00, 0xe0]);
+            other.restore(&state);
+            assert_same_machine(&emulator, &other);
+
+            // The PC, I, stack and timers were restored too, so both machines go on the same way.
+            for machine in [&mut emulator, &mut other] {
+                for _ in 0..3 {
+                    machine.run_frame();
+                }
+            }
+            assert_same_machine(&emulator, &other);
+            assert!(emulator.v_registers()[2] > 0);
+        }
+
+        #[test]
+        fn rejects_bytes_that_arent_a_state() {
+            let bytes = new_emulator(&ROM).snapshot().to_bytes();
+            assert!(EmulatorState::from_bytes(&bytes).is_some());
+
+            let mut bad_magic = bytes;
+            bad_magic[0] ^= 0xff;
+            assert!(EmulatorState::from_bytes(&bad_magic).is_none());
+            // A state of another version of the layout.
+            let mut other_version = bytes;
+            other_version[3] += 1;
+            assert!(EmulatorState::from_bytes(&other_version).is_none());
+
+            assert!(EmulatorState::from_bytes(&bytes[..STATE_SIZE - 1]).is_none());
+            assert!(EmulatorState::from_bytes(&[]).is_none());
+        }
+    }
+}
@@ -2,17 +2,29 @@
 // SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
 
 pub mod buzzer {
+    use crate::emu::backend::backends::BuzzerBackend;
+    use crate::error::emu_error::EmuError;
+
     use sdl2::audio::{ AudioCallback, AudioDevice, AudioSpecDesired };
     use sdl2::AudioSubsystem;
 
     pub struct BuzzerController {
-        device: AudioDevice<SquareWave>,
+        // None when running without sound (e.g. there's no audio device).
+        device: Option<AudioDevice<SquareWave>>,
         st_equals_buzzer: bool,
         is_playing: bool,
+        // Muted while the emulation isn't running at normal speed.
+        is_muted: bool,
     }
 
     impl BuzzerController {
-        pub fn new_square_wave_buzzer(audio_subsystem: AudioSubsystem, st_equals_buzzer: bool) -> Self {
+        // frequency is given in Hz and volume in percentage (0 to 100).
+        pub fn new_square_wave_buzzer(
+            audio_subsystem: AudioSubsystem,
+            st_equals_buzzer: bool,
+            frequency: u32,
+            volume: u32) -> Result<Self, EmuError> {
+
             let desired_spec = AudioSpecDesired {
                 freq: Some(44100),
                 channels: Some(1),
@@ -25,44 +37,69 @@ pub mod buzzer {
                 |spec| {
                 SquareWave {
                     phase: 0.0,
-                    phase_inc: 440.0 / spec.freq as f32,
-                    volume: 0.25
+                    phase_inc: frequency as f32 / spec.freq as f32,
+                    volume: volume as f32 / 100.0
                 }
-            }).unwrap();
+            }).map_err(EmuError::Audio)?;
 
-            Self {
-                device,
+            Ok(Self {
+                device: Some(device),
                 st_equals_buzzer,
                 is_playing: false,
+                is_muted: false,
+            })
+        }
+
+        // A buzzer that never sounds, used when the audio device isn't available.
+        pub fn new_silent() -> Self {
+            Self {
+                device: None,
+                st_equals_buzzer: false,
+                is_playing: false,
+                is_muted: true,
             }
         }
 
         pub fn play_based_on_st(&mut self, st: u8) {
-            if !self.st_equals_buzzer {
-                return;
-            }
+            self.set_buzzer(st > 0);
+        }
 
-            match st {
-                0 => self.pause(),
-                _ => self.play()
+        pub fn set_muted(&mut self, muted: bool) {
+            self.is_muted = muted;
+            if muted {
+                self.pause();
             }
         }
 
         fn play(&mut self) {
-            if !self.is_playing {
-                self.device.resume();
+            if let (Some(device), false) = (&self.device, self.is_playing) {
+                device.resume();
                 self.is_playing = true;
             }
         }
 
         fn pause(&mut self) {
-            if self.is_playing {
-                self.device.pause();
+            if let (Some(device), true) = (&self.device, self.is_playing) {
+                device.pause();
                 self.is_playing = false;
             }
         }
     }
 
+    impl BuzzerBackend for BuzzerController {
+        fn set_buzzer(&mut self, active: bool) {
+            if !self.st_equals_buzzer || self.is_muted {
+                return;
+            }
+
+            if active {
+                self.play();
+            } else {
+                self.pause();
+            }
+        }
+    }
+
     struct SquareWave {
         phase: f32,
         phase_inc: f32,
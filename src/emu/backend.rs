@@ -0,0 +1,37 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// The hardware a frontend connects the core to: a display, a keypad, a buzzer and a source of random numbers.
+// None of them allocates, so the same core runs on the desktop (SDL), on the web and on microcontrollers,
+// e.g. with an LCD, a button matrix, a piezo buzzer and a hardware RNG (see Emulator::run_frame_on).
+pub mod backends {
+    use rand::Rng;
+    use rand::rngs::StdRng;
+
+    // Shows the screen, given as SCREEN_W * SCREEN_H pixels by rows (1 is on and 0 is off).
+    pub trait DisplayBackend {
+        fn present(&mut self, pixels: &[u8]);
+    }
+
+    // Reads which keys are held down, indexed by their hex value (0 to F).
+    pub trait KeypadBackend {
+        fn read_keys(&mut self) -> [bool;16];
+    }
+
+    // Turns the buzzer on or off (it's on while the sound timer is active).
+    pub trait BuzzerBackend {
+        fn set_buzzer(&mut self, active: bool);
+    }
+
+    // The random numbers of the Cxkk instruction.
+    pub trait RandomSource {
+        fn next_byte(&mut self) -> u8;
+    }
+
+    // The default source, which can be seeded to make the execution reproducible.
+    impl RandomSource for StdRng {
+        fn next_byte(&mut self) -> u8 {
+            self.gen()
+        }
+    }
+}
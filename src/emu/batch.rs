@@ -0,0 +1,171 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// Runs many ROMs headless, each on its own machine, spread over parallel threads (e.g. to check a whole ROM collection).
+pub mod batch_run {
+    use crate::emu::api::emulator_api::Emulator;
+    use crate::emu::config::settings::EmuSettings;
+    #[cfg(feature = "jit")]
+    use crate::emu::jit::jit_compiler::Jit;
+    #[cfg(feature = "jit")]
+    use crate::emu::logic::cpu::JitMode;
+    use crate::error::emu_error::EmuError;
+
+    use std::path::{ Path, PathBuf };
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    use std::sync::{ Mutex, PoisonError };
+    use std::thread;
+
+    // How long each ROM runs when no amount of frames is given (10 seconds).
+    pub const DEFAULT_BATCH_FRAMES: u64 = 600;
+
+    // A ROM to run, with the settings it runs with (from the settings file, the CHIP-8 database, its profile and the command line).
+    pub struct BatchJob {
+        rom_path: PathBuf,
+        title: Option<String>,
+        rom: Vec<u8>,
+        emu_settings: EmuSettings,
+        #[cfg(feature = "jit")]
+        jit_mode: Option<JitMode>,
+    }
+
+    impl BatchJob {
+        pub fn new(rom_path: PathBuf, title: Option<String>, rom: Vec<u8>, emu_settings: EmuSettings) -> Self {
+            Self {
+                rom_path,
+                title,
+                rom,
+                emu_settings,
+                #[cfg(feature = "jit")]
+                jit_mode: None,
+            }
+        }
+
+        // Runs the ROM through the JIT.
+        #[cfg(feature = "jit")]
+        pub fn set_jit_mode(&mut self, jit_mode: Option<JitMode>) {
+            self.jit_mode = jit_mode;
+        }
+    }
+
+    // How a ROM ran: until the last frame or until the CPU was halted by a fault.
+    pub struct RunSummary {
+        frames: u64,
+        instructions: u64,
+        pixels_on: usize,
+        fault: Option<String>,
+        unknown_opcodes: Option<String>,
+        // The report of the JIT in lockstep mode.
+        #[cfg(feature = "jit")]
+        jit_report: Option<String>,
+    }
+
+    pub struct BatchResult {
+        rom_path: PathBuf,
+        title: Option<String>,
+        // Err if the ROM couldn't be loaded.
+        outcome: Result<RunSummary, EmuError>,
+    }
+
+    impl BatchResult {
+        pub fn get_rom_path(&self) -> &Path {
+            &self.rom_path
+        }
+
+        // Whether the ROM ran until the end without faults or unknown instructions.
+        pub fn is_ok(&self) -> bool {
+            self.outcome.as_ref().is_ok_and(|summary| summary.fault.is_none() && summary.unknown_opcodes.is_none())
+        }
+
+        // A line with the ROM, how it ended and what it did, e.g.
+        // "pong.ch8 [Pong]: ok, 600 frames, 12000 instructions, 84 pixels on".
+        pub fn get_line(&self) -> String {
+            let rom_name = self.rom_path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+            let rom_name = match &self.title {
+                Some(title) => format!("{rom_name} [{title}]"),
+                None => rom_name
+            };
+
+            let summary = match &self.outcome {
+                Ok(summary) => summary,
+                Err(err) => return format!("{rom_name}: not loaded, {err}")
+            };
+
+            let status = match (&summary.fault, &summary.unknown_opcodes) {
+                (Some(fault), _) => format!("halted by a {fault}"),
+                (None, Some(_)) => String::from("unknown instructions"),
+                (None, None) => String::from("ok")
+            };
+            let mut line = format!("{rom_name}: {status}, {} frames, {} instructions, {} pixels on",
+                summary.frames, summary.instructions, summary.pixels_on);
+            if let Some(unknown_opcodes) = &summary.unknown_opcodes {
+                line += &format!("\n    {unknown_opcodes}");
+            }
+            #[cfg(feature = "jit")]
+            if let Some(jit_report) = &summary.jit_report {
+                line += &format!("\n    {jit_report}");
+            }
+            line
+        }
+    }
+
+    pub struct BatchRunner;
+
+    impl BatchRunner {
+        // Runs each ROM for the amount of frames, with the given number of threads (at least one).
+        // The results are in the order of the jobs.
+        pub fn run(jobs: &[BatchJob], frames: u64, seed: Option<u64>, threads: usize) -> Vec<BatchResult> {
+            let next_job = AtomicUsize::new(0);
+            let results: Mutex<Vec<Option<BatchResult>>> = Mutex::new(jobs.iter().map(|_| None).collect());
+
+            // Each thread takes the next job until there are none left, so a slow ROM doesn't hold the others back.
+            thread::scope(|scope| {
+                for _ in 0..threads.clamp(1, jobs.len().max(1)) {
+                    scope.spawn(|| loop {
+                        let job_index = next_job.fetch_add(1, Ordering::Relaxed);
+                        let Some(job) = jobs.get(job_index) else { break; };
+                        let result = BatchRunner::run_job(job, frames, seed);
+                        results.lock().unwrap_or_else(PoisonError::into_inner)[job_index] = Some(result);
+                    });
+                }
+            });
+
+            results.into_inner().unwrap_or_else(PoisonError::into_inner).into_iter().flatten().collect()
+        }
+
+        fn run_job(job: &BatchJob, frames: u64, seed: Option<u64>) -> BatchResult {
+            let mut emulator = Emulator::with_settings(&job.emu_settings);
+            if let Some(seed) = seed {
+                emulator.set_seed(seed);
+            }
+            #[cfg(feature = "jit")]
+            if let Some(jit_mode) = job.jit_mode {
+                match Jit::new(jit_mode) {
+                    Ok(jit) => emulator.set_jit(Some(jit)),
+                    Err(err) => return BatchResult { rom_path: job.rom_path.clone(), title: job.title.clone(), outcome: Err(err) }
+                }
+            }
+
+            let outcome = emulator.load_rom(&job.rom).map(|_| {
+                let mut frame_count: u64 = 0;
+                let mut instructions: u64 = 0;
+                while frame_count < frames && emulator.fault().is_none() {
+                    instructions += emulator.run_frame();
+                    frame_count += 1;
+                }
+
+                RunSummary {
+                    frames: frame_count,
+                    instructions,
+                    pixels_on: emulator.framebuffer().iter().filter(|pixel| **pixel == 1).count(),
+                    fault: emulator.fault().map(|fault_info| fault_info.get_fault().get_description()),
+                    unknown_opcodes: emulator.unknown_opcode_summary(),
+                    #[cfg(feature = "jit")]
+                    jit_report: emulator.jit().and_then(|jit| jit.get_lockstep_report()),
+                }
+            });
+
+            BatchResult { rom_path: job.rom_path.clone(), title: job.title.clone(), outcome }
+        }
+    }
+}
@@ -2,95 +2,170 @@
 // SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
 
 pub mod settings {
+    use crate::emu::logic::cpu::{ FaultPolicy, UnknownOpcodePolicy, DEFAULT_CYCLES_PER_FRAME };
+    use crate::emu::sys_call::sys_handler::SysCallMode;
+    use crate::util::utilities::PathUtil;
+
+    use sdl2::keyboard::Keycode;
     use sdl2::pixels::Color;
-    use std::fs::File;
-    use std::io::Read;
+    use std::env;
+    use std::fmt;
+    use std::ops::RangeInclusive;
+    use std::path::{ Path, PathBuf };
+    use toml_edit::{ ImDocument, Item };
+
+    pub const CONFIG_FILE: &str = "config.toml";
+
+    pub const SCALE_RANGE: RangeInclusive<u32> = 1..=20;
+    pub const CYCLES_PER_FRAME_RANGE: RangeInclusive<u32> = 1..=99999;
+
+    // Built-in color palettes, as (name, background color, pixel color).
+    pub const PALETTES: [(&str, Color, Color);5] = [
+        ("mono",  Color::RGB(0x00, 0x00, 0x00), Color::RGB(0xff, 0xff, 0xff)),
+        ("amber", Color::RGB(0x1a, 0x0f, 0x00), Color::RGB(0xff, 0xb0, 0x00)),
+        ("green", Color::RGB(0x00, 0x1a, 0x00), Color::RGB(0x33, 0xff, 0x33)),
+        ("lcd",   Color::RGB(0x9b, 0xbc, 0x0f), Color::RGB(0x0f, 0x38, 0x0f)),
+        ("octo",  Color::RGB(0x99, 0x66, 0x00), Color::RGB(0xff, 0xcc, 0x00))];
 
+    // Default bindings for the 16 keys of the emulator keyboard, indexed by the hex key value (0 to f).
+    pub const DEFAULT_KEY_BINDINGS: [Keycode;16] = [
+        Keycode::X,    Keycode::Num1, Keycode::Num2, Keycode::Num3,
+        Keycode::Q,    Keycode::W,    Keycode::E,    Keycode::A,
+        Keycode::S,    Keycode::D,    Keycode::Z,    Keycode::C,
+        Keycode::Num4, Keycode::R,    Keycode::F,    Keycode::V];
+
+    #[derive(Clone)]
     pub struct EmuSettings {
+        // [video]
         scale: u32,
-        cycles_per_frame: u32,
         bg_color: Color,
         pixel_color: Color,
-        default_ch8_folder: String,
+        vsync: bool,
+        // [cpu]
+        cycles_per_frame: u32,
+        fault_policy: FaultPolicy,
+        unknown_opcode_policy: UnknownOpcodePolicy,
+        sys_calls: SysCallMode,
+        // [audio]
         st_equals_buzzer: bool,
+        buzzer_frequency: u32,
+        buzzer_volume: u32,
+        // [input]
+        key_bindings: [Keycode;16],
+        // [quirks]
         bit_shift_instructions_use_vy: bool,
         store_read_instructions_change_i: bool,
+        // [paths]
+        // The folders are kept as written and resolved by their getters (see base_folder).
+        default_ch8_folder: String,
+        profile_folder: Option<String>,
+        database_folder: Option<String>,
+        // Relative paths are resolved from the folder of the settings file
+        // (or from the current directory if there's no settings file).
+        base_folder: PathBuf,
+    }
+
+    impl Default for EmuSettings {
+        fn default() -> Self {
+            Self::new()
+        }
     }
 
     impl EmuSettings {
         pub fn new() -> Self {
-            let mut emu_settings = EmuSettings::new_default();
+            EmuSettings::from_file(&EmuSettings::find_config_file())
+        }
 
-            match File::open("config.txt") {
-                Ok(mut file) => {
-                    let mut file_str = String::new();
-                    if let Err(_) = file.read_to_string(&mut file_str) {
-                        return EmuSettings::log_invalid_use_default();
-                    }
+        // The settings file is looked for in the current directory, in the user's config directory
+        // and next to the executable, in this order.
+        // If it can't be found anywhere, the path in the current directory is returned.
+        pub fn find_config_file() -> PathBuf {
+            let local_config_file = PathBuf::from(CONFIG_FILE);
 
-                    let lines: Vec<&str> = file_str.split('\n').collect();
-                    for line in lines {
-                        if line.starts_with("#") { continue; }
-
-                        if !line.contains("=") { return EmuSettings::log_invalid_use_default(); }
-
-                        let full_setting: Vec<&str> = line.split('=').collect();
-                        if full_setting.len() > 2 { return EmuSettings::log_invalid_use_default(); }
-
-                        let setting_name: &str = full_setting.first().unwrap();
-                        let setting_val: &str = full_setting.last().unwrap();
-
-                        // Single values.
-                        if !setting_val.contains(",") {
-                            match setting_name {
-                                "scale" =>
-                                    emu_settings.scale = EmuSettings::parse_u32(setting_val, 1, 20, 10),
-                                "cycles_per_frame" =>
-                                    emu_settings.cycles_per_frame = EmuSettings::parse_u32(setting_val, 1, 99999, 20),
-                                "default_ch8_folder" =>
-                                    emu_settings.default_ch8_folder = String::from(setting_val.trim()),
-                                "st_equals_buzzer" =>
-                                    emu_settings.st_equals_buzzer = EmuSettings::parse_bool(setting_val, false),
-                                "bit_shift_instructions_use_vy" =>
-                                    emu_settings.bit_shift_instructions_use_vy = EmuSettings::parse_bool(setting_val, true),
-                                "store_read_instructions_change_i" =>
-                                    emu_settings.store_read_instructions_change_i = EmuSettings::parse_bool(setting_val, true),
-                                _ => return EmuSettings::log_invalid_use_default()
-                            }
-                        // CSV (color).
-                        } else {
-                            let rgb_vals: Vec<&str> = setting_val.split(',').collect();
-                            if rgb_vals.len() != 3 {
-                                return EmuSettings::log_invalid_use_default();
-                            }
-
-                            match setting_name {
-                                "bg_color" =>
-                                    emu_settings.bg_color = Color::RGB(
-                                        EmuSettings::parse_color(rgb_vals.get(0).unwrap(), false),
-                                        EmuSettings::parse_color(rgb_vals.get(1).unwrap(), false),
-                                        EmuSettings::parse_color(rgb_vals.get(2).unwrap(), false)),
-                                "pixel_color" =>
-                                    emu_settings.pixel_color = Color::RGB(
-                                        EmuSettings::parse_color(rgb_vals.get(0).unwrap(), true),
-                                        EmuSettings::parse_color(rgb_vals.get(1).unwrap(), true),
-                                        EmuSettings::parse_color(rgb_vals.get(2).unwrap(), true)),
-                                _ => return EmuSettings::log_invalid_use_default()
-                            }
-                        }
-                    }
-                },
+            [Some(local_config_file.clone()),
+             PathUtil::config_dir().map(|dir| dir.join(CONFIG_FILE)),
+             PathUtil::exe_dir().map(|dir| dir.join(CONFIG_FILE))]
+                .into_iter()
+                .flatten()
+                .find(|path| path.is_file())
+                .unwrap_or(local_config_file)
+        }
+
+        // Loads the settings from a TOML file, printing every problem found along the way.
+        // Invalid settings are reported and replaced by their default values, the valid ones are always kept.
+        pub fn from_file(path: &Path) -> Self {
+            let mut emu_settings = EmuSettings::new_default();
+
+            let file_str = match std::fs::read_to_string(path) {
+                Ok(file_str) => file_str,
                 Err(_) => {
-                    println!("Couldn't locate the file 'config.txt'! Default values will be used.");
-                    return EmuSettings::new_default();
+                    println!("Couldn't locate the file '{}'! Default values will be used.", path.display());
+                    return emu_settings;
                 }
             };
 
-            emu_settings.default_ch8_folder.insert_str(0, "\\");
-            emu_settings.default_ch8_folder.insert_str(emu_settings.get_default_ch8_folder().len(), "\\");
+            for error in emu_settings.apply_toml(&file_str) {
+                println!("{}:{error}", path.display());
+            }
+
+            if let Some(config_folder) = path.parent().filter(|folder| !folder.as_os_str().is_empty()) {
+                emu_settings.base_folder = config_folder.to_path_buf();
+            }
             emu_settings
         }
 
+        // Parses the content of a TOML settings file.
+        // Returns the resulting settings alongside every error found.
+        pub fn parse(file_str: &str) -> (Self, Vec<ConfigError>) {
+            let mut emu_settings = EmuSettings::new_default();
+            let errors = emu_settings.apply_toml(file_str);
+            (emu_settings, errors)
+        }
+
+        // Applies every valid setting found in the content of a TOML settings file on top of the current ones.
+        // Returns every error found.
+        pub fn apply_toml(&mut self, file_str: &str) -> Vec<ConfigError> {
+            let mut errors = Vec::new();
+
+            let doc = match ImDocument::parse(file_str) {
+                Ok(doc) => doc,
+                Err(err) => {
+                    errors.push(ConfigError::new(
+                        err.span().map(|span| line_of(file_str, span.start)),
+                        "",
+                        &format!("{} (the whole file was ignored)", err.message().trim().replace('\n', ", "))));
+                    return errors;
+                }
+            };
+
+            for (section_name, section) in doc.iter() {
+                let section_line = doc.as_table().key(section_name)
+                    .and_then(|key| key.span())
+                    .map(|span| line_of(file_str, span.start));
+
+                let table = match section.as_table() {
+                    Some(table) => table,
+                    None => {
+                        errors.push(ConfigError::new(section_line, section_name, "expected a section (e.g. [video])"));
+                        continue;
+                    }
+                };
+
+                for (key_name, item) in table.iter() {
+                    let line = table.key(key_name)
+                        .and_then(|key| key.span())
+                        .map(|span| line_of(file_str, span.start))
+                        .or(section_line);
+
+                    if let Err(reason) = self.apply(section_name, key_name, item) {
+                        errors.push(ConfigError::new(line, &format!("{section_name}.{key_name}"), &reason));
+                    }
+                }
+            }
+
+            errors
+        }
+
         pub fn get_bg_color(&self) -> Color {
             self.bg_color
         }
@@ -99,22 +174,60 @@ pub mod settings {
             self.cycles_per_frame
         }
 
-        pub fn get_default_ch8_folder(&self) -> &str {
-            &self.default_ch8_folder
+        pub fn get_fault_policy(&self) -> FaultPolicy {
+            self.fault_policy
+        }
+
+        pub fn get_unknown_opcode_policy(&self) -> UnknownOpcodePolicy {
+            self.unknown_opcode_policy
+        }
+
+        pub fn get_sys_calls(&self) -> SysCallMode {
+            self.sys_calls
+        }
+
+        pub fn get_default_ch8_folder(&self) -> PathBuf {
+            PathUtil::resolve(&self.default_ch8_folder, &self.base_folder)
+        }
+
+        // Defaults to the "profiles" folder inside the user's data directory.
+        pub fn get_profile_folder(&self) -> PathBuf {
+            self.resolve_data_folder(&self.profile_folder, "profiles")
+        }
+
+        // Defaults to the "database" folder inside the user's data directory.
+        pub fn get_database_folder(&self) -> PathBuf {
+            self.resolve_data_folder(&self.database_folder, "database")
         }
 
         pub fn get_pixel_color(&self) -> Color {
             self.pixel_color
         }
-        
+
         pub fn get_scale(&self) -> u32 {
             self.scale
         }
 
+        pub fn get_vsync(&self) -> bool {
+            self.vsync
+        }
+
         pub fn get_st_equals_buzzer(&self) -> bool {
             self.st_equals_buzzer
         }
 
+        pub fn get_buzzer_frequency(&self) -> u32 {
+            self.buzzer_frequency
+        }
+
+        pub fn get_buzzer_volume(&self) -> u32 {
+            self.buzzer_volume
+        }
+
+        pub fn get_key_bindings(&self) -> [Keycode;16] {
+            self.key_bindings
+        }
+
         pub fn get_bit_shift_instructions_use_vy(&self) -> bool {
             self.bit_shift_instructions_use_vy
         }
@@ -123,53 +236,257 @@ pub mod settings {
             self.store_read_instructions_change_i
         }
 
-        fn new_default() -> Self {
+        pub fn set_scale(&mut self, val: u32) {
+            self.scale = val;
+        }
+
+        pub fn set_bg_color(&mut self, val: Color) {
+            self.bg_color = val;
+        }
+
+        pub fn set_pixel_color(&mut self, val: Color) {
+            self.pixel_color = val;
+        }
+
+        pub fn set_cycles_per_frame(&mut self, val: u32) {
+            self.cycles_per_frame = val;
+        }
+
+        pub fn set_fault_policy(&mut self, val: FaultPolicy) {
+            self.fault_policy = val;
+        }
+
+        pub fn set_unknown_opcode_policy(&mut self, val: UnknownOpcodePolicy) {
+            self.unknown_opcode_policy = val;
+        }
+
+        pub fn set_sys_calls(&mut self, val: SysCallMode) {
+            self.sys_calls = val;
+        }
+
+        pub fn set_bit_shift_instructions_use_vy(&mut self, val: bool) {
+            self.bit_shift_instructions_use_vy = val;
+        }
+
+        pub fn set_store_read_instructions_change_i(&mut self, val: bool) {
+            self.store_read_instructions_change_i = val;
+        }
+
+        // The default values, without reading any settings file.
+        pub fn new_default() -> Self {
             Self {
                 scale: 10,
-                cycles_per_frame: 20,
                 bg_color: Color::RGB(0x00, 0x00, 0x00),
                 pixel_color: Color::RGB(0xff, 0xff, 0xff),
-                default_ch8_folder: String::from("\\ch8\\"),
+                vsync: false,
+                cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+                fault_policy: FaultPolicy::Halt,
+                unknown_opcode_policy: UnknownOpcodePolicy::LogOnce,
+                sys_calls: SysCallMode::Unknown,
                 st_equals_buzzer: false,
+                buzzer_frequency: 440,
+                buzzer_volume: 25,
+                key_bindings: DEFAULT_KEY_BINDINGS,
                 bit_shift_instructions_use_vy: true,
                 store_read_instructions_change_i: true,
+                default_ch8_folder: String::from("ch8"),
+                profile_folder: None,
+                database_folder: None,
+                base_folder: env::current_dir().unwrap_or_default(),
             }
         }
 
-        fn parse_u32(
-            setting_val: &str,
-            min_val_incl: u32,
-            max_val_incl: u32,
-            default_val: u32) ->u32 {
-                
-            match setting_val.trim().parse() {
-                Ok(parsed_val) =>
-                    if parsed_val < min_val_incl || parsed_val > max_val_incl {
-                        default_val
-                    } else {
-                        parsed_val
-                    }
-                Err(_) => default_val
+        fn resolve_data_folder(&self, folder: &Option<String>, default_folder: &str) -> PathBuf {
+            match (folder, PathUtil::data_dir()) {
+                (Some(folder), _) => PathUtil::resolve(folder, &self.base_folder),
+                (None, Some(data_dir)) => data_dir.join(default_folder),
+                (None, None) => self.base_folder.join(default_folder)
             }
         }
 
-        fn parse_bool(setting_val: &str, default_val: bool) -> bool {
-            match setting_val.trim().parse() {
-                Ok(parsed_val) => parsed_val,
-                Err(_) => default_val
+        // Applies a single setting, returning the reason why it was rejected (if it was).
+        fn apply(&mut self, section_name: &str, key_name: &str, item: &Item) -> Result<(), String> {
+            match (section_name, key_name) {
+                ("video", "scale") =>
+                    self.scale = EmuSettings::parse_u32(item, SCALE_RANGE)?,
+                ("video", "bg_color") =>
+                    self.bg_color = EmuSettings::parse_color(item)?,
+                ("video", "pixel_color") =>
+                    self.pixel_color = EmuSettings::parse_color(item)?,
+                ("video", "vsync") =>
+                    self.vsync = EmuSettings::parse_bool(item)?,
+                ("cpu", "cycles_per_frame") =>
+                    self.cycles_per_frame = EmuSettings::parse_u32(item, CYCLES_PER_FRAME_RANGE)?,
+                ("cpu", "fault_policy") =>
+                    self.fault_policy = EmuSettings::parse_fault_policy(item)?,
+                ("cpu", "unknown_opcode_policy") =>
+                    self.unknown_opcode_policy = EmuSettings::parse_unknown_opcode_policy(item)?,
+                ("cpu", "sys_calls") =>
+                    self.sys_calls = EmuSettings::parse_sys_calls(item)?,
+                ("audio", "st_equals_buzzer") =>
+                    self.st_equals_buzzer = EmuSettings::parse_bool(item)?,
+                ("audio", "buzzer_frequency") =>
+                    self.buzzer_frequency = EmuSettings::parse_u32(item, 20..=20000)?,
+                ("audio", "buzzer_volume") =>
+                    self.buzzer_volume = EmuSettings::parse_u32(item, 0..=100)?,
+                ("input", "key_bindings") =>
+                    self.key_bindings = EmuSettings::parse_key_bindings(item)?,
+                ("quirks", "bit_shift_instructions_use_vy") =>
+                    self.bit_shift_instructions_use_vy = EmuSettings::parse_bool(item)?,
+                ("quirks", "store_read_instructions_change_i") =>
+                    self.store_read_instructions_change_i = EmuSettings::parse_bool(item)?,
+                ("paths", "default_ch8_folder") =>
+                    self.default_ch8_folder = EmuSettings::parse_string(item)?,
+                ("paths", "profile_folder") =>
+                    self.profile_folder = Some(EmuSettings::parse_string(item)?),
+                ("paths", "database_folder") =>
+                    self.database_folder = Some(EmuSettings::parse_string(item)?),
+                ("video" | "cpu" | "audio" | "input" | "quirks" | "paths", _) =>
+                    return Err(String::from("unknown setting")),
+                _ =>
+                    return Err(format!("unknown section '[{section_name}]'")),
             }
+            Ok(())
         }
 
-        fn parse_color(setting_val: &str, is_pixel: bool) -> u8 {
-            match setting_val.trim().parse() {
-                Ok(parsed_val) => parsed_val,
-                Err(_) => if is_pixel { 255 } else { 0 }
+        fn parse_u32(item: &Item, range: RangeInclusive<u32>) -> Result<u32, String> {
+            let parsed_val = item.as_integer()
+                .ok_or_else(|| format!("expected an integer, found {}", item.type_name()))?;
+
+            match u32::try_from(parsed_val) {
+                Ok(parsed_val) if range.contains(&parsed_val) => Ok(parsed_val),
+                _ => Err(format!("{parsed_val} is out of range (min={}, max={})", range.start(), range.end()))
             }
         }
 
-        fn log_invalid_use_default() -> EmuSettings {
-            println!("Invalid 'config.txt' file! Default values will be used.");
-            EmuSettings::new_default()
+        fn parse_fault_policy(item: &Item) -> Result<FaultPolicy, String> {
+            let name = item.as_str()
+                .ok_or_else(|| format!("expected \"halt\", \"wrap\" or \"debug\", found {}", item.type_name()))?;
+
+            FaultPolicy::from_name(name.trim())
+                .ok_or_else(|| format!("unknown fault policy '{name}' (expected \"halt\", \"wrap\" or \"debug\")"))
+        }
+
+        fn parse_unknown_opcode_policy(item: &Item) -> Result<UnknownOpcodePolicy, String> {
+            let expected = "\"ignore\", \"log\", \"halt\" or \"debug\"";
+            let name = item.as_str()
+                .ok_or_else(|| format!("expected {expected}, found {}", item.type_name()))?;
+
+            UnknownOpcodePolicy::from_name(name.trim())
+                .ok_or_else(|| format!("unknown policy '{name}' (expected {expected})"))
+        }
+
+        fn parse_sys_calls(item: &Item) -> Result<SysCallMode, String> {
+            let name = item.as_str()
+                .ok_or_else(|| format!("expected \"unknown\" or \"ignore\", found {}", item.type_name()))?;
+
+            SysCallMode::from_name(name.trim())
+                .ok_or_else(|| format!("unknown mode '{name}' (expected \"unknown\" or \"ignore\")"))
+        }
+
+        fn parse_bool(item: &Item) -> Result<bool, String> {
+            item.as_bool()
+                .ok_or_else(|| format!("expected true or false, found {}", item.type_name()))
+        }
+
+        fn parse_string(item: &Item) -> Result<String, String> {
+            match item.as_str() {
+                Some(val) if !val.trim().is_empty() => Ok(String::from(val.trim())),
+                Some(_) => Err(String::from("expected a non-empty string")),
+                None => Err(format!("expected a string, found {}", item.type_name()))
+            }
         }
+
+        // Colors are written as an array of 3 decimal values (R, G, B) ranging from 0 to 255.
+        fn parse_color(item: &Item) -> Result<Color, String> {
+            let arr = item.as_array()
+                .ok_or_else(|| format!("expected an array like [255, 255, 255], found {}", item.type_name()))?;
+
+            if arr.len() != 3 {
+                return Err(format!("expected 3 values (R, G, B), found {}", arr.len()));
+            }
+
+            let mut rgb_vals = [0u8;3];
+            for (i, val) in arr.iter().enumerate() {
+                rgb_vals[i] = match val.as_integer() {
+                    Some(component @ 0..=255) => component as u8,
+                    Some(component) => return Err(format!("color component {component} is out of range (min=0, max=255)")),
+                    None => return Err(format!("expected an integer color component, found {}", val.type_name()))
+                };
+            }
+
+            Ok(Color::RGB(rgb_vals[0], rgb_vals[1], rgb_vals[2]))
+        }
+
+        // Key bindings are written as an array of 16 key names, where the position in the array
+        // is the emulator key (0 to f) and the name is the SDL name of the key (e.g. "X", "1", "Left").
+        fn parse_key_bindings(item: &Item) -> Result<[Keycode;16], String> {
+            let arr = item.as_array()
+                .ok_or_else(|| format!("expected an array of 16 key names, found {}", item.type_name()))?;
+
+            if arr.len() != 16 {
+                return Err(format!("expected 16 key names, found {}", arr.len()));
+            }
+
+            let mut key_bindings = DEFAULT_KEY_BINDINGS;
+            for (i, val) in arr.iter().enumerate() {
+                let key_name = val.as_str()
+                    .ok_or_else(|| format!("key {i:X}: expected a key name, found {}", val.type_name()))?;
+
+                key_bindings[i] = Keycode::from_name(key_name)
+                    .ok_or_else(|| format!("key {i:X}: unknown key name '{key_name}'"))?;
+            }
+            Ok(key_bindings)
+        }
+    }
+
+    // A problem found while reading the settings file.
+    pub struct ConfigError {
+        line: Option<usize>,
+        key: String,
+        reason: String,
+    }
+
+    impl ConfigError {
+        fn new(line: Option<usize>, key: &str, reason: &str) -> Self {
+            Self {
+                line,
+                key: String::from(key),
+                reason: String::from(reason),
+            }
+        }
+
+        pub fn get_line(&self) -> Option<usize> {
+            self.line
+        }
+
+        pub fn get_key(&self) -> &str {
+            &self.key
+        }
+
+        pub fn get_reason(&self) -> &str {
+            &self.reason
+        }
+    }
+
+    impl fmt::Display for ConfigError {
+        // Formatted as "line: 'key' reason", so it can be prefixed by the file name.
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self.line {
+                Some(line) => write!(f, "{line}: ")?,
+                None => write!(f, " ")?
+            }
+
+            if self.key.is_empty() {
+                write!(f, "{}", self.reason)
+            } else {
+                write!(f, "'{}' {}", self.key, self.reason)
+            }
+        }
+    }
+
+    // Converts a byte offset into a 1-based line number.
+    fn line_of(file_str: &str, offset: usize) -> usize {
+        file_str[..offset.min(file_str.len())].matches('\n').count() + 1
     }
 }
@@ -2,57 +2,345 @@
 // SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
 
 pub mod emulator {
+    use crate::emu::api::emulator_api::Emulator;
     use crate::emu::audio::buzzer::BuzzerController;
+    use crate::emu::backend::backends::{ BuzzerBackend, DisplayBackend };
+    use crate::emu::config::settings::PALETTES;
+    use crate::emu::debugger::terminal_debugger::{ Debugger, DebuggerExit };
+    use crate::emu::display::screen::{ Screen, ScreenController };
     use crate::emu::input::keyboard::{ CustomKeyEvent, KeyboardController };
-    use crate::emu::logic::cpu::CpuController;
-    use crate::emu::memory::memory::MemoryController;
+    use crate::emu::logic::cpu::{ CpuController, FaultInfo, FaultPolicy };
+    use crate::emu::memory::memory::{ Memory, MemoryController };
+    use crate::emu::overlay::menu::{ MenuAction, MenuStatus, Overlay };
+    use crate::emu::profile::rom_profile::RomProfile;
+    use crate::emu::timing::scheduler::{ FrameScheduler, PerfCounter };
+    use crate::emu::watcher::rom_watcher::{ RomWatcher, WatchMode };
+    use crate::error::emu_error::EmuError;
     use crate::sdl::wrapper::SDLController;
 
-    use std::time::Duration;
+    use sdl2::EventPump;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    // Why the emulator stopped running the current ROM.
+    pub enum EmuExit {
+        Quit,
+        LoadRom(PathBuf),
+    }
 
     pub struct EmuController;
 
     impl EmuController {
+        // Runs until the window is closed or, if given, until the amount of (60hz) frames is reached.
+        // Returns what must be done next, e.g. load another ROM selected in the overlay menu.
+        // With a watch mode, the ROM is reloaded whenever its file changes.
+        #[allow(clippy::too_many_arguments)]
         pub fn run_emulator(
             sdl_ctrl: &mut SDLController,
+            event_pump: &mut EventPump,
             mem_ctrl: &mut MemoryController,
             cpu_ctrl: &mut CpuController,
+            screen_ctrl: &mut ScreenController,
             keyboard_ctrl: &mut KeyboardController,
-            buzzer_ctrl: &mut BuzzerController) {
+            buzzer_ctrl: &mut BuzzerController,
+            rom_profile: &RomProfile,
+            scheduler: &mut FrameScheduler,
+            overlay: &mut Overlay,
+            watch: Option<WatchMode>,
+            frames: Option<u64>) -> Result<EmuExit, EmuError> {
 
-            let mut event_pump = sdl_ctrl.get_event_pump();
+            let mut perf_counter = PerfCounter::new();
 
             sdl_ctrl.set_canvas_scale();
-            sdl_ctrl.clear_screen();
+            screen_ctrl.clear_screen();
 
-            // TODO - At the moment, the timer variables are only being used to display information.
-            //        I plan to change from std::thread::sleep() to a more fine control
-            //        using the timer variables.
+            let mut rom_watcher = watch.map(|_| RomWatcher::new(mem_ctrl.get_rom_path()));
 
-            let initial_time = std::time::Instant::now();
-            loop {
-                let frame_start_time = std::time::Instant::now();
+            // A single save state slot, kept while the ROM is running.
+            let mut save_state: Option<(Memory, Screen)> = None;
 
-                if let Some(CustomKeyEvent::Quit) = keyboard_ctrl.check_input_events(&mut event_pump) {
-                    return;
+            let mut frame_count: u64 = 0;
+            let mut paused = false;
+            scheduler.reset();
+            while frames.is_none_or(|frames| frame_count < frames) {
+                let mut frame_advance = false;
+                match keyboard_ctrl.check_input_events(event_pump) {
+                    Some(CustomKeyEvent::Quit) => return Ok(EmuExit::Quit),
+                    Some(CustomKeyEvent::SoftReset) => EmuController::soft_reset(mem_ctrl, cpu_ctrl, screen_ctrl),
+                    Some(CustomKeyEvent::HardReset) => EmuController::reload_rom(mem_ctrl, cpu_ctrl, screen_ctrl),
+                    Some(CustomKeyEvent::SaveProfile) => match rom_profile.save(cpu_ctrl) {
+                        Ok(_) => println!("\nProfile saved to '{}'.", rom_profile.get_path().display()),
+                        Err(err) => println!("\nCouldn't save the profile '{}': {err}", rom_profile.get_path().display())
+                    },
+                    Some(CustomKeyEvent::TogglePause) => {
+                        paused = !paused;
+                        println!("\n{}", if paused { "Paused." } else { "Resumed." });
+                    },
+                    Some(CustomKeyEvent::FrameAdvance) => frame_advance = paused,
+                    Some(CustomKeyEvent::SlowDown) => scheduler.slow_down(),
+                    Some(CustomKeyEvent::SpeedUp) => scheduler.speed_up(),
+                    Some(CustomKeyEvent::NormalSpeed) => scheduler.reset_speed(),
+                    Some(CustomKeyEvent::Debug) => {
+                        if let DebuggerExit::Quit = Debugger::run(cpu_ctrl, mem_ctrl, screen_ctrl, keyboard_ctrl) {
+                            return Ok(EmuExit::Quit);
+                        }
+                        scheduler.reset();
+                    },
+                    Some(CustomKeyEvent::GamepadAdded(joystick_index)) => sdl_ctrl.open_gamepad(joystick_index),
+                    Some(menu_event) => match overlay.handle_event(&menu_event) {
+                        Some(MenuAction::SoftReset) => EmuController::soft_reset(mem_ctrl, cpu_ctrl, screen_ctrl),
+                        Some(MenuAction::HardReset) => EmuController::reload_rom(mem_ctrl, cpu_ctrl, screen_ctrl),
+                        Some(MenuAction::LoadRom(rom_path)) => match MemoryController::check_rom(&rom_path) {
+                            Ok(_) => return Ok(EmuExit::LoadRom(rom_path)),
+                            Err(err) => println!("\nCouldn't load the ROM, {err}.")
+                        },
+                        Some(MenuAction::Quit) => return Ok(EmuExit::Quit),
+                        Some(MenuAction::SlowDown) => scheduler.slow_down(),
+                        Some(MenuAction::SpeedUp) => scheduler.speed_up(),
+                        Some(MenuAction::CyclePalette(step)) => {
+                            let palette_index = match EmuController::get_palette_index(sdl_ctrl) {
+                                Some(palette_index) => (palette_index as i32 + step).rem_euclid(PALETTES.len() as i32) as usize,
+                                None => 0
+                            };
+                            let (_, bg_color, pixel_color) = PALETTES[palette_index];
+                            sdl_ctrl.set_colors(bg_color, pixel_color);
+                        },
+                        Some(MenuAction::ToggleShiftQuirk) =>
+                            cpu_ctrl.set_bit_shift_instructions_use_vy(!cpu_ctrl.get_bit_shift_instructions_use_vy()),
+                        Some(MenuAction::ToggleLoadStoreQuirk) =>
+                            cpu_ctrl.set_store_read_instructions_change_i(!cpu_ctrl.get_store_read_instructions_change_i()),
+                        Some(MenuAction::SaveState) => {
+                            save_state = Some((mem_ctrl.snapshot(), screen_ctrl.snapshot()));
+                            overlay.set_message("STATE SAVED.");
+                        },
+                        Some(MenuAction::LoadState) => match &save_state {
+                            Some((mem, screen)) => {
+                                mem_ctrl.restore(mem);
+                                screen_ctrl.restore(screen);
+                                // Like Emulator::restore, nothing of the run before the load is kept by the CPU (e.g. a fault).
+                                cpu_ctrl.reset();
+                                overlay.set_message("STATE LOADED.");
+                            },
+                            None => overlay.set_message("THERE'S NO SAVED STATE.")
+                        },
+                        None => ()
+                    },
+                    None => ()
+                }
+
+                if rom_watcher.as_mut().is_some_and(RomWatcher::poll) {
+                    match watch {
+                        Some(WatchMode::ReloadSettings) => match MemoryController::check_rom(mem_ctrl.get_rom_path()) {
+                            Ok(_) => {
+                                println!("\nROM changed, reloading '{}'.", mem_ctrl.get_rom_path().display());
+                                return Ok(EmuExit::LoadRom(mem_ctrl.get_rom_path().to_path_buf()));
+                            },
+                            Err(err) => println!("\nCouldn't reload the ROM, {err}.")
+                        },
+                        _ => EmuController::reload_rom(mem_ctrl, cpu_ctrl, screen_ctrl)
+                    }
+                }
+
+                // The emulation stops while the menu is open.
+                let paused_now = paused || overlay.is_open();
+
+                // The buzzer is only heard at normal speed, since its timing (and the games' music) wouldn't make sense otherwise.
+                buzzer_ctrl.set_muted(paused_now || !scheduler.is_normal_speed());
+
+                // Every 60hz tick that is due runs a frame worth of instructions and updates the timers,
+                // regardless of how many times the host is able to render.
+                // The speed scales how often the ticks are due, so the CPU and the timers are always in step.
+                let ticks = if paused_now {
+                    scheduler.wait_while_paused();
+                    frame_advance as u32
+                } else {
+                    scheduler.wait_for_ticks()
                 };
 
-                for _ in 0..cpu_ctrl.get_cycles_per_frame() {
-                    cpu_ctrl.fetch_exec(sdl_ctrl, mem_ctrl, keyboard_ctrl);
+                let mut instructions: u64 = 0;
+                for _ in 0..ticks {
+                    instructions += cpu_ctrl.run_frame(screen_ctrl, mem_ctrl, keyboard_ctrl);
+
+                    buzzer_ctrl.play_based_on_st(mem_ctrl.get_st());
+                    mem_ctrl.dec_all_timers();
+                    frame_count += 1;
+
+                    if let DebuggerExit::Quit = EmuController::handle_cpu_fault(mem_ctrl, cpu_ctrl, screen_ctrl, keyboard_ctrl) {
+                        return Ok(EmuExit::Quit);
+                    }
                 }
 
-                std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+                sdl_ctrl.render(screen_ctrl);
+
+                if overlay.is_open() {
+                    let menu_status = MenuStatus {
+                        speed_name: scheduler.get_speed_name().to_uppercase(),
+                        palette_name: EmuController::get_palette_index(sdl_ctrl)
+                            .map_or(String::from("CUSTOM"), |palette_index| PALETTES[palette_index].0.to_uppercase()),
+                        bit_shift_instructions_use_vy: cpu_ctrl.get_bit_shift_instructions_use_vy(),
+                        store_read_instructions_change_i: cpu_ctrl.get_store_read_instructions_change_i(),
+                        has_save_state: save_state.is_some(),
+                        fps: perf_counter.get_fps(),
+                        ips: perf_counter.get_ips(),
+                    };
+                    sdl_ctrl.render_text(&overlay.get_lines(&menu_status), true);
+                } else if overlay.get_show_perf() {
+                    sdl_ctrl.render_text(&[Overlay::get_perf_line(perf_counter.get_fps(), perf_counter.get_ips())], false);
+                }
 
-                buzzer_ctrl.play_based_on_st(mem_ctrl.get_st());
-                mem_ctrl.dec_all_timers();
                 sdl_ctrl.display_canvas();
 
-                let frame_end_time = std::time::Instant::now();
-                let total_frame_time = frame_end_time - frame_start_time;
-                let total_running_time = frame_start_time - initial_time;
-                
-                print!("Frame time: {}ms\tElapsed time: {}s\r", total_frame_time.as_millis(), total_running_time.as_secs());
+                if perf_counter.add_frame(instructions) {
+                    print!("FPS: {:.1}\tIPS: {:.0}\tSpeed: {}\t\r",
+                        perf_counter.get_fps(), perf_counter.get_ips(), scheduler.get_speed_name());
+                    let _ = std::io::stdout().flush();
+                }
             }
+            Ok(EmuExit::Quit)
+        }
+
+        // Runs independent machines (e.g. the same ROM with different quirks) side by side, each in its own window.
+        // They all get the same input, so they stay in step, and only the first one is heard.
+        // Esc (or closing the windows) quits, F2 restarts all of them and F6 pauses them.
+        pub fn run_side_by_side(
+            machines: &mut [(Emulator, SDLController)],
+            event_pump: &mut EventPump,
+            keyboard_ctrl: &mut KeyboardController,
+            buzzer_ctrl: &mut BuzzerController,
+            scheduler: &mut FrameScheduler,
+            frames: Option<u64>) {
+
+            let mut frame_count: u64 = 0;
+            let mut paused = false;
+            scheduler.reset();
+            while frames.is_none_or(|frames| frame_count < frames) {
+                match keyboard_ctrl.check_input_events(event_pump) {
+                    Some(CustomKeyEvent::Quit | CustomKeyEvent::ToggleMenu) => return,
+                    Some(CustomKeyEvent::SoftReset) => machines.iter_mut().for_each(|(emulator, _)| emulator.reset()),
+                    Some(CustomKeyEvent::TogglePause) => {
+                        paused = !paused;
+                        println!("{}", if paused { "Paused." } else { "Resumed." });
+                    },
+                    Some(CustomKeyEvent::GamepadAdded(joystick_index)) => machines[0].1.open_gamepad(joystick_index),
+                    _ => ()
+                }
+
+                buzzer_ctrl.set_muted(paused);
+                let ticks = if paused {
+                    scheduler.wait_while_paused();
+                    0
+                } else {
+                    scheduler.wait_for_ticks()
+                };
+
+                let keys = keyboard_ctrl.get_keys();
+                for _ in 0..ticks {
+                    for (emulator, _) in machines.iter_mut() {
+                        emulator.set_keys(keys);
+                        emulator.run_frame();
+                    }
+                    buzzer_ctrl.set_buzzer(machines[0].0.sound_active());
+                    frame_count += 1;
+                }
+
+                for (emulator, sdl_ctrl) in machines.iter_mut() {
+                    sdl_ctrl.present(emulator.framebuffer());
+                    sdl_ctrl.display_canvas();
+                }
+            }
+        }
+
+        // Runs without a window, audio or input, as fast as possible.
+        // Stops when the amount of frames (if given) is reached or when the CPU is halted by a fault.
+        pub fn run_headless(
+            mem_ctrl: &mut MemoryController,
+            cpu_ctrl: &mut CpuController,
+            screen_ctrl: &mut ScreenController,
+            keyboard_ctrl: &mut KeyboardController,
+            frames: Option<u64>) {
+
+            let mut frame_count: u64 = 0;
+            while frames.is_none_or(|frames| frame_count < frames) {
+                cpu_ctrl.run_frame(screen_ctrl, mem_ctrl, keyboard_ctrl);
+                mem_ctrl.dec_all_timers();
+                frame_count += 1;
+
+                // Nothing waits for the terminal here (e.g. in a script), so the debug policy only halts the CPU.
+                if let Some(fault_info) = EmuController::report_cpu_fault(cpu_ctrl) {
+                    if let FaultPolicy::Debug = fault_info.get_policy() {
+                        println!("The CPU is halted (the debugger isn't opened in headless mode).");
+                    }
+                }
+                if cpu_ctrl.get_fault().is_some() {
+                    return;
+                }
+            }
+        }
+
+        // Restarts the ROM already in RAM: the registers, stack and timers are reset and the screen is cleared.
+        pub fn soft_reset(mem_ctrl: &mut MemoryController, cpu_ctrl: &mut CpuController, screen_ctrl: &mut ScreenController) {
+            mem_ctrl.soft_reset();
+            cpu_ctrl.reset();
+            screen_ctrl.clear_screen();
+        }
+
+        // Restarts the ROM after reading it again from its file, e.g. after it was rebuilt.
+        // If the file can't be loaded, the machine keeps running as it was.
+        pub fn hard_reset(
+            mem_ctrl: &mut MemoryController,
+            cpu_ctrl: &mut CpuController,
+            screen_ctrl: &mut ScreenController) -> Result<(), EmuError> {
+
+            mem_ctrl.hard_reset()?;
+            cpu_ctrl.reset();
+            screen_ctrl.clear_screen();
+            Ok(())
+        }
+
+        // Hard reset reporting the result in the terminal.
+        fn reload_rom(mem_ctrl: &mut MemoryController, cpu_ctrl: &mut CpuController, screen_ctrl: &mut ScreenController) {
+            match EmuController::hard_reset(mem_ctrl, cpu_ctrl, screen_ctrl) {
+                Ok(_) => println!("\nROM reloaded from '{}'.", mem_ctrl.get_rom_path().display()),
+                Err(err) => println!("\nCouldn't reload the ROM, {err}.")
+            }
+        }
+
+        // Logs the unknown opcodes found in the frame and reports a new CPU fault (if any) in the terminal
+        // and, with the debug policy, opens the debugger.
+        // A halted CPU stays halted until it's reset (or the fault is cleared in the debugger).
+        fn handle_cpu_fault(
+            mem_ctrl: &mut MemoryController,
+            cpu_ctrl: &mut CpuController,
+            screen_ctrl: &mut ScreenController,
+            keyboard_ctrl: &mut KeyboardController) -> DebuggerExit {
+
+            let Some(fault_info) = EmuController::report_cpu_fault(cpu_ctrl) else {
+                return DebuggerExit::Continue;
+            };
+
+            match fault_info.get_policy() {
+                FaultPolicy::Halt => println!("The CPU is halted, reset (F2/F3) to restart the ROM."),
+                FaultPolicy::Wrap => println!("The address was wrapped around (further faults aren't reported)."),
+                FaultPolicy::Debug => return Debugger::run(cpu_ctrl, mem_ctrl, screen_ctrl, keyboard_ctrl)
+            }
+            DebuggerExit::Continue
+        }
+
+        // Logs the unknown opcodes found in the frame and prints a new CPU fault (if any), which is returned.
+        fn report_cpu_fault(cpu_ctrl: &mut CpuController) -> Option<FaultInfo> {
+            cpu_ctrl.drain_unknown_opcode_log(|addr, opcode|
+                println!("Tried to execute non-implemented instruction: {opcode:#06X} at {addr:#06X}"));
+
+            let fault_info = cpu_ctrl.take_new_fault()?;
+            println!("\n{}", fault_info.get_report());
+            cpu_ctrl.dump_trace(&fault_info.get_fault().get_description());
+            Some(fault_info)
+        }
+
+        // The built-in palette matching the current colors (if any).
+        fn get_palette_index(sdl_ctrl: &SDLController) -> Option<usize> {
+            PALETTES.iter().position(|(_, bg_color, pixel_color)|
+                *bg_color == sdl_ctrl.get_bg_color() && *pixel_color == sdl_ctrl.get_pixel_color())
         }
     }
 }
@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// Code coverage of the RAM: which addresses were executed as instructions, read as data (Dxyn and Fx65)
+// and written (Fx33 and Fx55) while the ROM was running.
+// It shows which parts of a ROM a playthrough exercised and tells code apart from data.
+pub mod coverage_map {
+    use crate::emu::memory::memory::RAM_SIZE;
+
+    #[cfg(feature = "desktop")]
+    use sdl2::image::SaveSurface;
+    #[cfg(feature = "desktop")]
+    use sdl2::pixels::{ Color, PixelFormatEnum };
+    #[cfg(feature = "desktop")]
+    use sdl2::rect::Rect;
+    #[cfg(feature = "desktop")]
+    use sdl2::surface::Surface;
+    use std::ops::Range;
+    #[cfg(feature = "desktop")]
+    use std::path::Path;
+
+    const EXECUTED: u8 = 0b001;
+    const READ: u8 =     0b010;
+    const WRITTEN: u8 =  0b100;
+
+    // The maps show the address space as 64 rows of 64 addresses.
+    const MAP_W: usize = 64;
+    // Size of each address in the PNG map, in pixels.
+    #[cfg(feature = "desktop")]
+    const PNG_CELL_SIZE: u32 = 8;
+
+    #[cfg(feature = "desktop")]
+    const UNUSED_COLOR: Color =        Color::RGB(0x20, 0x20, 0x20);
+    #[cfg(feature = "desktop")]
+    const UNUSED_ROM_COLOR: Color =    Color::RGB(0x60, 0x60, 0x60);
+    #[cfg(feature = "desktop")]
+    const READ_COLOR: Color =          Color::RGB(0x33, 0x66, 0xff);
+    #[cfg(feature = "desktop")]
+    const WRITTEN_COLOR: Color =       Color::RGB(0xff, 0x44, 0x44);
+    #[cfg(feature = "desktop")]
+    const MIXED_COLOR: Color =         Color::RGB(0xff, 0xcc, 0x00);
+
+    pub struct Coverage {
+        // Indexed by address, a combination of EXECUTED, READ and WRITTEN.
+        flags: Vec<u8>,
+        // How many times the instruction at each address was executed, used to shade the PNG map.
+        exec_counts: Vec<u64>,
+    }
+
+    impl Default for Coverage {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Coverage {
+        pub fn new() -> Self {
+            Self { flags: vec![0; RAM_SIZE], exec_counts: vec![0; RAM_SIZE], }
+        }
+
+        // Records an instruction that was just executed, given its address, opcode and the value of I before its execution.
+        // Without memory_accessed (e.g. the instruction was stopped by a fault), only the instruction itself is recorded.
+        pub fn record(&mut self, pc: u16, opcode: u16, i: u16, memory_accessed: bool) {
+            self.mark(pc, 2, EXECUTED);
+            self.exec_counts[pc as usize % RAM_SIZE] += 1;
+
+            if !memory_accessed {
+                return;
+            }
+
+            let x = (opcode >> 8) & 0xf;
+            match (opcode >> 12, opcode & 0xff) {
+                (0xd, _) => self.mark(i, opcode & 0xf, READ),
+                (0xf, 0x33) => self.mark(i, 3, WRITTEN),
+                (0xf, 0x55) => self.mark(i, x + 1, WRITTEN),
+                (0xf, 0x65) => self.mark(i, x + 1, READ),
+                _ => ()
+            }
+        }
+
+        // A summary and the ranges of addresses by usage, given where the ROM was loaded.
+        pub fn get_report(&self, rom_range: &Range<usize>) -> String {
+            let count_in_rom = |flag: u8| self.flags[rom_range.clone()].iter().filter(|flags| *flags & flag != 0).count();
+            let unused = self.flags[rom_range.clone()].iter().filter(|flags| **flags == 0).count();
+            let rom_percentage = |count: usize| {
+                if rom_range.is_empty() { 0.0 } else { count as f64 * 100.0 / rom_range.len() as f64 }
+            };
+
+            let mut lines = vec![
+                format!("Coverage of the ROM ({} bytes at {:04X}-{:04X}):",
+                    rom_range.len(), rom_range.start, rom_range.end.saturating_sub(1)),
+                format!("Executed:     {:>5} bytes ({:.1}%)", count_in_rom(EXECUTED), rom_percentage(count_in_rom(EXECUTED))),
+                format!("Read as data: {:>5} bytes ({:.1}%)", count_in_rom(READ), rom_percentage(count_in_rom(READ))),
+                format!("Written:      {:>5} bytes ({:.1}%)", count_in_rom(WRITTEN), rom_percentage(count_in_rom(WRITTEN))),
+                format!("Never used:   {:>5} bytes ({:.1}%)", unused, rom_percentage(unused)),
+                String::new(),
+                String::from("Ranges of the ROM:")];
+            lines.extend(self.get_ranges(rom_range.clone(), true));
+
+            let outside_rom: Vec<String> = self.get_ranges(0..rom_range.start, false).into_iter()
+                .chain(self.get_ranges(rom_range.end..RAM_SIZE, false))
+                .collect();
+            if !outside_rom.is_empty() {
+                lines.push(String::new());
+                lines.push(String::from("Used outside the ROM:"));
+                lines.extend(outside_rom);
+            }
+            lines.join("\n")
+        }
+
+        // The address space as text, with a character per address:
+        // X executed, d read as data, w written, + more than one of these, - unused ROM and . unused RAM.
+        pub fn get_ascii_map(&self, rom_range: &Range<usize>) -> String {
+            let mut lines = vec![String::from("X=executed d=data w=written +=mixed -=unused ROM .=unused")];
+            for (row, row_flags) in self.flags.chunks(MAP_W).enumerate() {
+                let cells: String = row_flags.iter()
+                    .enumerate()
+                    .map(|(col, flags)| match *flags {
+                        0 if rom_range.contains(&(row * MAP_W + col)) => '-',
+                        0 => '.',
+                        EXECUTED => 'X',
+                        READ => 'd',
+                        WRITTEN => 'w',
+                        _ => '+'
+                    })
+                    .collect();
+                lines.push(format!("{:04X} {cells}", row * MAP_W));
+            }
+            lines.join("\n")
+        }
+
+        // Saves the address space as a PNG image, with a square per address (64 per row) colored like the ASCII map:
+        // executed in green (brighter the more it was executed), data in blue, written in red, mixed in yellow,
+        // unused ROM in gray and unused RAM in dark gray.
+        #[cfg(feature = "desktop")]
+        pub fn save_png(&self, path: &Path, rom_range: &Range<usize>) -> Result<(), String> {
+            let map_h = (RAM_SIZE / MAP_W) as u32;
+            let mut surface = Surface::new(MAP_W as u32 * PNG_CELL_SIZE, map_h * PNG_CELL_SIZE, PixelFormatEnum::RGB24)?;
+
+            // The brightness follows a log scale, so the addresses executed only a few times are still visible.
+            let max_exec_count = self.exec_counts.iter().copied().max().unwrap_or(0).max(1) as f64;
+
+            for (addr, flags) in self.flags.iter().enumerate() {
+                let color = match *flags {
+                    0 if rom_range.contains(&addr) => UNUSED_ROM_COLOR,
+                    0 => UNUSED_COLOR,
+                    EXECUTED => {
+                        let heat = (self.exec_counts[addr] as f64).ln_1p() / max_exec_count.ln_1p();
+                        Color::RGB(0x20, (0x60 as f64 + heat * 0x9f as f64) as u8, 0x20)
+                    },
+                    READ => READ_COLOR,
+                    WRITTEN => WRITTEN_COLOR,
+                    _ => MIXED_COLOR
+                };
+
+                let cell = Rect::new(
+                    ((addr % MAP_W) as u32 * PNG_CELL_SIZE) as i32,
+                    ((addr / MAP_W) as u32 * PNG_CELL_SIZE) as i32,
+                    PNG_CELL_SIZE,
+                    PNG_CELL_SIZE);
+                surface.fill_rect(cell, color)?;
+            }
+
+            surface.save(path)
+        }
+
+        fn mark(&mut self, addr: u16, len: u16, flag: u8) {
+            for offset in 0..len {
+                self.flags[(addr.wrapping_add(offset) & 0xfff) as usize] |= flag;
+            }
+        }
+
+        // Contiguous addresses with the same usage, e.g. "0200-0231 code".
+        fn get_ranges(&self, addrs: Range<usize>, include_unused: bool) -> Vec<String> {
+            let mut ranges = Vec::new();
+            let mut start = addrs.start;
+            for addr in addrs.clone() {
+                let is_last = addr + 1 == addrs.end;
+                if !is_last && self.flags[addr + 1] == self.flags[start] {
+                    continue;
+                }
+
+                if self.flags[start] != 0 || include_unused {
+                    ranges.push(format!("{start:04X}-{addr:04X} {}", Coverage::get_usage_name(self.flags[start])));
+                }
+                start = addr + 1;
+            }
+            ranges
+        }
+
+        fn get_usage_name(flags: u8) -> String {
+            let usages: Vec<&str> = [(EXECUTED, "code"), (READ, "data"), (WRITTEN, "written")].iter()
+                .filter(|(flag, _)| flags & flag != 0)
+                .map(|(_, name)| *name)
+                .collect();
+
+            if usages.is_empty() {
+                String::from("unused")
+            } else {
+                usages.join("+")
+            }
+        }
+    }
+}
@@ -0,0 +1,68 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// Estimated machine cycle costs of the CHIP-8 instructions on the COSMAC VIP, used by the profiler to weigh
+// where a ROM spends its time. They're rough estimates of the interpreter's routines, not counts taken from its listing
+// (e.g. 8xyN has a single cost and Dxyn ignores the shift of unaligned sprites and the clipping),
+// so they rank the hot spots of a ROM rather than tell its speed on the VIP.
+//
+// The fetch/decode overhead is added to every instruction.
+// References the estimates are loosely based on:
+// https://laurencescotford.net/2020/07/25/chip-8-on-the-cosmac-vip-index/
+// https://www.emulator101.com/cosmac-vip-hardware.html
+pub mod cycle_estimates {
+    use crate::util::utilities::BitUtil;
+
+    // Fetching the 2 bytes of the instruction and jumping to its routine.
+    const FETCH_DECODE_CYCLES: u32 = 40;
+
+    // Skipping the next instruction costs a little more than not skipping it.
+    const SKIP_CYCLES: u32 = 4;
+
+    // Returns the estimated cost, in machine cycles, of an instruction that was just executed.
+    // skipped tells if a skip instruction (3xkk, 4xkk, 5xy0, 9xy0, Ex9E and ExA1) skipped the next one,
+    // and vx is the value of the register VX after the execution.
+    pub fn instr_cycles(word: u16, skipped: bool, vx: u8) -> u32 {
+        let first_nibble = (word >> 12) as u8;
+        let x = ((word >> 8) & 0xf) as u8;
+        let fourth_nibble = (word & 0xf) as u8;
+        let second_byte = (word & 0xff) as u8;
+        let skip_cycles = if skipped { SKIP_CYCLES } else { 0 };
+
+        let exec_cycles = match first_nibble {
+            0 => match word {
+                0x00e0 => 24 + 3078,       // CLS: clears the 256 bytes of the display buffer.
+                0x00ee => 10,              // RET
+                _ => 0                     // SYS: not implemented.
+            },
+            1 => 12,                       // JP addr
+            2 => 26,                       // CALL addr
+            3 | 4 => 10 + skip_cycles,     // SE/SNE Vx, byte
+            5 | 9 => 14 + skip_cycles,     // SE/SNE Vx, Vy
+            6 => 6,                        // LD Vx, byte
+            7 => 10,                       // ADD Vx, byte
+            8 => 44,                       // Logical and arithmetic (executed through a generated subroutine).
+            0xa => 12,                     // LD I, addr
+            0xb => 22,                     // JP V0, addr
+            0xc => 36,                     // RND Vx, byte
+            // DRW: every row of the sprite is shifted and XORed into the display buffer.
+            0xd => 26 + fourth_nibble as u32 * 46,
+            0xe => 14 + skip_cycles,       // SKP/SKNP Vx
+            0xf => match second_byte {
+                0x07 | 0x15 | 0x18 => 10,  // LD Vx, DT / LD DT, Vx / LD ST, Vx
+                0x0a => 20,                // LD Vx, K (per polling attempt)
+                0x1e | 0x29 => 16,         // ADD I, Vx / LD F, Vx
+                // LD B, Vx: the digits are calculated by repeated subtraction.
+                0x33 => {
+                    let (hundreds, tens, ones) = BitUtil::decimal_to_8bit_bcd_tuple(vx);
+                    80 + (hundreds + tens + ones) as u32 * 16
+                },
+                0x55 | 0x65 => 14 + (x as u32 + 1) * 14, // LD [I], Vx / LD Vx, [I]
+                _ => 0
+            },
+            _ => 0
+        };
+
+        FETCH_DECODE_CYCLES + exec_cycles
+    }
+}
@@ -0,0 +1,226 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// Support for a local copy of the community CHIP-8 program database:
+// https://github.com/chip-8/chip-8-database
+pub mod chip8_db {
+    use crate::emu::config::settings::EmuSettings;
+    use crate::util::utilities::ColorUtil;
+
+    use sdl2::pixels::Color;
+    use serde_json::{ Map, Value };
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+
+    #[derive(Clone, Copy, PartialEq)]
+    pub enum Platform {
+        Chip8,
+        SuperChip,
+        XoChip,
+        MegaChip,
+        Unknown,
+    }
+
+    impl Platform {
+        // Groups the platform ids used by the database into the platform families.
+        fn from_id(platform_id: &str) -> Self {
+            match platform_id {
+                "originalChip8" | "hybridVIP" | "modernChip8" | "chip8x" => Platform::Chip8,
+                "chip48" | "superchip1" | "superchip" => Platform::SuperChip,
+                "xochip" => Platform::XoChip,
+                "megachip8" => Platform::MegaChip,
+                _ => Platform::Unknown
+            }
+        }
+
+        pub fn get_name(&self) -> &str {
+            match self {
+                Platform::Chip8 => "CHIP-8",
+                Platform::SuperChip => "SCHIP",
+                Platform::XoChip => "XO-CHIP",
+                Platform::MegaChip => "MEGACHIP",
+                Platform::Unknown => "unknown platform"
+            }
+        }
+    }
+
+    // Everything the database knows about a single ROM.
+    pub struct RomInfo {
+        title: String,
+        authors: Vec<String>,
+        platform: Platform,
+        // The quirks are stored using the database's names (e.g. "shift", "memoryLeaveIUnchanged").
+        quirks: HashMap<String, bool>,
+        tickrate: Option<u32>,
+        colors: Option<(Color, Color)>,
+        // Key hints, as (action, emulator key), e.g. ("up", 5).
+        keys: Vec<(String, u8)>,
+    }
+
+    impl RomInfo {
+        // Applies the platform's quirks, tickrate and colors on top of the current settings.
+        pub fn apply_to(&self, emu_settings: &mut EmuSettings) {
+            // "shift": 8XY6 and 8XYE shift VX in place, ignoring VY.
+            if let Some(shift) = self.quirks.get("shift") {
+                emu_settings.set_bit_shift_instructions_use_vy(!shift);
+            }
+
+            // "memoryLeaveIUnchanged": FX55 and FX65 don't change I.
+            if let Some(leave_i_unchanged) = self.quirks.get("memoryLeaveIUnchanged") {
+                emu_settings.set_store_read_instructions_change_i(!leave_i_unchanged);
+            }
+
+            if let Some(tickrate) = self.tickrate {
+                emu_settings.set_cycles_per_frame(tickrate);
+            }
+
+            if let Some((bg_color, pixel_color)) = self.colors {
+                emu_settings.set_bg_color(bg_color);
+                emu_settings.set_pixel_color(pixel_color);
+            }
+        }
+
+        pub fn get_title(&self) -> &str {
+            &self.title
+        }
+
+        pub fn get_authors(&self) -> &[String] {
+            &self.authors
+        }
+
+        pub fn get_platform(&self) -> Platform {
+            self.platform
+        }
+
+        pub fn get_keys(&self) -> &[(String, u8)] {
+            &self.keys
+        }
+
+        // Title and authors, e.g. "Pong (by Paul Vervalin)".
+        pub fn get_description(&self) -> String {
+            if self.authors.is_empty() {
+                self.title.clone()
+            } else {
+                format!("{} (by {})", self.title, self.authors.join(", "))
+            }
+        }
+
+        // Key hints formatted for display, e.g. "up=5, down=8".
+        pub fn get_key_hints(&self) -> String {
+            self.keys.iter()
+                .map(|(action, key)| format!("{action}={key:X}"))
+                .collect::<Vec<String>>()
+                .join(", ")
+        }
+    }
+
+    pub struct Chip8Database {
+        // SHA-1 of the ROM -> index of the program.
+        hashes: HashMap<String, usize>,
+        programs: Vec<Value>,
+        // Default quirks of each platform id.
+        platform_quirks: HashMap<String, Map<String, Value>>,
+    }
+
+    impl Chip8Database {
+        // Loads the database from a folder containing "sha1-hashes.json" and "programs.json"
+        // ("platforms.json" is optional and provides the default quirks of each platform).
+        // Returns None if the database isn't available.
+        pub fn load(folder: &Path) -> Option<Self> {
+            if !folder.is_dir() {
+                return None;
+            }
+
+            let read_json = |file_name: &str| -> Option<Value> {
+                let file_str = fs::read_to_string(folder.join(file_name)).ok()?;
+                match serde_json::from_str(&file_str) {
+                    Ok(json) => Some(json),
+                    Err(err) => {
+                        println!("Invalid CHIP-8 database file '{}': {err}", folder.join(file_name).display());
+                        None
+                    }
+                }
+            };
+
+            let hashes: HashMap<String, usize> = read_json("sha1-hashes.json")?
+                .as_object()?
+                .iter()
+                .filter_map(|(sha1, index)| Some((sha1.to_lowercase(), index.as_u64()? as usize)))
+                .collect();
+
+            let programs = match read_json("programs.json")? {
+                Value::Array(programs) => programs,
+                _ => return None
+            };
+
+            let mut platform_quirks = HashMap::new();
+            if let Some(Value::Array(platforms)) = read_json("platforms.json") {
+                for platform in platforms {
+                    if let (Some(id), Some(quirks)) = (platform["id"].as_str(), platform["quirks"].as_object()) {
+                        platform_quirks.insert(String::from(id), quirks.clone());
+                    }
+                }
+            }
+
+            Some(Self { hashes, programs, platform_quirks, })
+        }
+
+        pub fn find(&self, rom_sha1: &str) -> Option<RomInfo> {
+            let program = self.programs.get(*self.hashes.get(&rom_sha1.to_lowercase())?)?;
+            let rom = &program["roms"][rom_sha1.to_lowercase()];
+
+            let title = program["title"].as_str().unwrap_or("Unknown title");
+            let authors = program["authors"].as_array()
+                .map(|authors| authors.iter().filter_map(|author| author.as_str().map(String::from)).collect())
+                .unwrap_or_default();
+
+            // The first platform listed is the one the ROM was made for.
+            let platform_id = rom["platforms"][0].as_str().unwrap_or("");
+
+            // Default quirks of the platform, overridden by the ROM's specific quirks (if any).
+            let mut quirks: HashMap<String, bool> = self.platform_quirks.get(platform_id)
+                .map(|quirks| quirks.iter().filter_map(|(name, val)| Some((name.clone(), val.as_bool()?))).collect())
+                .unwrap_or_else(|| Chip8Database::default_platform_quirks(platform_id));
+            if let Some(rom_quirks) = rom["quirkyPlatforms"][platform_id].as_object() {
+                for (name, val) in rom_quirks {
+                    if let Some(val) = val.as_bool() {
+                        quirks.insert(name.clone(), val);
+                    }
+                }
+            }
+
+            let colors = rom["colors"]["pixels"].as_array().and_then(|pixels| {
+                Some((
+                    ColorUtil::parse_hex_color(pixels.first()?.as_str()?)?,
+                    ColorUtil::parse_hex_color(pixels.get(1)?.as_str()?)?))
+            });
+
+            let keys = rom["keys"].as_object()
+                .map(|keys| keys.iter().filter_map(|(action, key)| Some((action.clone(), key.as_u64()? as u8))).collect())
+                .unwrap_or_default();
+
+            Some(RomInfo {
+                title: String::from(title),
+                authors,
+                platform: Platform::from_id(platform_id),
+                quirks,
+                tickrate: rom["tickrate"].as_u64().map(|tickrate| tickrate.clamp(1, 99999) as u32),
+                colors,
+                keys,
+            })
+        }
+
+        // The quirks that matter to this emulator, used when "platforms.json" isn't available.
+        fn default_platform_quirks(platform_id: &str) -> HashMap<String, bool> {
+            let (shift, leave_i_unchanged) = match Platform::from_id(platform_id) {
+                Platform::SuperChip => (true, platform_id == "superchip"),
+                _ => (false, false)
+            };
+
+            HashMap::from([
+                (String::from("shift"), shift),
+                (String::from("memoryLeaveIUnchanged"), leave_i_unchanged)])
+        }
+    }
+}
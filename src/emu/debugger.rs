@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// Minimal debugger driven from the terminal, opened on a CPU fault (with the debug fault policy) or with F12.
+// The emulation is stopped while it's open: the window isn't updated until the execution continues.
+pub mod terminal_debugger {
+    use crate::emu::display::screen::ScreenController;
+    use crate::emu::input::keyboard::KeyboardController;
+    use crate::emu::logic::cpu::CpuController;
+    use crate::emu::memory::memory::{ MemoryController, RAM_SIZE, STACK_SIZE };
+
+    use std::io::{ self, BufRead, Write };
+
+    const HELP: &str = "\
+Commands (values are hex, the 0x prefix is optional):
+  regs, r                 Prints the registers.
+  mem, m <ADDR> [LEN]     Prints LEN bytes of the RAM starting at ADDR (default: 0x40 bytes).
+  step, s [N]             Runs N instructions (default: 1), clearing the fault (if any).
+  set <REG> <VAL>         Changes a register (v0 to vf, i, pc, sp, dt or st).
+  screen                  Prints the screen.
+  continue, c             Clears the fault (if any) and continues the emulation.
+  quit, q                 Quits the emulator.
+  help, h                 Prints this message.";
+
+    // Amount of bytes printed by the mem command when the length isn't given.
+    const DEFAULT_MEM_LEN: usize = 0x40;
+
+    // What the emulator must do when the debugger is closed.
+    pub enum DebuggerExit {
+        Continue,
+        Quit,
+    }
+
+    pub struct Debugger;
+
+    impl Debugger {
+        // Reads commands from the terminal until the execution continues or the emulator quits.
+        // If the terminal input is closed, the emulator quits.
+        pub fn run(
+            cpu_ctrl: &mut CpuController,
+            mem_ctrl: &mut MemoryController,
+            screen_ctrl: &mut ScreenController,
+            keyboard_ctrl: &mut KeyboardController) -> DebuggerExit {
+
+            println!("\nDebugger (type 'help' for the commands).");
+
+            let stdin = io::stdin();
+            let mut line = String::new();
+            loop {
+                print!("debug {:04X}> ", mem_ctrl.get_pc());
+                let _ = io::stdout().flush();
+
+                line.clear();
+                match stdin.lock().read_line(&mut line) {
+                    Ok(0) | Err(_) => return DebuggerExit::Quit,
+                    Ok(_) => ()
+                }
+
+                let args: Vec<&str> = line.split_whitespace().collect();
+                let result = match args.as_slice() {
+                    [] => Ok(()),
+                    ["help" | "h"] => {
+                        println!("{HELP}");
+                        Ok(())
+                    },
+                    ["regs" | "r"] => {
+                        println!("{}", mem_ctrl.dump_registers());
+                        Ok(())
+                    },
+                    ["mem" | "m", addr, len @ ..] => Debugger::print_mem(mem_ctrl, addr, len.first()),
+                    ["step" | "s", count @ ..] => Debugger::step(cpu_ctrl, mem_ctrl, screen_ctrl, keyboard_ctrl, count.first()),
+                    ["set", reg, val] => Debugger::set_register(mem_ctrl, reg, val),
+                    ["screen"] => {
+                        print!("{}", screen_ctrl.to_ascii());
+                        Ok(())
+                    },
+                    ["continue" | "c"] => {
+                        cpu_ctrl.clear_fault();
+                        return DebuggerExit::Continue;
+                    },
+                    ["quit" | "q"] => return DebuggerExit::Quit,
+                    _ => Err(format!("unknown command '{}' (type 'help' for the commands)", line.trim()))
+                };
+
+                if let Err(err) = result {
+                    println!("Error: {err}.");
+                }
+            }
+        }
+
+        fn print_mem(mem_ctrl: &MemoryController, addr: &str, len: Option<&&str>) -> Result<(), String> {
+            let addr = Debugger::parse_hex(addr, RAM_SIZE - 1)?;
+            let len = match len {
+                Some(len) => Debugger::parse_hex(len, RAM_SIZE)?,
+                None => DEFAULT_MEM_LEN
+            };
+
+            let ram = mem_ctrl.get_ram();
+            let end = (addr + len).min(RAM_SIZE);
+            for (row, bytes) in ram[addr..end].chunks(16).enumerate() {
+                let bytes: Vec<String> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+                println!("{:04X}: {}", addr + row * 16, bytes.join(" "));
+            }
+            Ok(())
+        }
+
+        fn step(
+            cpu_ctrl: &mut CpuController,
+            mem_ctrl: &mut MemoryController,
+            screen_ctrl: &mut ScreenController,
+            keyboard_ctrl: &mut KeyboardController,
+            count: Option<&&str>) -> Result<(), String> {
+
+            let count = match count {
+                Some(count) => Debugger::parse_hex(count, usize::MAX)?,
+                None => 1
+            };
+
+            cpu_ctrl.clear_fault();
+            for _ in 0..count {
+                cpu_ctrl.fetch_exec(screen_ctrl, mem_ctrl, keyboard_ctrl);
+                if let Some(fault_info) = cpu_ctrl.take_new_fault() {
+                    println!("{}", fault_info.get_report());
+                    return Ok(());
+                }
+            }
+            println!("{}", mem_ctrl.dump_registers());
+            Ok(())
+        }
+
+        fn set_register(mem_ctrl: &mut MemoryController, reg: &str, val: &str) -> Result<(), String> {
+            let reg = reg.to_lowercase();
+            match reg.as_str() {
+                "i" => mem_ctrl.set_i(Debugger::parse_hex(val, 0xffff)? as u16),
+                "pc" => mem_ctrl.set_pc(Debugger::parse_hex(val, RAM_SIZE - 2)? as u16),
+                "sp" => mem_ctrl.set_sp(Debugger::parse_hex(val, STACK_SIZE)? as u8),
+                "dt" => mem_ctrl.set_dt(Debugger::parse_hex(val, 0xff)? as u8),
+                "st" => mem_ctrl.set_st(Debugger::parse_hex(val, 0xff)? as u8),
+                _ => match reg.strip_prefix('v').and_then(|nibble| u8::from_str_radix(nibble, 16).ok()) {
+                    Some(nibble) if nibble <= 0xf => mem_ctrl.set_v(nibble, Debugger::parse_hex(val, 0xff)? as u8),
+                    _ => return Err(format!("unknown register '{reg}'"))
+                }
+            }
+            Ok(())
+        }
+
+        fn parse_hex(val: &str, max: usize) -> Result<usize, String> {
+            let digits = val.strip_prefix("0x").or_else(|| val.strip_prefix("0X")).unwrap_or(val);
+            match usize::from_str_radix(digits, 16) {
+                Ok(parsed_val) if parsed_val <= max => Ok(parsed_val),
+                Ok(_) => Err(format!("{val} is out of range (max={max:#X})")),
+                Err(_) => Err(format!("invalid hex value '{val}'"))
+            }
+        }
+    }
+}
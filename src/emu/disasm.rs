@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// Disassembler for the CHIP-8 instruction set, using the mnemonics of Cowgod's Chip-8 Technical Reference
+// (the same ones listed in the README), e.g. "LD V1, #2A" or "DRW V0, V1, 5".
+// Instructions are decoded exactly like the CPU does, so what is shown is what is executed.
+pub mod disassembler {
+    // Returns the mnemonic of an instruction, or "DW #nnnn" (data word) if it isn't a known instruction.
+    pub fn disassemble(word: u16) -> String {
+        let first_nibble = word >> 12;
+        let x = (word >> 8) & 0xf;
+        let y = (word >> 4) & 0xf;
+        let fourth_nibble = word & 0xf;
+        let byte = word & 0xff;
+        let addr = word & 0xfff;
+
+        match first_nibble {
+            0 => match word {
+                0x00e0 => String::from("CLS"),
+                0x00ee => String::from("RET"),
+                _ => format!("SYS #{addr:03X}")
+            },
+            1 => format!("JP #{addr:03X}"),
+            2 => format!("CALL #{addr:03X}"),
+            3 => format!("SE V{x:X}, #{byte:02X}"),
+            4 => format!("SNE V{x:X}, #{byte:02X}"),
+            5 => format!("SE V{x:X}, V{y:X}"),
+            6 => format!("LD V{x:X}, #{byte:02X}"),
+            7 => format!("ADD V{x:X}, #{byte:02X}"),
+            8 => match fourth_nibble {
+                0 => format!("LD V{x:X}, V{y:X}"),
+                1 => format!("OR V{x:X}, V{y:X}"),
+                2 => format!("AND V{x:X}, V{y:X}"),
+                3 => format!("XOR V{x:X}, V{y:X}"),
+                4 => format!("ADD V{x:X}, V{y:X}"),
+                5 => format!("SUB V{x:X}, V{y:X}"),
+                6 => format!("SHR V{x:X}, V{y:X}"),
+                7 => format!("SUBN V{x:X}, V{y:X}"),
+                0xe => format!("SHL V{x:X}, V{y:X}"),
+                _ => format!("DW #{word:04X}")
+            },
+            9 => format!("SNE V{x:X}, V{y:X}"),
+            0xa => format!("LD I, #{addr:03X}"),
+            0xb => format!("JP V0, #{addr:03X}"),
+            0xc => format!("RND V{x:X}, #{byte:02X}"),
+            0xd => format!("DRW V{x:X}, V{y:X}, {fourth_nibble}"),
+            0xe => match byte {
+                0x9e => format!("SKP V{x:X}"),
+                0xa1 => format!("SKNP V{x:X}"),
+                _ => format!("DW #{word:04X}")
+            },
+            _ => match byte {
+                0x07 => format!("LD V{x:X}, DT"),
+                0x0a => format!("LD V{x:X}, K"),
+                0x15 => format!("LD DT, V{x:X}"),
+                0x18 => format!("LD ST, V{x:X}"),
+                0x1e => format!("ADD I, V{x:X}"),
+                0x29 => format!("LD F, V{x:X}"),
+                0x33 => format!("LD B, V{x:X}"),
+                0x55 => format!("LD [I], V{x:X}"),
+                0x65 => format!("LD V{x:X}, [I]"),
+                _ => format!("DW #{word:04X}")
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+pub mod screen {
+    use crate::emu::memory::memory::MemoryController;
+    use crate::util::utilities::Math2d;
+
+    // The internal video resolution.
+    pub const SCREEN_W: u32 = 64;
+    pub const SCREEN_H: u32 = 32;
+    // Size of the state of a Screen as bytes (see Screen::to_bytes).
+    pub const SCREEN_BYTES: usize = (SCREEN_W * SCREEN_H) as usize;
+
+    #[derive(Clone)]
+    pub struct Screen {
+        // pixels is used to represent the pixels on the screen
+        // for extremely fast collision checking (renderer independent).
+        // Since it's a 1d array and the screen is 2d, it's always indexed with the following formula:
+        // screen width * y + x
+        pixels: [u8;(SCREEN_W * SCREEN_H) as usize],
+    }
+
+    impl Default for Screen {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Screen {
+        pub fn new() -> Self {
+            Self { pixels: [0;(SCREEN_W * SCREEN_H) as usize], }
+        }
+
+        // The pixels as bytes, e.g. for save states kept by a frontend.
+        pub fn to_bytes(&self) -> [u8;SCREEN_BYTES] {
+            self.pixels
+        }
+
+        // Reads the pixels written by to_bytes(), as ON (any value other than 0) or OFF.
+        // Returns None if they have another size.
+        pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+            if bytes.len() != SCREEN_BYTES {
+                return None;
+            }
+
+            let mut screen = Screen::new();
+            for (pixel, byte) in screen.pixels.iter_mut().zip(bytes) {
+                *pixel = (*byte != 0) as u8;
+            }
+            Some(screen)
+        }
+    }
+
+    pub struct ScreenController {
+        screen: Screen,
+    }
+
+    impl ScreenController {
+        pub fn new(screen: Screen) -> Self {
+            Self { screen, }
+        }
+
+        pub fn snapshot(&self) -> Screen {
+            self.screen.clone()
+        }
+
+        pub fn restore(&mut self, screen: &Screen) {
+            self.screen = screen.clone();
+        }
+
+        // put_pixel() is called by the Dxyn instruction.
+        pub fn put_pixel(&mut self, x: u8, y: u8, mem_ctrl: &mut MemoryController) {
+            let corrected_x = Math2d::wrap_coord(x, SCREEN_W);
+            let corrected_y = Math2d::wrap_coord(y, SCREEN_H);
+            let pixel_i = (SCREEN_W * corrected_y as u32 + corrected_x as u32) as usize;
+
+            // Drawing on top of an ON pixel turns it OFF and sets the collision flag.
+            if self.screen.pixels[pixel_i] == 1 && mem_ctrl.get_v(0xf) == 0 {
+                mem_ctrl.set_v(0xf, 1); // vf = collision flag
+            }
+
+            // Flips the current pixel.
+            self.screen.pixels[pixel_i] ^= 1;
+        }
+
+        pub fn clear_screen(&mut self) {
+            self.screen.pixels.fill(0);
+        }
+
+        pub fn get_pixels(&self) -> &[u8] {
+            &self.screen.pixels
+        }
+
+        pub fn is_pixel_on(&self, x: u32, y: u32) -> bool {
+            self.screen.pixels[(SCREEN_W * y + x) as usize] == 1
+        }
+
+        // Renders the screen as text, one line per row ('#' for ON pixels and '.' for OFF pixels).
+        #[cfg(feature = "std")]
+        pub fn to_ascii(&self) -> String {
+            let mut ascii = String::with_capacity(((SCREEN_W + 1) * SCREEN_H) as usize);
+            for y in 0..SCREEN_H {
+                for x in 0..SCREEN_W {
+                    ascii.push(if self.is_pixel_on(x, y) { '#' } else { '.' });
+                }
+                ascii.push('\n');
+            }
+            ascii
+        }
+    }
+}
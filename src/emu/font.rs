@@ -0,0 +1,74 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// Built-in 3x5 bitmap font used by the overlay, so no font files are needed.
+pub mod bitmap_font {
+    pub const GLYPH_W: u32 = 3;
+    pub const GLYPH_H: u32 = 5;
+
+    // Each row of a glyph uses the 3 least significant bits, from left (0b100) to right (0b001).
+    const UNKNOWN_GLYPH: [u8;5] = [0b110, 0b001, 0b010, 0b000, 0b010];
+
+    // Returns the rows of a character's glyph. Lowercase letters use the uppercase glyphs.
+    pub fn get_glyph(c: char) -> [u8;5] {
+        match c.to_ascii_uppercase() {
+            'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+            'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+            'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+            'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+            'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+            'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+            'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+            'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+            'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+            'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+            'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+            'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+            'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+            'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+            'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+            'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+            'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+            'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+            'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+            'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+            'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+            'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+            'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+            'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+            'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+            'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+            '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+            '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+            '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+            '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+            '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+            '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+            '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+            '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+            '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+            '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+            ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+            '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+            ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+            ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+            '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+            '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+            '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+            '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+            ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+            '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+            '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+            '[' => [0b011, 0b010, 0b010, 0b010, 0b011],
+            ']' => [0b110, 0b010, 0b010, 0b010, 0b110],
+            '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+            '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+            '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+            '*' => [0b000, 0b101, 0b010, 0b101, 0b000],
+            '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+            '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+            '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+            _ => UNKNOWN_GLYPH
+        }
+    }
+}
@@ -0,0 +1,258 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+//! Reinforcement learning environment: a headless game with a Gym-style `reset`/`step` loop,
+//! whose reward and end are read from the RAM of the game.
+pub mod gym_env {
+    use crate::emu::api::emulator_api::{ Emulator, EmulatorState };
+    use crate::emu::memory::memory::RAM_SIZE;
+    use crate::error::emu_error::EmuError;
+
+    use rand::rngs::StdRng;
+
+    /// What the agent observes after each step.
+    #[derive(Clone, Copy, PartialEq)]
+    pub enum Observation {
+        /// The screen, as `SCREEN_W * SCREEN_H` (64x32) pixels by rows, where 1 is on and 0 is off.
+        Framebuffer,
+        /// The RAM (4KB).
+        Ram,
+    }
+
+    /// A number the game keeps in its RAM, like a score or the lives left.
+    #[derive(Clone, Copy, PartialEq)]
+    pub enum RamValue {
+        /// A byte.
+        Byte(u16),
+        /// Two bytes (big-endian).
+        Word(u16),
+        /// A decimal number with one digit per byte, starting with the most significant one:
+        /// how Fx33 stores VX (3 digits), which games use to draw their scores.
+        Bcd { addr: u16, digits: u8 },
+    }
+
+    impl RamValue {
+        /// Reads the value (the addresses wrap around the end of the RAM).
+        pub fn read(&self, ram: &[u8;RAM_SIZE]) -> u32 {
+            let byte = |addr: u16| ram[addr as usize % RAM_SIZE] as u32;
+            match *self {
+                RamValue::Byte(addr) => byte(addr),
+                RamValue::Word(addr) => byte(addr) << 8 | byte(addr.wrapping_add(1)),
+                RamValue::Bcd { addr, digits } => (0..digits as u16)
+                    .fold(0, |number, digit| number * 10 + byte(addr.wrapping_add(digit)) % 10)
+            }
+        }
+    }
+
+    /// When a value ends the episode.
+    #[derive(Clone, Copy, PartialEq)]
+    pub enum DoneCondition {
+        Equals(u32),
+        Below(u32),
+        Above(u32),
+    }
+
+    impl DoneCondition {
+        pub fn is_met(&self, val: u32) -> bool {
+            match *self {
+                DoneCondition::Equals(target) => val == target,
+                DoneCondition::Below(target) => val < target,
+                DoneCondition::Above(target) => val > target
+            }
+        }
+    }
+
+    /// Everything that changes while the agent plays: the machine (with its random numbers)
+    /// and where the episode is. Cloning it is a copy of a few KB, e.g. for tree searches.
+    #[derive(Clone)]
+    pub struct EnvState {
+        emulator: EmulatorState,
+        rng: StdRng,
+        reward_values: Vec<u32>,
+        frames: u64,
+        done: bool,
+    }
+
+    /// A CHIP-8 game as a reinforcement learning environment.
+    ///
+    /// The actions are the keys held down during a step, as a bitmask indexed by their hex value
+    /// (bit 5 is the key 5). A step runs `frames_per_step` frames with the keys held, the reward is
+    /// how much the reward rules changed and the episode is done when a done rule is met:
+    /// ```no_run
+    /// use rusted_chip8::{ DoneCondition, Emulator, Env, Observation, RamValue };
+    ///
+    /// let rom = std::fs::read("game.ch8").unwrap();
+    /// let mut env = Env::new(Emulator::new(), &rom, Observation::Framebuffer).unwrap();
+    /// env.add_reward_rule(RamValue::Bcd { addr: 0x300, digits: 3 }, 1.0);   // The score.
+    /// env.add_done_rule(RamValue::Byte(0x310), DoneCondition::Equals(0));   // No lives left.
+    ///
+    /// # fn agent(_observation: &[u8]) -> u16 { 1 << 5 }
+    /// let mut observation = env.reset(42);
+    /// let mut total_reward = 0.0;
+    /// loop {
+    ///     let (next_observation, reward, done) = env.step(agent(&observation));
+    ///     observation = next_observation;
+    ///     total_reward += reward;
+    ///     if done {
+    ///         break;
+    ///     }
+    /// }
+    /// ```
+    pub struct Env {
+        emulator: Emulator,
+        // The machine right after the ROM was loaded, where every episode starts.
+        initial_state: EmulatorState,
+        observation: Observation,
+        frames_per_step: u32,
+        max_frames: Option<u64>,
+        reward_rules: Vec<(RamValue, f32)>,
+        done_rules: Vec<(RamValue, DoneCondition)>,
+        // The values of the reward rules after the last step.
+        reward_values: Vec<u32>,
+        frames: u64,
+        done: bool,
+    }
+
+    impl Env {
+        /// Loads the ROM on a machine with its settings (speed and quirks already set).
+        /// Each step runs 4 frames until `set_frames_per_step()` is called, and there are no rules:
+        /// the reward is always 0 and the episode only ends after `set_max_frames()`.
+        pub fn new(mut emulator: Emulator, rom: &[u8], observation: Observation) -> Result<Self, EmuError> {
+            emulator.load_rom(rom)?;
+            let initial_state = emulator.snapshot();
+            Ok(Self {
+                emulator,
+                initial_state,
+                observation,
+                frames_per_step: 4,
+                max_frames: None,
+                reward_rules: Vec::new(),
+                done_rules: Vec::new(),
+                reward_values: Vec::new(),
+                frames: 0,
+                done: false,
+            })
+        }
+
+        /// Sets how many frames (of 1/60s) the keys of an action are held.
+        pub fn set_frames_per_step(&mut self, frames_per_step: u32) {
+            self.frames_per_step = frames_per_step.max(1);
+        }
+
+        /// Ends the episodes after some frames, whatever the done rules.
+        pub fn set_max_frames(&mut self, max_frames: Option<u64>) {
+            self.max_frames = max_frames;
+        }
+
+        /// Rewards the change of a value in each step, multiplied by `scale`:
+        /// 1.0 for a score, or e.g. 10.0 for the lives, so losing one is a reward of -10.
+        pub fn add_reward_rule(&mut self, value: RamValue, scale: f32) {
+            self.reward_rules.push((value, scale));
+            self.reward_values.push(value.read(self.emulator.ram()));
+        }
+
+        /// Ends the episode when a value meets the condition.
+        pub fn add_done_rule(&mut self, value: RamValue, condition: DoneCondition) {
+            self.done_rules.push((value, condition));
+        }
+
+        /// Starts an episode from the beginning of the game, with the random numbers of a seed.
+        /// Returns the first observation.
+        pub fn reset(&mut self, seed: u64) -> Vec<u8> {
+            self.emulator.restore(&self.initial_state);
+            self.emulator.set_seed(seed);
+            self.emulator.set_keys([false;16]);
+
+            let ram = self.emulator.ram();
+            self.reward_values = self.reward_rules.iter().map(|(value, _)| value.read(ram)).collect();
+            self.frames = 0;
+            self.done = false;
+            self.observe()
+        }
+
+        /// Holds the keys of `action` for a step. Returns the observation, the reward and whether the episode is done.
+        /// The step stops at the frame the episode ends, and once it's done nothing runs until `reset()`.
+        pub fn step(&mut self, action: u16) -> (Vec<u8>, f32, bool) {
+            if self.done {
+                return (self.observe(), 0.0, true);
+            }
+
+            self.emulator.set_keys(core::array::from_fn(|key| action & (1 << key) != 0));
+            for _ in 0..self.frames_per_step {
+                self.emulator.run_frame();
+                self.frames += 1;
+                if self.is_done() {
+                    self.done = true;
+                    break;
+                }
+            }
+
+            let ram = self.emulator.ram();
+            let mut reward = 0.0;
+            for ((value, scale), last_val) in self.reward_rules.iter().zip(self.reward_values.iter_mut()) {
+                let val = value.read(ram);
+                reward += (val as i64 - *last_val as i64) as f32 * scale;
+                *last_val = val;
+            }
+            (self.observe(), reward, self.done)
+        }
+
+        /// A copy of the environment as it is, to go back to it with `restore_state()`.
+        pub fn clone_state(&self) -> EnvState {
+            EnvState {
+                emulator: self.emulator.snapshot(),
+                rng: self.emulator.rng().clone(),
+                reward_values: self.reward_values.clone(),
+                frames: self.frames,
+                done: self.done,
+            }
+        }
+
+        /// Goes back to a state taken by `clone_state()`. The steps after it repeat the same random numbers.
+        pub fn restore_state(&mut self, state: &EnvState) {
+            self.emulator.restore(&state.emulator);
+            self.emulator.set_rng(state.rng.clone());
+            self.reward_values.clone_from(&state.reward_values);
+            self.frames = state.frames;
+            self.done = state.done;
+        }
+
+        /// The current observation.
+        pub fn observe(&self) -> Vec<u8> {
+            match self.observation {
+                Observation::Framebuffer => self.emulator.framebuffer().to_vec(),
+                Observation::Ram => self.emulator.ram().to_vec()
+            }
+        }
+
+        /// The size of the observations (2048 for the framebuffer, 4096 for the RAM).
+        pub fn observation_size(&self) -> usize {
+            match self.observation {
+                Observation::Framebuffer => self.emulator.framebuffer().len(),
+                Observation::Ram => RAM_SIZE
+            }
+        }
+
+        /// How many frames were run since the episode started.
+        pub fn frames(&self) -> u64 {
+            self.frames
+        }
+
+        /// The machine, e.g. to read more of the RAM than the rules do.
+        pub fn emulator(&self) -> &Emulator {
+            &self.emulator
+        }
+
+        fn is_done(&self) -> bool {
+            if self.max_frames.is_some_and(|max_frames| self.frames >= max_frames) {
+                return true;
+            }
+            if self.done_rules.is_empty() {
+                return false;
+            }
+
+            let ram = self.emulator.ram();
+            self.done_rules.iter().any(|(value, condition)| condition.is_met(value.read(ram)))
+        }
+    }
+}
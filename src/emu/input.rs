@@ -2,14 +2,28 @@
 // SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
 
 pub mod keyboard {
+    #[cfg(feature = "desktop")]
+    use crate::emu::config::settings::DEFAULT_KEY_BINDINGS;
+
+    #[cfg(feature = "desktop")]
+    use sdl2::controller::Button;
+    #[cfg(feature = "desktop")]
     use sdl2::event::Event;
+    #[cfg(feature = "desktop")]
     use sdl2::keyboard::Keycode;
+    #[cfg(feature = "desktop")]
     use sdl2::EventPump;
 
     pub struct Keyboard {
         key_arr: [u8;16],
     }
 
+    impl Default for Keyboard {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
     impl Keyboard {
         pub fn new() -> Self {
             Self { key_arr: [0;16], }
@@ -18,19 +32,62 @@ pub mod keyboard {
 
     pub struct KeyboardController {
         keyboard: Keyboard,
+        // The host key bound to each emulator key, indexed by the hex key value (0 to f).
+        #[cfg(feature = "desktop")]
+        key_bindings: [Keycode;16],
     }
 
     impl KeyboardController {
+        // Without SDL, the keys are set by the frontend (see set_keys).
         pub fn new(keyboard: Keyboard) -> Self {
-            Self { keyboard, }
+            Self {
+                keyboard,
+                #[cfg(feature = "desktop")]
+                key_bindings: DEFAULT_KEY_BINDINGS,
+            }
         }
 
+        #[cfg(feature = "desktop")]
+        pub fn with_key_bindings(keyboard: Keyboard, key_bindings: [Keycode;16]) -> Self {
+            Self { keyboard, key_bindings, }
+        }
+
+        #[cfg(feature = "desktop")]
         pub fn check_input_events(&mut self, event_pump: &mut EventPump) -> Option<CustomKeyEvent> {
             self.reset_state();
 
+            let mut custom_key_event = None;
             for event in event_pump.poll_iter() {
                 match event {
                     Event::Quit { .. } => return Some(CustomKeyEvent::Quit),
+                    Event::KeyDown { keycode: Some(Keycode::F2), repeat: false, .. } =>
+                        custom_key_event = Some(CustomKeyEvent::SoftReset),
+                    Event::KeyDown { keycode: Some(Keycode::F3), repeat: false, .. } =>
+                        custom_key_event = Some(CustomKeyEvent::HardReset),
+                    Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } =>
+                        custom_key_event = Some(CustomKeyEvent::SaveProfile),
+                    Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. } =>
+                        custom_key_event = Some(CustomKeyEvent::TogglePause),
+                    Event::KeyDown { keycode: Some(Keycode::F7), .. } =>
+                        custom_key_event = Some(CustomKeyEvent::FrameAdvance),
+                    Event::KeyDown { keycode: Some(Keycode::F8), repeat: false, .. } =>
+                        custom_key_event = Some(CustomKeyEvent::SlowDown),
+                    Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } =>
+                        custom_key_event = Some(CustomKeyEvent::SpeedUp),
+                    Event::KeyDown { keycode: Some(Keycode::F10), repeat: false, .. } =>
+                        custom_key_event = Some(CustomKeyEvent::NormalSpeed),
+                    Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } =>
+                        custom_key_event = Some(CustomKeyEvent::Debug),
+                    Event::KeyDown { keycode: Some(keycode), .. } =>
+                        if let Some(menu_event) = KeyboardController::get_menu_event_by_key(keycode) {
+                            custom_key_event = Some(menu_event);
+                        },
+                    Event::ControllerButtonDown { button, .. } =>
+                        if let Some(menu_event) = KeyboardController::get_menu_event_by_button(button) {
+                            custom_key_event = Some(menu_event);
+                        },
+                    Event::ControllerDeviceAdded { which, .. } =>
+                        custom_key_event = Some(CustomKeyEvent::GamepadAdded(which)),
                     _ => continue
                 }
             }
@@ -41,32 +98,17 @@ pub mod keyboard {
                 .collect();
 
             for pressed_key in pressed_keys {
-                match pressed_key {
-                    Keycode::Num1 => self.set_key_down_by_index(1),
-                    Keycode::Num2 => self.set_key_down_by_index(2),
-                    Keycode::Num3 => self.set_key_down_by_index(3),
-                    Keycode::Num4 => self.set_key_down_by_index(0xc),
-                    Keycode::Q =>    self.set_key_down_by_index(4),
-                    Keycode::W =>    self.set_key_down_by_index(5),
-                    Keycode::E =>    self.set_key_down_by_index(6),
-                    Keycode::R =>    self.set_key_down_by_index(0xd),
-                    Keycode::A =>    self.set_key_down_by_index(7),
-                    Keycode::S =>    self.set_key_down_by_index(8),
-                    Keycode::D =>    self.set_key_down_by_index(9),
-                    Keycode::F =>    self.set_key_down_by_index(0xe),
-                    Keycode::Z =>    self.set_key_down_by_index(0xa),
-                    Keycode::X =>    self.set_key_down_by_index(0),
-                    Keycode::C =>    self.set_key_down_by_index(0xb),
-                    Keycode::V =>    self.set_key_down_by_index(0xf),
-                    _ => continue
+                if let Some(key_index) = self.key_bindings.iter().position(|key| *key == pressed_key) {
+                    self.set_key_down_by_index(key_index);
                 }
             }
-            
-            None
+
+            custom_key_event
         }
 
+        // Like the VIP, only the low nibble of the value selects the key, so any VX is a valid key.
         pub fn is_key_x_pressed(&mut self, key_index: u8) -> bool {
-            self.keyboard.key_arr[key_index as usize] == 1
+            self.keyboard.key_arr[(key_index & 0xf) as usize] == 1
         }
 
         pub fn get_any_key_down(&self) -> Option<u8> {
@@ -82,6 +124,49 @@ pub mod keyboard {
             self.keyboard.key_arr[key_index] = 1;
         }
 
+        // The state of all the keys, indexed by the hex key value (0 to f).
+        pub fn get_keys(&self) -> [bool;16] {
+            self.keyboard.key_arr.map(|key| key == 1)
+        }
+
+        // Sets the state of all the keys at once, indexed by the hex key value (0 to f), e.g. from a frontend without SDL.
+        pub fn set_keys(&mut self, keys: [bool;16]) {
+            for (key, is_down) in self.keyboard.key_arr.iter_mut().zip(keys) {
+                *key = is_down as u8;
+            }
+        }
+
+        // The overlay menu is driven by the arrow keys, Enter, Backspace and Esc.
+        #[cfg(feature = "desktop")]
+        fn get_menu_event_by_key(keycode: Keycode) -> Option<CustomKeyEvent> {
+            match keycode {
+                Keycode::Escape => Some(CustomKeyEvent::ToggleMenu),
+                Keycode::Up => Some(CustomKeyEvent::MenuUp),
+                Keycode::Down => Some(CustomKeyEvent::MenuDown),
+                Keycode::Left => Some(CustomKeyEvent::MenuLeft),
+                Keycode::Right => Some(CustomKeyEvent::MenuRight),
+                Keycode::Return | Keycode::KpEnter => Some(CustomKeyEvent::MenuSelect),
+                Keycode::Backspace => Some(CustomKeyEvent::MenuBack),
+                _ => None
+            }
+        }
+
+        // Or by the gamepad's Start button, D-pad, A and B.
+        #[cfg(feature = "desktop")]
+        fn get_menu_event_by_button(button: Button) -> Option<CustomKeyEvent> {
+            match button {
+                Button::Start => Some(CustomKeyEvent::ToggleMenu),
+                Button::DPadUp => Some(CustomKeyEvent::MenuUp),
+                Button::DPadDown => Some(CustomKeyEvent::MenuDown),
+                Button::DPadLeft => Some(CustomKeyEvent::MenuLeft),
+                Button::DPadRight => Some(CustomKeyEvent::MenuRight),
+                Button::A => Some(CustomKeyEvent::MenuSelect),
+                Button::B => Some(CustomKeyEvent::MenuBack),
+                _ => None
+            }
+        }
+
+        #[cfg(feature = "desktop")]
         fn reset_state(&mut self) {
             self.keyboard.key_arr = [0;16];
         }
@@ -89,5 +174,32 @@ pub mod keyboard {
 
     pub enum CustomKeyEvent {
         Quit,
+        // F2: restarts the ROM, keeping the RAM.
+        SoftReset,
+        // F3: restarts the ROM, reading it again from its file.
+        HardReset,
+        // F5: stores the current speed and quirk settings in the ROM's profile.
+        SaveProfile,
+        // F6: pauses or resumes the emulation.
+        TogglePause,
+        // F7: while paused, runs a single 60hz frame (holding the key repeats it).
+        FrameAdvance,
+        // F8 and F9: steps the speed through 0.25x, 0.5x, 1x, 2x, 4x and unlimited.
+        SlowDown,
+        SpeedUp,
+        // F10: back to 1x.
+        NormalSpeed,
+        // F12: stops the emulation and opens the debugger in the terminal.
+        Debug,
+        // Navigation of the overlay menu (keyboard or gamepad).
+        ToggleMenu,
+        MenuUp,
+        MenuDown,
+        MenuLeft,
+        MenuRight,
+        MenuSelect,
+        MenuBack,
+        // A gamepad was connected (with its joystick index), it must be opened to send events.
+        GamepadAdded(u32),
     }
 }
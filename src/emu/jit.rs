@@ -0,0 +1,800 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+#[cfg(not(all(target_arch = "x86_64", unix)))]
+compile_error!("the \"jit\" feature generates x86-64 code for Unix systems");
+
+// Dynamic recompiler: translates blocks of CHIP-8 instructions into x86-64 code.
+// A block is a run of instructions that only use the registers and timers (6xkk, 7xkk, 8xyN, Annn, Fx07, Fx15, Fx18,
+// Fx1E and Fx29), ending at a jump (1nnn, Bnnn) or a skip (3xkk, 4xkk, 5xy0, 9xy0), or right before an instruction
+// it can't compile (calls, returns, Dxyn, Cxkk, the keys and the RAM accesses), which the interpreter executes.
+pub mod jit_compiler {
+    use crate::emu::backend::backends::RandomSource;
+    use crate::emu::display::screen::ScreenController;
+    use crate::emu::input::keyboard::KeyboardController;
+    use crate::emu::logic::cpu::{ CpuController, FaultPolicy, JitMode };
+    use crate::emu::memory::memory::{ CodeVersion, Memory, MemoryController, RAM_SIZE };
+    use crate::error::emu_error::EmuError;
+
+    use std::io;
+    use std::ptr;
+
+    // The longest block, in instructions.
+    const MAX_BLOCK_INSTRS: usize = 64;
+    // Size of the executable memory. When it's full, every block is compiled again.
+    const CODE_SIZE: usize = 1 << 20;
+
+    // Where each register is in JitRegs, which the compiled code gets in rdi.
+    const VF_OFFSET: u8 = 15;
+    const I_OFFSET: u8 = 16;
+    const PC_OFFSET: u8 = 18;
+    const DT_OFFSET: u8 = 20;
+    const ST_OFFSET: u8 = 21;
+
+    // The registers the compiled code works on, copied from and back to the MemoryController
+    // around the instructions run by the interpreter.
+    #[repr(C)]
+    struct JitRegs {
+        v: [u8;16],
+        i: u16,
+        pc: u16,
+        dt: u8,
+        st: u8,
+    }
+
+    impl JitRegs {
+        fn load(mem_ctrl: &MemoryController) -> Self {
+            Self {
+                v: mem_ctrl.get_v_regs(),
+                i: mem_ctrl.get_i(),
+                pc: mem_ctrl.get_pc(),
+                dt: mem_ctrl.get_dt(),
+                st: mem_ctrl.get_st(),
+            }
+        }
+
+        fn store(&self, mem_ctrl: &mut MemoryController) {
+            mem_ctrl.set_v_regs(self.v);
+            mem_ctrl.set_i(self.i);
+            mem_ctrl.set_pc(self.pc);
+            mem_ctrl.set_dt(self.dt);
+            mem_ctrl.set_st(self.st);
+        }
+    }
+
+    struct Block {
+        // Where the code starts in the executable memory.
+        code_offset: usize,
+        // 0 if the first instruction can't be compiled, so it's left to the interpreter.
+        instrs: usize,
+        // The most instructions it could hold when it was compiled.
+        max_instrs: usize,
+        // The instructions it was compiled from, to check whether they changed after a write to decoded instructions.
+        bytes: Vec<u8>,
+        // The CodeVersion of the RAM when the instructions were last known to be the same.
+        code_version: CodeVersion,
+    }
+
+    pub struct Jit {
+        mode: JitMode,
+        code: ExecMemory,
+        // The blocks starting at each address: the longest one, which fits in a frame, and the shorter ones
+        // that fit in what's left of a frame when it's entered near the end.
+        blocks: Vec<Vec<Block>>,
+        // The quirk the blocks were compiled with (they're compiled again when it changes).
+        bit_shift_instructions_use_vy: bool,
+        // The most instructions of the longest blocks (they're compiled again when cycles_per_frame changes it).
+        max_block_instrs: usize,
+        // The machine the interpreter runs on in lockstep mode. It has its own CPU, so nothing the interpreter
+        // decodes there (nor its state) is shared with the CPU running the game (boxed, since a CPU holds its Jit).
+        lockstep_cpu: Box<CpuController>,
+        lockstep_mem: MemoryController,
+        checked_blocks: u64,
+        mismatches: u64,
+        first_mismatch: Option<String>,
+    }
+
+    impl Jit {
+        pub fn new(mode: JitMode) -> Result<Self, EmuError> {
+            let lockstep_mem = MemoryController::new(Memory::new());
+            // The compiled instructions don't use the random numbers, so the seed doesn't matter.
+            let lockstep_cpu = CpuController::new(&lockstep_mem, 1, false, true, FaultPolicy::Halt, Some(0));
+            Ok(Self {
+                mode,
+                code: ExecMemory::new().map_err(EmuError::Jit)?,
+                blocks: (0..RAM_SIZE).map(|_| Vec::new()).collect(),
+                bit_shift_instructions_use_vy: false,
+                max_block_instrs: MAX_BLOCK_INSTRS,
+                lockstep_cpu: Box::new(lockstep_cpu),
+                lockstep_mem,
+                checked_blocks: 0,
+                mismatches: 0,
+                first_mismatch: None,
+            })
+        }
+
+        pub fn get_mode(&self) -> JitMode {
+            self.mode
+        }
+
+        // Runs a frame (cycles_per_frame instructions), like CpuController::run_frame.
+        pub fn run_frame<R: RandomSource>(
+            &mut self,
+            cpu_ctrl: &mut CpuController<R>,
+            screen_ctrl: &mut ScreenController,
+            mem_ctrl: &mut MemoryController,
+            keyboard_ctrl: &mut KeyboardController) -> u64 {
+
+            if cpu_ctrl.get_bit_shift_instructions_use_vy() != self.bit_shift_instructions_use_vy {
+                self.bit_shift_instructions_use_vy = cpu_ctrl.get_bit_shift_instructions_use_vy();
+                self.flush();
+            }
+
+            let cycles_per_frame = cpu_ctrl.get_cycles_per_frame() as u64;
+            let max_block_instrs = MAX_BLOCK_INSTRS.min(cycles_per_frame as usize);
+            if max_block_instrs != self.max_block_instrs {
+                self.max_block_instrs = max_block_instrs;
+                self.flush();
+            }
+
+            let mut instructions: u64 = 0;
+            let mut regs = JitRegs::load(mem_ctrl);
+
+            while instructions < cycles_per_frame && cpu_ctrl.get_fault().is_none() {
+                let budget = (cycles_per_frame - instructions) as usize;
+                if let Some((code_offset, block_instrs)) = self.get_block(cpu_ctrl, mem_ctrl, regs.pc, budget) {
+                    if block_instrs > 0 {
+                        match self.mode {
+                            // Safety: the offset is where get_block() placed the code of the block.
+                            JitMode::Native => unsafe { self.code.call(code_offset, &mut regs) },
+                            JitMode::Lockstep => self.run_lockstep(
+                                cpu_ctrl, screen_ctrl, mem_ctrl, keyboard_ctrl, code_offset, block_instrs, &mut regs)
+                        }
+                        instructions += block_instrs as u64;
+                        continue;
+                    }
+                }
+
+                regs.store(mem_ctrl);
+                cpu_ctrl.fetch_exec(screen_ctrl, mem_ctrl, keyboard_ctrl);
+                regs = JitRegs::load(mem_ctrl);
+                instructions += 1;
+            }
+
+            regs.store(mem_ctrl);
+            instructions
+        }
+
+        // How the blocks checked in lockstep mode went, formatted for display (if the mode is lockstep).
+        pub fn get_lockstep_report(&self) -> Option<String> {
+            if self.mode != JitMode::Lockstep {
+                return None;
+            }
+
+            let mut report = format!("JIT lockstep: {} blocks checked, {} mismatches", self.checked_blocks, self.mismatches);
+            if let Some(first_mismatch) = &self.first_mismatch {
+                report += &format!("\nFirst mismatch: {first_mismatch}");
+            }
+            Some(report)
+        }
+
+        // Runs the block as native code and with the interpreter from the same state, keeping the interpreter's result.
+        #[allow(clippy::too_many_arguments)]
+        fn run_lockstep<R: RandomSource>(
+            &mut self,
+            cpu_ctrl: &CpuController<R>,
+            screen_ctrl: &mut ScreenController,
+            mem_ctrl: &mut MemoryController,
+            keyboard_ctrl: &mut KeyboardController,
+            code_offset: usize,
+            block_instrs: usize,
+            regs: &mut JitRegs) {
+
+            let block_pc = regs.pc;
+            regs.store(mem_ctrl);
+            self.lockstep_mem.restore(&mem_ctrl.snapshot());
+            self.lockstep_cpu.reset();
+            self.lockstep_cpu.set_bit_shift_instructions_use_vy(cpu_ctrl.get_bit_shift_instructions_use_vy());
+            self.lockstep_cpu.set_store_read_instructions_change_i(cpu_ctrl.get_store_read_instructions_change_i());
+
+            // Safety: the offset is where get_block() placed the code of the block.
+            unsafe { self.code.call(code_offset, regs) };
+            regs.store(mem_ctrl);
+
+            // The compiled instructions don't draw nor read the keys, so the screen and the keyboard stay the same.
+            for _ in 0..block_instrs {
+                self.lockstep_cpu.fetch_exec(screen_ctrl, &mut self.lockstep_mem, keyboard_ctrl);
+            }
+
+            self.checked_blocks += 1;
+            let interpreter_state = self.lockstep_mem.snapshot();
+            if mem_ctrl.snapshot().to_bytes() != interpreter_state.to_bytes() {
+                self.mismatches += 1;
+                self.first_mismatch.get_or_insert_with(|| format!(
+                    "block at {block_pc:#05X} ({block_instrs} instructions)\nJIT:\n{}\nInterpreter:\n{}",
+                    mem_ctrl.dump_registers(),
+                    self.lockstep_mem.dump_registers()));
+                mem_ctrl.restore(&interpreter_state);
+                *regs = JitRegs::load(mem_ctrl);
+            }
+        }
+
+        // The longest block starting at the address that runs at most budget instructions (compiling it if needed),
+        // as its code offset and instructions. None where the interpreter must handle the PC
+        // (past the last instruction that fits in the RAM).
+        fn get_block<R: RandomSource>(
+            &mut self,
+            cpu_ctrl: &mut CpuController<R>,
+            mem_ctrl: &mut MemoryController,
+            pc: u16,
+            budget: usize) -> Option<(usize, usize)> {
+
+            let addr = pc as usize;
+            if addr > RAM_SIZE - 2 {
+                return None;
+            }
+
+            // Decoded instructions were written over: the blocks are kept if their own instructions are the same
+            // (they're decoded again, so later writes over them are noticed).
+            let code_version = mem_ctrl.get_code_version();
+            self.blocks[addr].retain_mut(|block| {
+                if block.code_version == code_version {
+                    return true;
+                }
+                if mem_ctrl.get_ram()[addr..addr + block.bytes.len()] != block.bytes[..] {
+                    return false;
+                }
+                for instr_addr in (addr..addr + block.bytes.len()).step_by(2) {
+                    cpu_ctrl.decode_instr_at(mem_ctrl, instr_addr);
+                }
+                block.code_version = code_version;
+                true
+            });
+
+            let longest = self.get_compiled_block(cpu_ctrl, mem_ctrl, addr, self.max_block_instrs);
+            if longest.1 <= budget {
+                return Some(longest);
+            }
+            // Near the end of the frame, a shorter block runs the instructions left (the longest one would run too many).
+            Some(self.get_compiled_block(cpu_ctrl, mem_ctrl, addr, budget))
+        }
+
+        // The block starting at the address compiled with at most max_instrs instructions, compiling it if needed.
+        fn get_compiled_block<R: RandomSource>(
+            &mut self,
+            cpu_ctrl: &mut CpuController<R>,
+            mem_ctrl: &mut MemoryController,
+            addr: usize,
+            max_instrs: usize) -> (usize, usize) {
+
+            if let Some(block) = self.blocks[addr].iter().find(|block| block.max_instrs == max_instrs) {
+                return (block.code_offset, block.instrs);
+            }
+
+            let block = self.compile(cpu_ctrl, mem_ctrl, addr, max_instrs);
+            let compiled = (block.code_offset, block.instrs);
+            self.blocks[addr].push(block);
+            compiled
+        }
+
+        fn compile<R: RandomSource>(
+            &mut self,
+            cpu_ctrl: &mut CpuController<R>,
+            mem_ctrl: &mut MemoryController,
+            start_addr: usize,
+            max_instrs: usize) -> Block {
+
+            let mut emitter = X64Emitter::new();
+            let mut addr = start_addr;
+            let mut instrs = 0;
+
+            loop {
+                // Both bytes of the instruction must be inside the RAM, otherwise the interpreter raises the fault.
+                if instrs == max_instrs || addr > RAM_SIZE - 2 {
+                    emitter.set_pc(addr as u16);
+                    break;
+                }
+
+                // Decoding it through the CPU marks it as code, so writes over it are noticed.
+                let opcode = cpu_ctrl.decode_instr_at(mem_ctrl, addr);
+                match emitter.emit_instr(opcode, addr as u16, self.bit_shift_instructions_use_vy) {
+                    Emitted::Next => {
+                        instrs += 1;
+                        addr += 2;
+                    },
+                    Emitted::BlockEnd => {
+                        instrs += 1;
+                        addr += 2;
+                        break;
+                    },
+                    Emitted::NotCompiled => {
+                        emitter.set_pc(addr as u16);
+                        break;
+                    }
+                }
+            }
+            emitter.ret();
+
+            // The first instruction is checked for changes even when the interpreter executes it.
+            let bytes_end = if instrs == 0 { start_addr + 2 } else { addr };
+            let mut block = Block {
+                code_offset: 0,
+                instrs: 0,
+                max_instrs,
+                bytes: mem_ctrl.get_ram()[start_addr..bytes_end].to_vec(),
+                code_version: mem_ctrl.get_code_version(),
+            };
+            if instrs == 0 {
+                return block;
+            }
+
+            let code_offset = match self.code.push(emitter.get_code()) {
+                Ok(Some(code_offset)) => Some(code_offset),
+                Ok(None) => {
+                    self.flush();
+                    self.code.push(emitter.get_code()).ok().flatten()
+                },
+                Err(_) => None
+            };
+            // Without room for the code, the interpreter executes the instructions.
+            if let Some(code_offset) = code_offset {
+                block.code_offset = code_offset;
+                block.instrs = instrs;
+            }
+            block
+        }
+
+        // Forgets every block and its code.
+        fn flush(&mut self) {
+            self.blocks.iter_mut().for_each(Vec::clear);
+            self.code.clear();
+        }
+    }
+
+    // How an instruction was compiled.
+    enum Emitted {
+        Next,
+        // It sets the PC, so the block ends with it.
+        BlockEnd,
+        // It's left to the interpreter, so the block ends before it.
+        NotCompiled,
+    }
+
+    // Writes x86-64 code working on JitRegs, pointed to by rdi, using only rax, rcx and rdx (scratch registers).
+    struct X64Emitter {
+        code: Vec<u8>,
+    }
+
+    // The registers of the x86-64 instructions.
+    const EAX: u8 = 0;
+    const ECX: u8 = 1;
+    const EDX: u8 = 2;
+
+    impl X64Emitter {
+        fn new() -> Self {
+            Self { code: Vec::new() }
+        }
+
+        fn get_code(&self) -> &[u8] {
+            &self.code
+        }
+
+        fn emit_instr(&mut self, opcode: u16, addr: u16, bit_shift_instructions_use_vy: bool) -> Emitted {
+            let x = (opcode >> 8 & 0xf) as u8;
+            let y = (opcode >> 4 & 0xf) as u8;
+            let kk = (opcode & 0xff) as u8;
+            let nnn = opcode & 0xfff;
+            let next_pc = addr.wrapping_add(2);
+
+            match (opcode >> 12, opcode & 0xf) {
+                // 1nnn - JP addr
+                (1, _) => {
+                    self.set_pc(nnn);
+                    return Emitted::BlockEnd;
+                },
+                // 3xkk - SE Vx, byte / 4xkk - SNE Vx, byte
+                (3 | 4, _) => {
+                    self.emit(&[0x80, X64Emitter::modrm_rdi(7), x, kk]); // cmp byte [rdi+x], kk
+                    self.skip_if(opcode >> 12 == 3, next_pc);
+                    return Emitted::BlockEnd;
+                },
+                // 5xy0 - SE Vx, Vy / 9xy0 - SNE Vx, Vy
+                (5 | 9, _) => {
+                    self.load_byte(EAX, x);
+                    self.emit(&[0x3a, X64Emitter::modrm_rdi(EAX), y]); // cmp al, [rdi+y]
+                    self.skip_if(opcode >> 12 == 5, next_pc);
+                    return Emitted::BlockEnd;
+                },
+                // 6xkk - LD Vx, byte
+                (6, _) => self.emit(&[0xc6, X64Emitter::modrm_rdi(0), x, kk]),
+                // 7xkk - ADD Vx, byte
+                (7, _) => self.emit(&[0x80, X64Emitter::modrm_rdi(0), x, kk]),
+                // 8xy0 - LD Vx, Vy
+                (8, 0) => {
+                    self.load_byte(EAX, y);
+                    self.store_byte(EAX, x);
+                },
+                // 8xy1 - OR Vx, Vy / 8xy2 - AND Vx, Vy / 8xy3 - XOR Vx, Vy
+                (8, op @ 1..=3) => {
+                    self.load_byte(EAX, x);
+                    let alu_opcode = match op {
+                        1 => 0x0a,
+                        2 => 0x22,
+                        _ => 0x32
+                    };
+                    self.emit(&[alu_opcode, X64Emitter::modrm_rdi(EAX), y]); // or/and/xor al, [rdi+y]
+                    self.store_byte(EAX, x);
+                },
+                // 8xy4 - ADD Vx, Vy
+                (8, 4) => {
+                    self.load_byte(EAX, x);
+                    self.load_byte(ECX, y);
+                    self.emit(&[0x01, 0xc8]);       // add eax, ecx
+                    self.emit(&[0x89, 0xc2]);       // mov edx, eax
+                    self.emit(&[0xc1, 0xea, 0x08]); // shr edx, 8 (the carry)
+                    self.store_byte(EDX, VF_OFFSET);
+                    self.store_byte(EAX, x);
+                },
+                // 8xy5 - SUB Vx, Vy
+                (8, 5) => {
+                    self.load_byte(EAX, x);
+                    self.load_byte(ECX, y);
+                    self.emit(&[0x38, 0xc8]);       // cmp al, cl
+                    self.emit(&[0x0f, 0x97, 0xc2]); // seta dl (NOT borrow)
+                    self.emit(&[0x28, 0xc8]);       // sub al, cl
+                    self.store_byte(EDX, VF_OFFSET);
+                    self.store_byte(EAX, x);
+                },
+                // 8xy6 - SHR Vx {, Vy}
+                (8, 6) => {
+                    self.load_byte(EAX, if bit_shift_instructions_use_vy { y } else { x });
+                    self.emit(&[0x89, 0xc1]);       // mov ecx, eax
+                    self.emit(&[0x80, 0xe1, 0x01]); // and cl, 1 (the least significant bit)
+                    self.emit(&[0xd0, 0xe8]);       // shr al, 1
+                    self.store_byte(ECX, VF_OFFSET);
+                    self.store_byte(EAX, x);
+                },
+                // 8xy7 - SUBN Vx, Vy
+                (8, 7) => {
+                    self.load_byte(EAX, x);
+                    self.load_byte(ECX, y);
+                    self.emit(&[0x38, 0xc1]);       // cmp cl, al
+                    self.emit(&[0x0f, 0x97, 0xc2]); // seta dl (NOT borrow)
+                    self.emit(&[0x28, 0xc1]);       // sub cl, al
+                    self.store_byte(EDX, VF_OFFSET);
+                    self.store_byte(ECX, x);
+                },
+                // 8xyE - SHL Vx {, Vy}
+                (8, 0xe) => {
+                    self.load_byte(EAX, if bit_shift_instructions_use_vy { y } else { x });
+                    self.emit(&[0x89, 0xc1]);       // mov ecx, eax
+                    self.emit(&[0xc0, 0xe9, 0x07]); // shr cl, 7 (the most significant bit)
+                    self.emit(&[0xd0, 0xe0]);       // shl al, 1
+                    self.store_byte(ECX, VF_OFFSET);
+                    self.store_byte(EAX, x);
+                },
+                // Annn - LD I, addr
+                (0xa, _) => self.store_word_imm(I_OFFSET, nnn),
+                // Bnnn - JP V0, addr
+                (0xb, _) => {
+                    self.load_byte(EAX, 0);
+                    self.emit(&[0x05]);             // add eax, nnn
+                    self.emit(&(nnn as u32).to_le_bytes());
+                    self.store_word(EAX, PC_OFFSET);
+                    return Emitted::BlockEnd;
+                },
+                (0xf, _) => match kk {
+                    // Fx07 - LD Vx, DT
+                    0x07 => {
+                        self.load_byte(EAX, DT_OFFSET);
+                        self.store_byte(EAX, x);
+                    },
+                    // Fx15 - LD DT, Vx
+                    0x15 => {
+                        self.load_byte(EAX, x);
+                        self.store_byte(EAX, DT_OFFSET);
+                    },
+                    // Fx18 - LD ST, Vx
+                    0x18 => {
+                        self.load_byte(EAX, x);
+                        self.store_byte(EAX, ST_OFFSET);
+                    },
+                    // Fx1E - ADD I, Vx
+                    0x1e => {
+                        self.load_byte(EAX, x);
+                        self.emit(&[0x66, 0x01, X64Emitter::modrm_rdi(EAX), I_OFFSET]); // add [rdi+I], ax
+                    },
+                    // Fx29 - LD F, Vx
+                    0x29 => {
+                        self.load_byte(EAX, x);
+                        self.emit(&[0x8d, 0x04, 0x80]); // lea eax, [rax+rax*4]
+                        self.store_word(EAX, I_OFFSET);
+                    },
+                    _ => return Emitted::NotCompiled
+                },
+                _ => return Emitted::NotCompiled
+            }
+            Emitted::Next
+        }
+
+        // Sets the PC to the next instruction, or to the one after it if the comparison just made
+        // found the values equal (equal = true) or different (equal = false).
+        fn skip_if(&mut self, equal: bool, next_pc: u16) {
+            self.set_pc(next_pc);
+            // jne/je over the next mov (6 bytes), to the end of the block.
+            self.emit(&[if equal { 0x75 } else { 0x74 }, 0x06]);
+            self.set_pc(next_pc.wrapping_add(2));
+        }
+
+        fn set_pc(&mut self, pc: u16) {
+            self.store_word_imm(PC_OFFSET, pc);
+        }
+
+        fn ret(&mut self) {
+            self.emit(&[0xc3]);
+        }
+
+        // movzx reg, byte [rdi+offset]
+        fn load_byte(&mut self, reg: u8, offset: u8) {
+            self.emit(&[0x0f, 0xb6, X64Emitter::modrm_rdi(reg), offset]);
+        }
+
+        // mov [rdi+offset], reg (8 bits)
+        fn store_byte(&mut self, reg: u8, offset: u8) {
+            self.emit(&[0x88, X64Emitter::modrm_rdi(reg), offset]);
+        }
+
+        // mov [rdi+offset], reg (16 bits)
+        fn store_word(&mut self, reg: u8, offset: u8) {
+            self.emit(&[0x66, 0x89, X64Emitter::modrm_rdi(reg), offset]);
+        }
+
+        // mov word [rdi+offset], val
+        fn store_word_imm(&mut self, offset: u8, val: u16) {
+            self.emit(&[0x66, 0xc7, X64Emitter::modrm_rdi(0), offset]);
+            self.emit(&val.to_le_bytes());
+        }
+
+        // The ModRM byte of [rdi+disp8] with the register (or the opcode extension).
+        fn modrm_rdi(reg: u8) -> u8 {
+            0b01_000_111 | reg << 3
+        }
+
+        fn emit(&mut self, bytes: &[u8]) {
+            self.code.extend_from_slice(bytes);
+        }
+    }
+
+    // Memory for the compiled code, writable only while code is added to it (W^X).
+    struct ExecMemory {
+        ptr: *mut u8,
+        used: usize,
+    }
+
+    // The mapping is owned by the ExecMemory, like a Box.
+    unsafe impl Send for ExecMemory {}
+
+    impl ExecMemory {
+        fn new() -> io::Result<Self> {
+            // Safety: a new private mapping, not aliasing anything.
+            let ptr = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    CODE_SIZE,
+                    libc::PROT_READ | libc::PROT_EXEC,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                    -1,
+                    0)
+            };
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self { ptr: ptr as *mut u8, used: 0 })
+        }
+
+        // Adds the code, returning its offset, or None if there's no room left.
+        fn push(&mut self, code: &[u8]) -> io::Result<Option<usize>> {
+            if self.used + code.len() > CODE_SIZE {
+                return Ok(None);
+            }
+
+            let offset = self.used;
+            self.protect(libc::PROT_READ | libc::PROT_WRITE)?;
+            // Safety: the code fits in the mapping, which is writable until it's protected again.
+            unsafe { ptr::copy_nonoverlapping(code.as_ptr(), self.ptr.add(offset), code.len()) };
+            self.protect(libc::PROT_READ | libc::PROT_EXEC)?;
+            self.used += code.len();
+            Ok(Some(offset))
+        }
+
+        fn clear(&mut self) {
+            self.used = 0;
+        }
+
+        fn protect(&self, prot: libc::c_int) -> io::Result<()> {
+            // Safety: the whole mapping, which is only accessed through this ExecMemory.
+            if unsafe { libc::mprotect(self.ptr as *mut libc::c_void, CODE_SIZE, prot) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        // Runs the code at the offset on the registers.
+        // Safety: the offset must be one returned by push() since the last clear().
+        unsafe fn call(&self, offset: usize, regs: &mut JitRegs) {
+            let code: extern "sysv64" fn(*mut JitRegs) = unsafe { std::mem::transmute::<*mut u8, extern "sysv64" fn(*mut JitRegs)>(self.ptr.add(offset)) };
+            code(regs);
+        }
+    }
+
+    impl Drop for ExecMemory {
+        fn drop(&mut self) {
+            // Safety: the mapping made by new(), not used after this.
+            unsafe { libc::munmap(self.ptr as *mut libc::c_void, CODE_SIZE) };
+        }
+    }
+
+    #[cfg(all(test, feature = "jit"))]
+    mod tests {
+        use super::Jit;
+        use crate::emu::display::screen::{ Screen, ScreenController };
+        use crate::emu::input::keyboard::{ Keyboard, KeyboardController };
+        use crate::emu::logic::cpu::{ CpuController, FaultPolicy, JitMode };
+        use crate::emu::memory::memory::{ Memory, MemoryController, PROGRAM_START_ADDR };
+
+        // Mixes compiled instructions (ALU, I and timers) with the ones left to the interpreter
+        // (a call writing to the RAM with Fx55 and Fx33, Cxkk, a skip and Dxyn) in a loop.
+        const MIXED_ROM: [u8;76] = [
+            0x60, 0x05, // 200: LD V0, 5
+            0x61, 0x0a, // 202: LD V1, 10
+            0xa3, 0x00, // 204: LD I, 300
+            0x80, 0x14, // 206: ADD V0, V1
+            0x81, 0x06, // 208: SHR V1, V0
+            0x71, 0x03, // 20A: ADD V1, 3
+            0x82, 0x0e, // 20C: SHL V2, V0
+            0x83, 0x25, // 20E: SUB V3, V2
+            0x84, 0x37, // 210: SUBN V4, V3
+            0xf0, 0x15, // 212: LD DT, V0
+            0xf5, 0x18, // 214: LD ST, V5
+            0xf5, 0x07, // 216: LD V5, DT
+            0xf0, 0x1e, // 218: ADD I, V0
+            0x22, 0x40, // 21A: CALL 240
+            0xc3, 0x7f, // 21C: RND V3, 7F
+            0x43, 0x00, // 21E: SNE V3, 0
+            0x76, 0x01, // 220: ADD V6, 1
+            0xd1, 0x25, // 222: DRW V1, V2, 5
+            0x12, 0x06, // 224: JP 206
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0xa3, 0x00, // 240: LD I, 300
+            0xf4, 0x55, // 242: LD [I], V4
+            0xf2, 0x33, // 244: LD B, V2
+            0xf0, 0x29, // 246: LD F, V0
+            0xa3, 0x00, // 248: LD I, 300
+            0x00, 0xee, // 24A: RET
+        ];
+
+        struct Machine {
+            cpu_ctrl: CpuController,
+            screen_ctrl: ScreenController,
+            mem_ctrl: MemoryController,
+            keyboard_ctrl: KeyboardController,
+        }
+
+        impl Machine {
+            // Without a JIT mode, only the interpreter runs the ROM.
+            fn new(rom: &[u8], jit_mode: Option<JitMode>) -> Self {
+                let mut mem_ctrl = MemoryController::new(Memory::new());
+                for (offset, byte) in rom.iter().enumerate() {
+                    mem_ctrl.set_ram(PROGRAM_START_ADDR as usize + offset, *byte);
+                }
+
+                let mut cpu_ctrl = CpuController::new(&mem_ctrl, 100, true, true, FaultPolicy::Halt, Some(1));
+                cpu_ctrl.set_jit(jit_mode.map(|mode| Jit::new(mode).unwrap()));
+                Self {
+                    cpu_ctrl,
+                    screen_ctrl: ScreenController::new(Screen::new()),
+                    mem_ctrl,
+                    keyboard_ctrl: KeyboardController::new(Keyboard::new()),
+                }
+            }
+
+            fn run_frames(&mut self, frames: usize) {
+                for _ in 0..frames {
+                    self.cpu_ctrl.run_frame(&mut self.screen_ctrl, &mut self.mem_ctrl, &mut self.keyboard_ctrl);
+                    self.mem_ctrl.dec_all_timers();
+                }
+            }
+
+            fn assert_same_state(&self, other: &Machine) {
+                assert_eq!(self.mem_ctrl.get_ram()[..], other.mem_ctrl.get_ram()[..]);
+                assert_eq!(self.mem_ctrl.get_v_regs(), other.mem_ctrl.get_v_regs());
+                assert_eq!(self.mem_ctrl.get_i(), other.mem_ctrl.get_i());
+                assert_eq!(self.mem_ctrl.get_pc(), other.mem_ctrl.get_pc());
+                assert_eq!(self.mem_ctrl.get_dt(), other.mem_ctrl.get_dt());
+                assert_eq!(self.mem_ctrl.get_st(), other.mem_ctrl.get_st());
+            }
+        }
+
+        #[test]
+        fn runs_like_the_interpreter() {
+            let mut interpreter = Machine::new(&MIXED_ROM, None);
+            let mut native = Machine::new(&MIXED_ROM, Some(JitMode::Native));
+            let mut lockstep = Machine::new(&MIXED_ROM, Some(JitMode::Lockstep));
+            for machine in [&mut interpreter, &mut native, &mut lockstep] {
+                machine.run_frames(120);
+                assert!(machine.cpu_ctrl.get_fault().is_none());
+            }
+
+            native.assert_same_state(&interpreter);
+            lockstep.assert_same_state(&interpreter);
+            let jit = lockstep.cpu_ctrl.get_jit().unwrap();
+            assert!(jit.checked_blocks > 0);
+            assert_eq!(jit.mismatches, 0);
+        }
+
+        #[test]
+        fn runs_self_modifying_code_like_the_interpreter() {
+            let rom = [
+                0x7a, 0x01, // 200: ADD VA, 1
+                0x12, 0x06, // 202: JP 206
+                0x00, 0x00,
+                0x60, 0x7a, // 206: LD V0, 7A
+                0x61, 0x05, // 208: LD V1, 5
+                0xa2, 0x00, // 20A: LD I, 200
+                0xf1, 0x55, // 20C: LD [I], V1 (200: ADD VA, 5)
+                0x12, 0x00, // 20E: JP 200
+            ];
+            let mut interpreter = Machine::new(&rom, None);
+            let mut native = Machine::new(&rom, Some(JitMode::Native));
+            let mut lockstep = Machine::new(&rom, Some(JitMode::Lockstep));
+            for machine in [&mut interpreter, &mut native, &mut lockstep] {
+                machine.run_frames(10);
+            }
+
+            assert_eq!(interpreter.mem_ctrl.get_ram()[PROGRAM_START_ADDR as usize + 1], 0x05);
+            native.assert_same_state(&interpreter);
+            lockstep.assert_same_state(&interpreter);
+            assert_eq!(lockstep.cpu_ctrl.get_jit().unwrap().mismatches, 0);
+        }
+
+        #[test]
+        fn runs_code_longer_than_a_frame_natively() {
+            // 200-2EE: ADD VA, 1 (120 times)
+            // 2F0: JP 200
+            let mut rom = [0x7a, 0x01].repeat(120);
+            rom.extend_from_slice(&[0x12, 0x00]);
+            let mut interpreter = Machine::new(&rom, None);
+            let mut lockstep = Machine::new(&rom, Some(JitMode::Lockstep));
+            for machine in [&mut interpreter, &mut lockstep] {
+                // Fewer instructions per frame than a block can hold.
+                machine.cpu_ctrl.set_cycles_per_frame(20);
+                machine.run_frames(10);
+            }
+
+            lockstep.assert_same_state(&interpreter);
+            let jit = lockstep.cpu_ctrl.get_jit().unwrap();
+            assert!(jit.checked_blocks >= 10);
+            assert_eq!(jit.mismatches, 0);
+            assert!(jit.blocks.iter().flatten().all(|block| block.instrs <= 20));
+        }
+
+        #[test]
+        fn recompiles_patched_code() {
+            // 200: ADD VA, 1
+            // 202: JP 200
+            let mut machine = Machine::new(&[0x7a, 0x01, 0x12, 0x00], Some(JitMode::Native));
+            machine.run_frames(1);
+            assert_eq!(machine.mem_ctrl.get_v(0xa), 50);
+            let jit = machine.cpu_ctrl.get_jit().unwrap();
+            assert_eq!(jit.blocks[PROGRAM_START_ADDR as usize][0].instrs, 2);
+
+            // 200: ADD VA, 3
+            machine.mem_ctrl.set_ram(PROGRAM_START_ADDR as usize + 1, 0x03);
+            machine.run_frames(1);
+            assert_eq!(machine.mem_ctrl.get_v(0xa), 50 + 50 * 3);
+            let jit = machine.cpu_ctrl.get_jit().unwrap();
+            assert_eq!(jit.blocks[PROGRAM_START_ADDR as usize][0].bytes, [0x7a, 0x03, 0x12, 0x00]);
+        }
+    }
+}
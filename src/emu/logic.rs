@@ -2,14 +2,41 @@
 // SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
 
 pub mod cpu {
+    use crate::emu::backend::backends::RandomSource;
+    #[cfg(feature = "std")]
+    use crate::emu::coverage::coverage_map::Coverage;
+    use crate::emu::display::screen::ScreenController;
     use crate::emu::input::keyboard::KeyboardController;
-    use crate::emu::memory::memory::MemoryController;
-    use crate::sdl::wrapper::SDLController;
+    #[cfg(feature = "jit")]
+    use crate::emu::jit::jit_compiler::Jit;
+    use crate::emu::memory::memory::{ CodeVersion, MemoryController, RAM_SIZE, STACK_SIZE };
+    #[cfg(feature = "std")]
+    use crate::emu::profiler::exec_profiler::Profiler;
+    #[cfg(feature = "std")]
+    use crate::emu::sys_call::sys_handler::SysHandler;
+    #[cfg(feature = "desktop")]
+    use crate::emu::trace::tracer::Tracer;
     use crate::util::utilities::{ BitUtil, LogicUtil };
 
-    use rand::Rng;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    #[cfg(feature = "std")]
+    use std::collections::BTreeMap;
+    #[cfg(feature = "std")]
+    use std::collections::hash_map::RandomState;
+    #[cfg(feature = "std")]
+    use std::hash::{ BuildHasher, Hasher };
 
-    pub struct CpuController {
+    // Instructions per frame of the default settings.
+    pub const DEFAULT_CYCLES_PER_FRAME: u32 = 20;
+
+    // How many unknown opcodes (log once policy) can wait for the frontend to log them.
+    const MAX_PENDING_UNKNOWN_OPCODES: usize = 32;
+
+    // The random numbers come from R, e.g. a hardware RNG on a microcontroller.
+    // It keeps the decoded instructions of the RAM it runs (with their validity), so a new or reset CPU decodes
+    // everything again whatever MemoryController it's paired with.
+    pub struct CpuController<R: RandomSource = StdRng> {
         // Big-endian.
         word: u16,         // [0000111100001111]
         first_byte: u8,    // [00001111]00001111
@@ -22,14 +49,87 @@ pub mod cpu {
         cycles_per_frame: u32,
         bit_shift_instructions_use_vy: bool,
         store_read_instructions_change_i: bool,
+        fault_policy: FaultPolicy,
+        // The fault that halted the CPU (nothing is executed until it's cleared).
+        fault: Option<FaultInfo>,
+        // A fault that wasn't reported yet (with the wrap policy, only the first one is reported).
+        new_fault: Option<FaultInfo>,
+        wrapped_fault_reported: bool,
+        unknown_opcode_policy: UnknownOpcodePolicy,
+        // Addresses where an unknown opcode was already logged (log once policy), a bit per address.
+        logged_unknown_addrs: [u8;RAM_SIZE / 8],
+        // Unknown opcodes found for the first time at their address, as (address, opcode), until the frontend logs them.
+        // The queue has a fixed size: while it's full, the next ones aren't logged.
+        pending_unknown_opcodes: [(u16, u16);MAX_PENDING_UNKNOWN_OPCODES],
+        pending_unknown_opcodes_len: usize,
+        // The instruction at each address, decoded the first time it's executed.
+        decoded_instrs: [DecodedInstr;RAM_SIZE],
+        // The entries of decoded_instrs that are valid, a bit per address. They're all dropped when the CPU is reset
+        // and when the MemoryController's CodeVersion differs from the one they were decoded at (decoded code was
+        // written over, or the CPU runs on another RAM).
+        cached_instrs: [u64;RAM_SIZE / 64],
+        cached_code_version: Option<CodeVersion>,
+        // How many times each unknown opcode was executed, kept across resets.
+        #[cfg(feature = "std")]
+        unknown_opcode_counts: BTreeMap<u16, u64>,
+        // Handles the 0nnn instructions (without it, they're unknown opcodes).
+        #[cfg(feature = "std")]
+        sys_handler: Option<Box<dyn SysHandler>>,
+        #[cfg(feature = "desktop")]
+        tracer: Option<Tracer>,
+        #[cfg(feature = "std")]
+        profiler: Option<Profiler>,
+        #[cfg(feature = "std")]
+        coverage: Option<Coverage>,
+        // Runs the frames as native code (without a tracer, a profiler or a coverage map).
+        #[cfg(feature = "jit")]
+        jit: Option<Jit>,
+        // Used by the Cxkk instruction, it can be seeded to make the execution reproducible.
+        rng: R,
     }
 
+    #[cfg(feature = "std")]
     impl CpuController {
+        // Without a seed, a different one is picked on each run.
         pub fn new(
             mem_ctrl: &MemoryController,
             cycles_per_frame: u32,
             bit_shift_instructions_use_vy: bool,
-            store_read_instructions_change_i: bool) -> Self {
+            store_read_instructions_change_i: bool,
+            fault_policy: FaultPolicy,
+            seed: Option<u64>) -> Self {
+
+            CpuController::with_rng(
+                mem_ctrl,
+                cycles_per_frame,
+                bit_shift_instructions_use_vy,
+                store_read_instructions_change_i,
+                fault_policy,
+                StdRng::seed_from_u64(seed.unwrap_or_else(CpuController::random_seed)))
+        }
+
+        // A seed that changes on each run, without needing the OS's randomness from rand:
+        // the standard library seeds the hashers of the collections with it (where there's an OS).
+        fn random_seed() -> u64 {
+            RandomState::new().build_hasher().finish()
+        }
+    }
+
+    impl CpuController<StdRng> {
+        // Restarts the random numbers of the Cxkk instruction from a seed, e.g. one given by a frontend without an OS.
+        pub fn set_seed(&mut self, seed: u64) {
+            self.rng = StdRng::seed_from_u64(seed);
+        }
+    }
+
+    impl<R: RandomSource> CpuController<R> {
+        pub fn with_rng(
+            mem_ctrl: &MemoryController,
+            cycles_per_frame: u32,
+            bit_shift_instructions_use_vy: bool,
+            store_read_instructions_change_i: bool,
+            fault_policy: FaultPolicy,
+            rng: R) -> Self {
 
             let lower_addr = mem_ctrl.get_pc() as usize;
             let first_byte = mem_ctrl.get_ram()[lower_addr];
@@ -39,124 +139,426 @@ pub mod cpu {
                 first_byte,
                 second_byte,
                 word:          BitUtil::make_16bit_instr_from_bytes(first_byte, second_byte),
-                first_nibble:  mem_ctrl.get_ram()[lower_addr] >> 4,
-                x:             mem_ctrl.get_ram()[lower_addr] & 0b0000_1111,
-                y:             mem_ctrl.get_ram()[lower_addr + 1] >> 4,
-                fourth_nibble: mem_ctrl.get_ram()[lower_addr + 1] & 0b0000_1111,
+                first_nibble:  first_byte >> 4,
+                x:             first_byte & 0b0000_1111,
+                y:             second_byte >> 4,
+                fourth_nibble: second_byte & 0b0000_1111,
                 inc_pc: true,
                 cycles_per_frame,
                 bit_shift_instructions_use_vy,
                 store_read_instructions_change_i,
+                fault_policy,
+                fault: None,
+                new_fault: None,
+                wrapped_fault_reported: false,
+                unknown_opcode_policy: UnknownOpcodePolicy::LogOnce,
+                logged_unknown_addrs: [0;RAM_SIZE / 8],
+                pending_unknown_opcodes: [(0, 0);MAX_PENDING_UNKNOWN_OPCODES],
+                pending_unknown_opcodes_len: 0,
+                decoded_instrs: [DecodedInstr { op: Op::Unknown, first_byte: 0, second_byte: 0 };RAM_SIZE],
+                cached_instrs: [0;RAM_SIZE / 64],
+                cached_code_version: None,
+                #[cfg(feature = "std")]
+                unknown_opcode_counts: BTreeMap::new(),
+                #[cfg(feature = "std")]
+                sys_handler: None,
+                #[cfg(feature = "desktop")]
+                tracer: None,
+                #[cfg(feature = "std")]
+                profiler: None,
+                #[cfg(feature = "std")]
+                coverage: None,
+                #[cfg(feature = "jit")]
+                jit: None,
+                rng,
+            }
+        }
+
+        // Runs a (60hz) frame worth of instructions and returns how many were executed.
+        pub fn run_frame(
+            &mut self,
+            screen_ctrl: &mut ScreenController,
+            mem_ctrl: &mut MemoryController,
+            keyboard_ctrl: &mut KeyboardController) -> u64 {
+
+            #[cfg(feature = "jit")]
+            if self.jit.is_some() && !self.has_instr_hooks() {
+                return self.run_jit_frame(screen_ctrl, mem_ctrl, keyboard_ctrl);
+            }
+
+            let mut instructions: u64 = 0;
+            while instructions < self.cycles_per_frame as u64 && self.fault.is_none() {
+                self.fetch_exec(screen_ctrl, mem_ctrl, keyboard_ctrl);
+                instructions += 1;
+            }
+
+            #[cfg(feature = "std")]
+            if let Some(profiler) = &mut self.profiler {
+                profiler.end_frame();
             }
+            instructions
+        }
+
+        #[cfg(feature = "jit")]
+        fn run_jit_frame(
+            &mut self,
+            screen_ctrl: &mut ScreenController,
+            mem_ctrl: &mut MemoryController,
+            keyboard_ctrl: &mut KeyboardController) -> u64 {
+
+            // The JIT runs the instructions it can't compile through this CPU.
+            let Some(mut jit) = self.jit.take() else {
+                return 0;
+            };
+            let instructions = jit.run_frame(self, screen_ctrl, mem_ctrl, keyboard_ctrl);
+            self.jit = Some(jit);
+            instructions
         }
 
+        // Whether something must see each instruction, which the JIT can't do.
+        #[cfg(feature = "jit")]
+        fn has_instr_hooks(&self) -> bool {
+            #[cfg(feature = "desktop")]
+            if self.tracer.is_some() {
+                return true;
+            }
+            self.profiler.is_some() || self.coverage.is_some()
+        }
+
+        // Forgets the execution state kept between instructions and frames, used when the machine is reset.
+        pub fn reset(&mut self) {
+            self.inc_pc = true;
+            self.cached_instrs = [0;RAM_SIZE / 64];
+            self.fault = None;
+            self.new_fault = None;
+            self.wrapped_fault_reported = false;
+        }
+
+        // Does nothing while the CPU is halted by a fault.
         pub fn fetch_exec(
             &mut self,
-            sdl_ctrl: &mut SDLController,
+            screen_ctrl: &mut ScreenController,
             mem_ctrl: &mut MemoryController,
             keyboard_ctrl: &mut KeyboardController) {
 
+            if self.fault.is_some() {
+                return;
+            }
+
             self.inc_pc = true;
-            self.load_next_instr(mem_ctrl);
-            
-            match self.word {
-                0x00e0 => self.clear_screen(sdl_ctrl),
-                0x00ee => self.return_from_subroutine(mem_ctrl),
-                _ => self.exec_instr_by_nibble(sdl_ctrl, mem_ctrl, keyboard_ctrl)
+
+            // Both bytes of the instruction must be inside the RAM.
+            let pc = mem_ctrl.get_pc();
+            if pc as usize > RAM_SIZE - 2 {
+                if !self.raise_fault(mem_ctrl, CpuFault::PcOutOfBounds(pc), None) {
+                    return;
+                }
+                mem_ctrl.set_pc(pc & 0xfff);
             }
 
+            let op = self.load_next_instr(mem_ctrl);
+            // For the trace, the profiler and the coverage.
+            #[cfg(feature = "std")]
+            let (instr_pc, instr_i) = (mem_ctrl.get_pc(), mem_ctrl.get_i());
+
+            CpuController::DISPATCH_TABLE[op as usize](self, screen_ctrl, mem_ctrl, keyboard_ctrl);
+
             if self.inc_pc {
                 // The program counter is always incremented by 2 because all instructions are 2 bytes
                 // and the ram stores 1 byte values only.
                 mem_ctrl.inc_pc_by(2);
             }
+
+            #[cfg(feature = "desktop")]
+            if let Some(tracer) = &mut self.tracer {
+                tracer.trace(instr_pc, self.word, mem_ctrl);
+            }
+            #[cfg(feature = "std")]
+            if let Some(profiler) = &mut self.profiler {
+                profiler.record(instr_pc, self.word, mem_ctrl);
+            }
+            #[cfg(feature = "std")]
+            if let Some(coverage) = &mut self.coverage {
+                coverage.record(instr_pc, self.word, instr_i, self.fault.is_none());
+            }
         }
 
-        fn load_next_instr(&mut self, mem_ctrl: &MemoryController) {
+        // Puts the instruction at the address in the decoded instruction cache, decoding it if it isn't there
+        // (the first time it's executed, or after its bytes were written). Returns its opcode.
+        pub fn decode_instr_at(&mut self, mem_ctrl: &mut MemoryController, addr: usize) -> u16 {
+            if self.cached_code_version != Some(mem_ctrl.get_code_version()) {
+                self.cached_instrs = [0;RAM_SIZE / 64];
+                self.cached_code_version = Some(mem_ctrl.get_code_version());
+            }
+
+            if self.cached_instrs[addr / 64] & (1 << (addr % 64)) == 0 {
+                // With the wrap policy, an instruction at 0xfff takes its second byte from 0x000.
+                let (first_byte, second_byte) = (mem_ctrl.get_ram()[addr], mem_ctrl.get_ram()[(addr + 1) % RAM_SIZE]);
+                self.decoded_instrs[addr] = DecodedInstr { op: Op::decode(first_byte, second_byte), first_byte, second_byte };
+                self.cached_instrs[addr / 64] |= 1 << (addr % 64);
+                mem_ctrl.set_code_addr(addr);
+            }
+
+            let instr = self.decoded_instrs[addr];
+            BitUtil::make_16bit_instr_from_bytes(instr.first_byte, instr.second_byte)
+        }
+
+        // Loads the instruction at the PC from the decoded instruction cache. Returns what it does.
+        fn load_next_instr(&mut self, mem_ctrl: &mut MemoryController) -> Op {
             let lower_addr = mem_ctrl.get_pc() as usize;
+            self.decode_instr_at(mem_ctrl, lower_addr);
+
+            let instr = self.decoded_instrs[lower_addr];
+            self.first_byte =    instr.first_byte;
+            self.second_byte =   instr.second_byte;
+            self.word =          BitUtil::make_16bit_instr_from_bytes(instr.first_byte, instr.second_byte);
+            self.first_nibble =  instr.first_byte >> 4;
+            self.x =             instr.first_byte & 0b0000_1111;
+            self.y =             instr.second_byte >> 4;
+            self.fourth_nibble = instr.second_byte & 0b0000_1111;
+            instr.op
+        }
 
-            self.first_byte =    mem_ctrl.get_ram()[lower_addr];
-            self.second_byte =   mem_ctrl.get_ram()[lower_addr + 1];
-            self.word =          BitUtil::make_16bit_instr_from_bytes(self.first_byte, self.second_byte);
-            self.first_nibble =  mem_ctrl.get_ram()[lower_addr] >> 4;
-            self.x =             mem_ctrl.get_ram()[lower_addr] & 0b0000_1111;
-            self.y =             mem_ctrl.get_ram()[lower_addr + 1] >> 4;
-            self.fourth_nibble = mem_ctrl.get_ram()[lower_addr + 1] & 0b0000_1111;
+        // Handles a fault according to the fault policy.
+        // Returns true if the execution must go on with the address wrapped (wrap policy),
+        // otherwise the CPU is halted, keeping the PC at the faulting instruction.
+        fn raise_fault(&mut self, mem_ctrl: &MemoryController, fault: CpuFault, opcode: Option<u16>) -> bool {
+            self.raise_fault_with_policy(mem_ctrl, fault, opcode, self.fault_policy)
         }
 
-        fn exec_instr_by_nibble(
+        fn raise_fault_with_policy(
             &mut self,
-            sdl_ctrl: &mut SDLController,
-            mem_ctrl: &mut MemoryController,
-            keyboard_ctrl: &mut KeyboardController) {
-                
-            match self.first_nibble {
-                1 => { self.jump_to_address(mem_ctrl); self.inc_pc = false; },
-                2 => { self.call_address(mem_ctrl); self.inc_pc = false; },
-                3 => self.skip_equal_vx_byte(mem_ctrl),
-                4 => self.skip_not_equal_vx_byte(mem_ctrl),
-                5 => self.skip_equal_vx_vy(mem_ctrl),
-                6 => self.set_vx_byte(mem_ctrl),
-                7 => self.add_vx_byte(mem_ctrl),
-                8 => {
-                    match self.fourth_nibble {
-                        0 => self.set_vx_vy(mem_ctrl),
-                        1 => self.set_vx_or_vy(mem_ctrl),
-                        2 => self.set_vx_and_vy(mem_ctrl),
-                        3 => self.set_vx_xor_vy(mem_ctrl),
-                        4 => self.add_vx_vy(mem_ctrl),
-                        5 => self.sub_vx_vy(mem_ctrl),
-                        6 => self.shift_right_vx_vy(mem_ctrl),
-                        7 => self.subn_vx_vy(mem_ctrl),
-                        0xe => self.shift_left_vx_vy(mem_ctrl),
-                        _ => CpuController::log_not_implemented(self.word)
-                    }
-                },
-                9 => self.skip_not_equal_vx_vy(mem_ctrl),
-                0xa => self.set_i_address(mem_ctrl),
-                0xb => { self.jump_to_address_plus_v0(mem_ctrl); self.inc_pc = false; },
-                0xc => self.set_vx_and_random_byte(mem_ctrl),
-                0xd => self.draw_sprite(mem_ctrl, sdl_ctrl),
-                0xe => {
-                    match self.second_byte {
-                        0x9e => self.skip_if_key_vx_is_pressed(keyboard_ctrl, mem_ctrl),
-                        0xa1 => self.skip_if_key_vx_is_not_pressed(keyboard_ctrl, mem_ctrl),
-                        _ => CpuController::log_not_implemented(self.word)
-                    }
-                },
-                0xf => {
-                    match self.second_byte {
-                        0x07 => self.set_vx_dt(mem_ctrl),
-                        0x0a => {
-                            if let CpuState::Halted = self.halt_until_key_press(keyboard_ctrl, mem_ctrl) {
-                                self.inc_pc = false;
-                            }
-                        },
-                        0x15 => self.set_dt_vx(mem_ctrl),
-                        0x18 => self.set_st_vx(mem_ctrl),
-                        0x1e => self.add_i_vx(mem_ctrl),
-                        0x29 => self.set_i_sprite_digit_vx(mem_ctrl),
-                        0x33 => self.copy_bcd_vx_into_addr_i(mem_ctrl),
-                        0x55 => self.copy_v0_through_vx_into_addr_i(mem_ctrl),
-                        0x65 => self.read_v0_through_vx_from_addr_i(mem_ctrl),
-                        _ => CpuController::log_not_implemented(self.word)
-                    }
-                },
-                _ => CpuController::log_not_implemented(self.word)
+            mem_ctrl: &MemoryController,
+            fault: CpuFault,
+            opcode: Option<u16>,
+            policy: FaultPolicy) -> bool {
+
+            let fault_info = FaultInfo {
+                fault,
+                pc: mem_ctrl.get_pc(),
+                opcode,
+                policy,
+                #[cfg(feature = "std")]
+                registers: mem_ctrl.dump_registers(),
+            };
+
+            if let FaultPolicy::Wrap = policy {
+                if !self.wrapped_fault_reported {
+                    self.wrapped_fault_reported = true;
+                    self.new_fault = Some(fault_info);
+                }
+                return true;
+            }
+
+            self.fault = Some(fault_info.clone());
+            self.new_fault = Some(fault_info);
+            self.inc_pc = false;
+            false
+        }
+
+        // Checks an access of len bytes starting at addr (through I), raising a fault if it goes past the end of the RAM.
+        // Returns true if the instruction can go on (the addresses must then be wrapped with & 0xfff).
+        fn check_ram_range(&mut self, mem_ctrl: &MemoryController, addr: u16, len: u16) -> bool {
+            let last_addr = addr as usize + len as usize;
+            if len == 0 || last_addr <= RAM_SIZE {
+                return true;
             }
+            self.raise_fault(mem_ctrl, CpuFault::MemoryOutOfBounds((last_addr - 1) as u16), Some(self.word))
         }
 
+        // The function that executes each Op (in the order of Op), called by fetch_exec with the instruction loaded.
+        const DISPATCH_TABLE: [InstrHandler<R>;OP_COUNT] = [
+            |cpu, screen_ctrl, _, _| cpu.clear_screen(screen_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.return_from_subroutine(mem_ctrl),
+            |cpu, screen_ctrl, mem_ctrl, _| cpu.sys_call(mem_ctrl, screen_ctrl),
+            |cpu, _, mem_ctrl, _| { cpu.jump_to_address(mem_ctrl); cpu.inc_pc = false; },
+            |cpu, _, mem_ctrl, _| { cpu.call_address(mem_ctrl); cpu.inc_pc = false; },
+            |cpu, _, mem_ctrl, _| cpu.skip_equal_vx_byte(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.skip_not_equal_vx_byte(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.skip_equal_vx_vy(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.set_vx_byte(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.add_vx_byte(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.set_vx_vy(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.set_vx_or_vy(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.set_vx_and_vy(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.set_vx_xor_vy(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.add_vx_vy(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.sub_vx_vy(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.shift_right_vx_vy(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.subn_vx_vy(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.shift_left_vx_vy(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.skip_not_equal_vx_vy(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.set_i_address(mem_ctrl),
+            |cpu, _, mem_ctrl, _| { cpu.jump_to_address_plus_v0(mem_ctrl); cpu.inc_pc = false; },
+            |cpu, _, mem_ctrl, _| cpu.set_vx_and_random_byte(mem_ctrl),
+            |cpu, screen_ctrl, mem_ctrl, _| cpu.draw_sprite(mem_ctrl, screen_ctrl),
+            |cpu, _, mem_ctrl, keyboard_ctrl| cpu.skip_if_key_vx_is_pressed(keyboard_ctrl, mem_ctrl),
+            |cpu, _, mem_ctrl, keyboard_ctrl| cpu.skip_if_key_vx_is_not_pressed(keyboard_ctrl, mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.set_vx_dt(mem_ctrl),
+            |cpu, _, mem_ctrl, keyboard_ctrl| {
+                if let CpuState::Halted = cpu.halt_until_key_press(keyboard_ctrl, mem_ctrl) {
+                    cpu.inc_pc = false;
+                }
+            },
+            |cpu, _, mem_ctrl, _| cpu.set_dt_vx(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.set_st_vx(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.add_i_vx(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.set_i_sprite_digit_vx(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.copy_bcd_vx_into_addr_i(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.copy_v0_through_vx_into_addr_i(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.read_v0_through_vx_from_addr_i(mem_ctrl),
+            |cpu, _, mem_ctrl, _| cpu.handle_unknown_opcode(mem_ctrl),
+        ];
+
         pub fn get_cycles_per_frame(&self) -> u32 {
             self.cycles_per_frame
         }
 
+        pub fn set_cycles_per_frame(&mut self, val: u32) {
+            self.cycles_per_frame = val;
+        }
+
+        pub fn get_rng(&self) -> &R {
+            &self.rng
+        }
+
+        pub fn set_rng(&mut self, rng: R) {
+            self.rng = rng;
+        }
+
+        pub fn get_fault_policy(&self) -> FaultPolicy {
+            self.fault_policy
+        }
+
+        // The fault that halted the CPU (if any).
+        pub fn get_fault(&self) -> Option<&FaultInfo> {
+            self.fault.as_ref()
+        }
+
+        // Resumes the execution after a fault, e.g. from the debugger after fixing the state.
+        pub fn clear_fault(&mut self) {
+            self.fault = None;
+        }
+
+        // Returns a fault that happened since the last call, so it's reported only once.
+        pub fn take_new_fault(&mut self) -> Option<FaultInfo> {
+            self.new_fault.take()
+        }
+
+        pub fn get_unknown_opcode_policy(&self) -> UnknownOpcodePolicy {
+            self.unknown_opcode_policy
+        }
+
+        pub fn set_unknown_opcode_policy(&mut self, val: UnknownOpcodePolicy) {
+            self.unknown_opcode_policy = val;
+        }
+
+        #[cfg(feature = "std")]
+        pub fn set_sys_handler(&mut self, sys_handler: Option<Box<dyn SysHandler>>) {
+            self.sys_handler = sys_handler;
+        }
+
+        #[cfg(feature = "desktop")]
+        pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+            self.tracer = tracer;
+        }
+
+        #[cfg(feature = "std")]
+        pub fn get_profiler(&self) -> Option<&Profiler> {
+            self.profiler.as_ref()
+        }
+
+        #[cfg(feature = "std")]
+        pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+            self.profiler = profiler;
+        }
+
+        #[cfg(feature = "std")]
+        pub fn get_coverage(&self) -> Option<&Coverage> {
+            self.coverage.as_ref()
+        }
+
+        #[cfg(feature = "std")]
+        pub fn set_coverage(&mut self, coverage: Option<Coverage>) {
+            self.coverage = coverage;
+        }
+
+        #[cfg(feature = "jit")]
+        pub fn get_jit(&self) -> Option<&Jit> {
+            self.jit.as_ref()
+        }
+
+        #[cfg(feature = "jit")]
+        pub fn set_jit(&mut self, jit: Option<Jit>) {
+            self.jit = jit;
+        }
+
+        // Writes the last traced instructions (with a ring buffer trace), e.g. after a fault.
+        #[cfg(feature = "desktop")]
+        pub fn dump_trace(&mut self, reason: &str) {
+            if let Some(tracer) = &mut self.tracer {
+                tracer.dump_ring(reason);
+            }
+        }
+
+        // Hands the unknown opcodes found since the last call (log once policy) to the frontend, e.g. to print them,
+        // as (address, opcode).
+        pub fn drain_unknown_opcode_log(&mut self, mut log: impl FnMut(u16, u16)) {
+            for (addr, opcode) in &self.pending_unknown_opcodes[..self.pending_unknown_opcodes_len] {
+                log(*addr, *opcode);
+            }
+            self.pending_unknown_opcodes_len = 0;
+        }
+
+        // How many times each unknown opcode was executed since the ROM was loaded.
+        #[cfg(feature = "std")]
+        pub fn get_unknown_opcode_counts(&self) -> &BTreeMap<u16, u64> {
+            &self.unknown_opcode_counts
+        }
+
+        // The unknown opcodes executed since the ROM was loaded, formatted for display (if there were any).
+        #[cfg(feature = "std")]
+        pub fn get_unknown_opcode_summary(&self) -> Option<String> {
+            if self.unknown_opcode_counts.is_empty() {
+                return None;
+            }
+
+            let total: u64 = self.unknown_opcode_counts.values().sum();
+            let counts: Vec<String> = self.unknown_opcode_counts.iter()
+                .map(|(opcode, count)| format!("{opcode:04X} x{count}"))
+                .collect();
+            Some(format!("Unknown opcodes executed: {total} ({} distinct): {}", counts.len(), counts.join(", ")))
+        }
+
+        pub fn get_bit_shift_instructions_use_vy(&self) -> bool {
+            self.bit_shift_instructions_use_vy
+        }
+
+        pub fn get_store_read_instructions_change_i(&self) -> bool {
+            self.store_read_instructions_change_i
+        }
+
+        pub fn set_bit_shift_instructions_use_vy(&mut self, val: bool) {
+            self.bit_shift_instructions_use_vy = val;
+        }
+
+        pub fn set_store_read_instructions_change_i(&mut self, val: bool) {
+            self.store_read_instructions_change_i = val;
+        }
+
         // 00E0 - CLS
-        fn clear_screen(&self, sdl_ctrl: &mut SDLController) {
-            sdl_ctrl.clear_screen();
+        fn clear_screen(&self, screen_ctrl: &mut ScreenController) {
+            screen_ctrl.clear_screen();
         }
 
         // 00EE - RET
-        fn return_from_subroutine(&self, mem_ctrl: &mut MemoryController) {
+        fn return_from_subroutine(&mut self, mem_ctrl: &mut MemoryController) {
+            if mem_ctrl.get_sp() == 0 {
+                if !self.raise_fault(mem_ctrl, CpuFault::StackUnderflow, Some(self.word)) {
+                    return;
+                }
+                mem_ctrl.set_sp(STACK_SIZE as u8);
+            }
             mem_ctrl.stack_pop();
         }
 
@@ -169,7 +571,13 @@ pub mod cpu {
         }
 
         // 2nnn - CALL addr
-        fn call_address(&self, mem_ctrl: &mut MemoryController) {
+        fn call_address(&mut self, mem_ctrl: &mut MemoryController) {
+            if mem_ctrl.get_sp() as usize >= STACK_SIZE {
+                if !self.raise_fault(mem_ctrl, CpuFault::StackOverflow, Some(self.word)) {
+                    return;
+                }
+                mem_ctrl.set_sp(0);
+            }
             mem_ctrl.stack_push(BitUtil::make_16bit_addr_from_nibbles(
                 self.x,
                 self.y,
@@ -294,32 +702,34 @@ pub mod cpu {
         }
 
         // Cxkk - RND Vx, byte
-        fn set_vx_and_random_byte(&self, mem_ctrl: &mut MemoryController) {
+        fn set_vx_and_random_byte(&mut self, mem_ctrl: &mut MemoryController) {
             mem_ctrl.set_v(
                 self.x,
-                rand::thread_rng().gen_range(0..=255) & self.second_byte);
+                self.rng.next_byte() & self.second_byte);
         }
 
         // Dxyn - DRW Vx, Vy, nibble.
-        fn draw_sprite(&self, mem_ctrl: &mut MemoryController, sdl_ctrl: &mut SDLController) {
+        fn draw_sprite(&mut self, mem_ctrl: &mut MemoryController, screen_ctrl: &mut ScreenController) {
+            if !self.check_ram_range(mem_ctrl, mem_ctrl.get_i(), self.fourth_nibble as u16) {
+                return;
+            }
+
             mem_ctrl.set_v(0xf, 0);
 
             // fourth_nibble specifies the size of the sprite.
             for byte_i in 0..self.fourth_nibble {
                 // Load is based on the address stored on the register i.
-                let sprite_byte = mem_ctrl.get_ram()[(mem_ctrl.get_i() + byte_i as u16) as usize];
+                let sprite_byte = mem_ctrl.get_ram()[(mem_ctrl.get_i().wrapping_add(byte_i as u16) & 0xfff) as usize];
 
-                let mut curr_bit = 0;
-                for rev_bit_i in (0..8).rev() {
+                for (curr_bit, rev_bit_i) in (0..8).rev().enumerate() {
                     // Only draws bits that are equal to 1, from most significant to least significant.
                     if (sprite_byte >> rev_bit_i & 1) == 1 {
                         // If a collision happened, vf is set to 1 for the entire current drawing routine.
-                        sdl_ctrl.put_pixel(
-                            mem_ctrl.get_v(self.x).wrapping_add(curr_bit), 
+                        screen_ctrl.put_pixel(
+                            mem_ctrl.get_v(self.x).wrapping_add(curr_bit as u8),
                             mem_ctrl.get_v(self.y).wrapping_add(byte_i),
                             mem_ctrl);
                     }
-                    curr_bit += 1;
                 }
             }
         }
@@ -383,19 +793,27 @@ pub mod cpu {
         }
 
         // Fx33 - LD B, Vx
-        fn copy_bcd_vx_into_addr_i(&self, mem_ctrl: &mut MemoryController) {
+        fn copy_bcd_vx_into_addr_i(&mut self, mem_ctrl: &mut MemoryController) {
+            if !self.check_ram_range(mem_ctrl, mem_ctrl.get_i(), 3) {
+                return;
+            }
+
             let bcd_tuple = BitUtil::decimal_to_8bit_bcd_tuple(mem_ctrl.get_v(self.x));
             let i = mem_ctrl.get_i() as usize;
-            mem_ctrl.set_ram(i, bcd_tuple.0);
-            mem_ctrl.set_ram(i + 1, bcd_tuple.1);
-            mem_ctrl.set_ram(i + 2, bcd_tuple.2);
+            mem_ctrl.set_ram(i % RAM_SIZE, bcd_tuple.0);
+            mem_ctrl.set_ram((i + 1) % RAM_SIZE, bcd_tuple.1);
+            mem_ctrl.set_ram((i + 2) % RAM_SIZE, bcd_tuple.2);
         }
 
         // Fx55 - LD [I], Vx
-        fn copy_v0_through_vx_into_addr_i(&self, mem_ctrl: &mut MemoryController) {
+        fn copy_v0_through_vx_into_addr_i(&mut self, mem_ctrl: &mut MemoryController) {
+            if !self.check_ram_range(mem_ctrl, mem_ctrl.get_i(), self.x as u16 + 1) {
+                return;
+            }
+
             let mut vi: u8;
             for i in 0..=self.x {
-                let index = (mem_ctrl.get_i() + i as u16) as usize;
+                let index = (mem_ctrl.get_i().wrapping_add(i as u16) & 0xfff) as usize;
                 vi = mem_ctrl.get_v(i);
                 mem_ctrl.set_ram(index, vi);
             }
@@ -403,11 +821,15 @@ pub mod cpu {
         }
 
         // Fx65 - LD Vx, [I]
-        fn read_v0_through_vx_from_addr_i(&self, mem_ctrl: &mut MemoryController) {
+        fn read_v0_through_vx_from_addr_i(&mut self, mem_ctrl: &mut MemoryController) {
+            if !self.check_ram_range(mem_ctrl, mem_ctrl.get_i(), self.x as u16 + 1) {
+                return;
+            }
+
             for i in 0..=self.x {
                 mem_ctrl.set_v(
                     i,
-                    mem_ctrl.get_ram()[(mem_ctrl.get_i() + i as u16) as usize]);
+                    mem_ctrl.get_ram()[(mem_ctrl.get_i().wrapping_add(i as u16) & 0xfff) as usize]);
             }
             self.check_inc_i(mem_ctrl);
         }
@@ -418,8 +840,285 @@ pub mod cpu {
             }
         }
 
-        fn log_not_implemented(instr: u16) {
-            println!("Tried to execute non-implemented instruction: {:#06X}", instr);
+        // 0nnn - SYS addr
+        #[cfg_attr(not(feature = "std"), allow(unused_variables))]
+        fn sys_call(&mut self, mem_ctrl: &mut MemoryController, screen_ctrl: &mut ScreenController) {
+            #[cfg(feature = "std")]
+            let handled = match &mut self.sys_handler {
+                Some(sys_handler) => sys_handler.call(self.word & 0x0fff, mem_ctrl, screen_ctrl),
+                None => false
+            };
+            // Without an allocator there are no handlers.
+            #[cfg(not(feature = "std"))]
+            let handled = false;
+
+            if !handled {
+                self.handle_unknown_opcode(mem_ctrl);
+            }
+        }
+
+        // Counts the unknown opcode and handles it according to the unknown opcode policy.
+        fn handle_unknown_opcode(&mut self, mem_ctrl: &MemoryController) {
+            #[cfg(feature = "std")]
+            {
+                *self.unknown_opcode_counts.entry(self.word).or_insert(0) += 1;
+            }
+
+            match self.unknown_opcode_policy {
+                UnknownOpcodePolicy::Ignore => (),
+                UnknownOpcodePolicy::LogOnce => self.log_unknown_opcode_once(mem_ctrl.get_pc()),
+                UnknownOpcodePolicy::Halt => {
+                    self.raise_fault_with_policy(mem_ctrl, CpuFault::UnknownOpcode, Some(self.word), FaultPolicy::Halt);
+                },
+                UnknownOpcodePolicy::Debug => {
+                    self.raise_fault_with_policy(mem_ctrl, CpuFault::UnknownOpcode, Some(self.word), FaultPolicy::Debug);
+                }
+            }
+        }
+
+        // Queues the unknown opcode for the frontend to log (see drain_unknown_opcode_log),
+        // unless one was already found at its address.
+        fn log_unknown_opcode_once(&mut self, addr: u16) {
+            let (byte, bit) = ((addr as usize % RAM_SIZE) / 8, addr % 8);
+            if self.logged_unknown_addrs[byte] & (1 << bit) != 0 {
+                return;
+            }
+            self.logged_unknown_addrs[byte] |= 1 << bit;
+
+            if self.pending_unknown_opcodes_len < MAX_PENDING_UNKNOWN_OPCODES {
+                self.pending_unknown_opcodes[self.pending_unknown_opcodes_len] = (addr, self.word);
+                self.pending_unknown_opcodes_len += 1;
+            }
+        }
+    }
+
+    // Executes an instruction loaded by the CPU (see CpuController::DISPATCH_TABLE).
+    type InstrHandler<R> = fn(&mut CpuController<R>, &mut ScreenController, &mut MemoryController, &mut KeyboardController);
+
+    const OP_COUNT: usize = Op::Unknown as usize + 1;
+
+    // What an instruction does, which indexes the dispatch table.
+    #[derive(Clone, Copy, PartialEq)]
+    enum Op {
+        Cls, Ret, Sys, Jp, Call,
+        SeVxByte, SneVxByte, SeVxVy, LdVxByte, AddVxByte,
+        LdVxVy, Or, And, Xor, AddVxVy, Sub, Shr, Subn, Shl,
+        SneVxVy, LdI, JpV0, Rnd, Drw, Skp, Sknp,
+        LdVxDt, LdVxK, LdDtVx, LdStVx, AddIVx, LdFVx, LdBVx, LdIVx, LdVxI,
+        Unknown,
+    }
+
+    impl Op {
+        fn decode(first_byte: u8, second_byte: u8) -> Self {
+            match (first_byte >> 4, second_byte) {
+                (0, 0xe0) if first_byte == 0 => Op::Cls,
+                (0, 0xee) if first_byte == 0 => Op::Ret,
+                (0, _) => Op::Sys,
+                (1, _) => Op::Jp,
+                (2, _) => Op::Call,
+                (3, _) => Op::SeVxByte,
+                (4, _) => Op::SneVxByte,
+                (5, _) => Op::SeVxVy,
+                (6, _) => Op::LdVxByte,
+                (7, _) => Op::AddVxByte,
+                (8, _) => match second_byte & 0b0000_1111 {
+                    0 => Op::LdVxVy,
+                    1 => Op::Or,
+                    2 => Op::And,
+                    3 => Op::Xor,
+                    4 => Op::AddVxVy,
+                    5 => Op::Sub,
+                    6 => Op::Shr,
+                    7 => Op::Subn,
+                    0xe => Op::Shl,
+                    _ => Op::Unknown
+                },
+                (9, _) => Op::SneVxVy,
+                (0xa, _) => Op::LdI,
+                (0xb, _) => Op::JpV0,
+                (0xc, _) => Op::Rnd,
+                (0xd, _) => Op::Drw,
+                (0xe, 0x9e) => Op::Skp,
+                (0xe, 0xa1) => Op::Sknp,
+                (0xf, 0x07) => Op::LdVxDt,
+                (0xf, 0x0a) => Op::LdVxK,
+                (0xf, 0x15) => Op::LdDtVx,
+                (0xf, 0x18) => Op::LdStVx,
+                (0xf, 0x1e) => Op::AddIVx,
+                (0xf, 0x29) => Op::LdFVx,
+                (0xf, 0x33) => Op::LdBVx,
+                (0xf, 0x55) => Op::LdIVx,
+                (0xf, 0x65) => Op::LdVxI,
+                _ => Op::Unknown
+            }
+        }
+    }
+
+    // An instruction in the decoded instruction cache.
+    #[derive(Clone, Copy)]
+    struct DecodedInstr {
+        op: Op,
+        first_byte: u8,
+        second_byte: u8,
+    }
+
+    // How the JIT runs the blocks it compiles.
+    #[derive(Clone, Copy, PartialEq)]
+    pub enum JitMode {
+        // As native code.
+        Native,
+        // As native code and, from the same state, with the interpreter, comparing the results (slow, to check the JIT).
+        Lockstep,
+    }
+
+    impl JitMode {
+        pub fn from_name(name: &str) -> Option<Self> {
+            match name {
+                "native" => Some(JitMode::Native),
+                "lockstep" => Some(JitMode::Lockstep),
+                _ => None
+            }
+        }
+
+        pub fn get_name(&self) -> &str {
+            match self {
+                JitMode::Native => "native",
+                JitMode::Lockstep => "lockstep"
+            }
+        }
+    }
+
+    // What the CPU does when a fault happens.
+    #[derive(Clone, Copy, PartialEq)]
+    pub enum FaultPolicy {
+        // Stops executing (until the machine is reset).
+        Halt,
+        // Wraps the address around the RAM (or the stack pointer around the stack) and goes on.
+        Wrap,
+        // Stops executing and opens the debugger.
+        Debug,
+    }
+
+    impl FaultPolicy {
+        pub fn from_name(name: &str) -> Option<Self> {
+            match name {
+                "halt" => Some(FaultPolicy::Halt),
+                "wrap" => Some(FaultPolicy::Wrap),
+                "debug" => Some(FaultPolicy::Debug),
+                _ => None
+            }
+        }
+
+        pub fn get_name(&self) -> &str {
+            match self {
+                FaultPolicy::Halt => "halt",
+                FaultPolicy::Wrap => "wrap",
+                FaultPolicy::Debug => "debug"
+            }
+        }
+    }
+
+    // What the CPU does when it finds an instruction it doesn't know.
+    #[derive(Clone, Copy, PartialEq)]
+    pub enum UnknownOpcodePolicy {
+        // Skips it silently.
+        Ignore,
+        // Skips it, logging it the first time it's found at each address.
+        LogOnce,
+        // Stops executing (until the machine is reset).
+        Halt,
+        // Stops executing and opens the debugger.
+        Debug,
+    }
+
+    impl UnknownOpcodePolicy {
+        pub fn from_name(name: &str) -> Option<Self> {
+            match name {
+                "ignore" => Some(UnknownOpcodePolicy::Ignore),
+                "log" => Some(UnknownOpcodePolicy::LogOnce),
+                "halt" => Some(UnknownOpcodePolicy::Halt),
+                "debug" => Some(UnknownOpcodePolicy::Debug),
+                _ => None
+            }
+        }
+
+        pub fn get_name(&self) -> &str {
+            match self {
+                UnknownOpcodePolicy::Ignore => "ignore",
+                UnknownOpcodePolicy::LogOnce => "log",
+                UnknownOpcodePolicy::Halt => "halt",
+                UnknownOpcodePolicy::Debug => "debug"
+            }
+        }
+    }
+
+    // Errors of the emulated program that would otherwise crash the emulator.
+    #[derive(Clone, Copy, PartialEq)]
+    pub enum CpuFault {
+        // 2nnn with 16 return addresses already in the stack.
+        StackOverflow,
+        // 00EE with an empty stack.
+        StackUnderflow,
+        // The PC (given) points past the last instruction that fits in the RAM.
+        PcOutOfBounds(u16),
+        // An instruction accessed, through I, an address (given) past the end of the RAM.
+        MemoryOutOfBounds(u16),
+        // An instruction that isn't part of the CHIP-8 instruction set (with the halt or debug unknown opcode policy).
+        UnknownOpcode,
+    }
+
+    impl CpuFault {
+        #[cfg(feature = "std")]
+        pub fn get_description(&self) -> String {
+            match self {
+                CpuFault::StackOverflow => String::from("stack overflow (more than 16 nested subroutines)"),
+                CpuFault::StackUnderflow => String::from("stack underflow (return without a subroutine call)"),
+                CpuFault::PcOutOfBounds(addr) => format!("PC out of bounds ({addr:#06X})"),
+                CpuFault::MemoryOutOfBounds(addr) => format!("memory access out of bounds ({addr:#06X})"),
+                CpuFault::UnknownOpcode => String::from("unknown instruction")
+            }
+        }
+    }
+
+    // A fault alongside the instruction that caused it.
+    #[derive(Clone)]
+    pub struct FaultInfo {
+        fault: CpuFault,
+        pc: u16,
+        // None when the instruction couldn't be fetched.
+        opcode: Option<u16>,
+        // How the fault is handled (the fault policy, or the unknown opcode policy for unknown opcodes).
+        policy: FaultPolicy,
+        // The registers when the fault happened.
+        #[cfg(feature = "std")]
+        registers: String,
+    }
+
+    impl FaultInfo {
+        pub fn get_fault(&self) -> CpuFault {
+            self.fault
+        }
+
+        pub fn get_pc(&self) -> u16 {
+            self.pc
+        }
+
+        pub fn get_opcode(&self) -> Option<u16> {
+            self.opcode
+        }
+
+        pub fn get_policy(&self) -> FaultPolicy {
+            self.policy
+        }
+
+        // The fault, PC, opcode and registers formatted for display.
+        #[cfg(feature = "std")]
+        pub fn get_report(&self) -> String {
+            let opcode = self.opcode.map_or(String::from("----"), |opcode| format!("{opcode:04X}"));
+            format!("CPU fault: {}\nPC={:04X} opcode={opcode}\n{}",
+                self.fault.get_description(),
+                self.pc,
+                self.registers)
         }
     }
 
@@ -427,4 +1126,229 @@ pub mod cpu {
         Halted,
         NotHalted,
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{ CpuController, CpuFault, FaultPolicy };
+        use crate::emu::display::screen::{ Screen, ScreenController };
+        use crate::emu::input::keyboard::{ Keyboard, KeyboardController };
+        use crate::emu::memory::memory::{ Memory, MemoryController };
+
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+
+        // Loaded like a ROM file, with the sprites of the digits at 0x000.
+        fn ram_with_rom(rom: &[u8]) -> MemoryController {
+            let mut mem_ctrl = MemoryController::new(Memory::new());
+            mem_ctrl.init_ram_from_bytes(rom).unwrap();
+            mem_ctrl
+        }
+
+        fn new_cpu(mem_ctrl: &MemoryController, fault_policy: FaultPolicy) -> CpuController {
+            CpuController::with_rng(mem_ctrl, 20, true, true, fault_policy, StdRng::seed_from_u64(1))
+        }
+
+        // Executes the instructions one by one, like Emulator::step.
+        fn step(cpu_ctrl: &mut CpuController, mem_ctrl: &mut MemoryController, instrs: usize) {
+            let mut screen_ctrl = ScreenController::new(Screen::new());
+            let mut keyboard_ctrl = KeyboardController::new(Keyboard::new());
+            for _ in 0..instrs {
+                cpu_ctrl.fetch_exec(&mut screen_ctrl, mem_ctrl, &mut keyboard_ctrl);
+            }
+        }
+
+        // Checks that the CPU is halted by the fault of the instruction at pc, with the registers of that moment.
+        fn assert_halted(cpu_ctrl: &mut CpuController, mem_ctrl: &mut MemoryController, fault: CpuFault, pc: u16, opcode: Option<u16>) {
+            let registers = mem_ctrl.get_v_regs();
+            let fault_info = cpu_ctrl.get_fault().expect("the CPU isn't halted");
+            assert!(fault_info.get_fault() == fault);
+            assert_eq!(fault_info.get_pc(), pc);
+            assert_eq!(fault_info.get_opcode(), opcode);
+            assert!(fault_info.get_policy() == FaultPolicy::Halt);
+            #[cfg(feature = "std")]
+            assert!(fault_info.get_report().ends_with(&mem_ctrl.dump_registers()));
+            assert!(cpu_ctrl.take_new_fault().is_some());
+
+            // Nothing is executed until the fault is cleared.
+            step(cpu_ctrl, mem_ctrl, 1);
+            assert_eq!(mem_ctrl.get_pc(), pc);
+            assert_eq!(mem_ctrl.get_v_regs(), registers);
+        }
+
+        // Checks that the fault was reported once and the CPU went on.
+        fn assert_wrapped(cpu_ctrl: &mut CpuController, fault: CpuFault) {
+            assert!(cpu_ctrl.get_fault().is_none());
+            let fault_info = cpu_ctrl.take_new_fault().expect("the fault wasn't reported");
+            assert!(fault_info.get_fault() == fault);
+            assert!(fault_info.get_policy() == FaultPolicy::Wrap);
+        }
+
+        #[test]
+        fn faults_on_stack_overflow() {
+            let rom = [
+                0x60, 0x2a, // 200: LD V0, 2A
+                0x22, 0x02, // 202: CALL 202
+            ];
+            let mut mem_ctrl = ram_with_rom(&rom);
+            let mut cpu_ctrl = new_cpu(&mem_ctrl, FaultPolicy::Halt);
+            step(&mut cpu_ctrl, &mut mem_ctrl, 1 + 17);
+            assert_eq!(mem_ctrl.get_sp(), 16);
+            assert_halted(&mut cpu_ctrl, &mut mem_ctrl, CpuFault::StackOverflow, 0x202, Some(0x2202));
+
+            // The stack pointer wraps to the first return address.
+            let mut mem_ctrl = ram_with_rom(&rom);
+            let mut cpu_ctrl = new_cpu(&mem_ctrl, FaultPolicy::Wrap);
+            step(&mut cpu_ctrl, &mut mem_ctrl, 1 + 17);
+            assert_wrapped(&mut cpu_ctrl, CpuFault::StackOverflow);
+            assert_eq!(mem_ctrl.get_sp(), 1);
+            assert_eq!(mem_ctrl.get_pc(), 0x202);
+        }
+
+        #[test]
+        fn faults_on_stack_underflow() {
+            let rom = [
+                0x60, 0x2a, // 200: LD V0, 2A
+                0x00, 0xee, // 202: RET
+            ];
+            let mut mem_ctrl = ram_with_rom(&rom);
+            let mut cpu_ctrl = new_cpu(&mem_ctrl, FaultPolicy::Halt);
+            step(&mut cpu_ctrl, &mut mem_ctrl, 2);
+            assert_halted(&mut cpu_ctrl, &mut mem_ctrl, CpuFault::StackUnderflow, 0x202, Some(0x00ee));
+
+            // The stack pointer wraps to the last (empty) return address.
+            let mut mem_ctrl = ram_with_rom(&rom);
+            let mut cpu_ctrl = new_cpu(&mem_ctrl, FaultPolicy::Wrap);
+            step(&mut cpu_ctrl, &mut mem_ctrl, 2);
+            assert_wrapped(&mut cpu_ctrl, CpuFault::StackUnderflow);
+            assert_eq!(mem_ctrl.get_sp(), 15);
+            assert_eq!(mem_ctrl.get_pc(), 0x002);
+        }
+
+        #[test]
+        fn faults_on_pc_out_of_bounds() {
+            let rom = [
+                0x60, 0x2a, // 200: LD V0, 2A
+                0x1f, 0xff, // 202: JP FFF
+            ];
+            let mut mem_ctrl = ram_with_rom(&rom);
+            mem_ctrl.set_ram(0xfff, 0x7a);
+            let mut cpu_ctrl = new_cpu(&mem_ctrl, FaultPolicy::Halt);
+            step(&mut cpu_ctrl, &mut mem_ctrl, 3);
+            assert_halted(&mut cpu_ctrl, &mut mem_ctrl, CpuFault::PcOutOfBounds(0xfff), 0xfff, None);
+
+            // The instruction takes its second byte from 0x000 (F0, the first byte of the sprite of 0): ADD VA, F0.
+            let mut mem_ctrl = ram_with_rom(&rom);
+            mem_ctrl.set_ram(0xfff, 0x7a);
+            let mut cpu_ctrl = new_cpu(&mem_ctrl, FaultPolicy::Wrap);
+            step(&mut cpu_ctrl, &mut mem_ctrl, 3);
+            assert_wrapped(&mut cpu_ctrl, CpuFault::PcOutOfBounds(0xfff));
+            assert_eq!(mem_ctrl.get_v(0xa), 0xf0);
+        }
+
+        #[test]
+        fn faults_on_memory_out_of_bounds() {
+            // LD I, FFE then an instruction accessing the RAM through I, with the last address it accesses.
+            let cases = [
+                (0xd015, 0x1002), // DRW V0, V1, 5
+                (0xf033, 0x1000), // LD B, V0
+                (0xf355, 0x1001), // LD [I], V3
+                (0xf365, 0x1001), // LD V3, [I]
+            ];
+            for (opcode, last_addr) in cases {
+                let mut mem_ctrl = ram_with_rom(&[0xaf, 0xfe, (opcode >> 8) as u8, opcode as u8]);
+                let ram = mem_ctrl.get_ram().to_vec();
+                let mut cpu_ctrl = new_cpu(&mem_ctrl, FaultPolicy::Halt);
+                step(&mut cpu_ctrl, &mut mem_ctrl, 2);
+                assert_halted(&mut cpu_ctrl, &mut mem_ctrl, CpuFault::MemoryOutOfBounds(last_addr), 0x202, Some(opcode));
+                assert_eq!(mem_ctrl.get_ram()[..], ram[..]);
+                assert_eq!(mem_ctrl.get_i(), 0xffe);
+            }
+        }
+
+        #[test]
+        fn wraps_memory_accesses_past_the_end() {
+            // The sprite takes its last 3 bytes from the sprite of 0 (F0 90 90), so drawing it twice erases pixels.
+            let mut mem_ctrl = ram_with_rom(&[
+                0xaf, 0xfe, // 200: LD I, FFE
+                0xd0, 0x15, // 202: DRW V0, V1, 5
+                0xd0, 0x15, // 204: DRW V0, V1, 5
+            ]);
+            let mut cpu_ctrl = new_cpu(&mem_ctrl, FaultPolicy::Wrap);
+            step(&mut cpu_ctrl, &mut mem_ctrl, 3);
+            assert_wrapped(&mut cpu_ctrl, CpuFault::MemoryOutOfBounds(0x1002));
+            assert_eq!(mem_ctrl.get_v(0xf), 1);
+
+            let mut mem_ctrl = ram_with_rom(&[
+                0x60, 0x7b, // 200: LD V0, 123
+                0xaf, 0xfe, // 202: LD I, FFE
+                0xf0, 0x33, // 204: LD B, V0
+            ]);
+            let mut cpu_ctrl = new_cpu(&mem_ctrl, FaultPolicy::Wrap);
+            step(&mut cpu_ctrl, &mut mem_ctrl, 3);
+            assert_wrapped(&mut cpu_ctrl, CpuFault::MemoryOutOfBounds(0x1000));
+            assert_eq!(mem_ctrl.get_ram()[0xffe..], [1, 2]);
+            assert_eq!(mem_ctrl.get_ram()[0], 3);
+
+            let mut mem_ctrl = ram_with_rom(&[
+                0x60, 0x01, // 200: LD V0, 1
+                0x61, 0x02, // 202: LD V1, 2
+                0x62, 0x03, // 204: LD V2, 3
+                0x63, 0x04, // 206: LD V3, 4
+                0xaf, 0xfe, // 208: LD I, FFE
+                0xf3, 0x55, // 20A: LD [I], V3
+            ]);
+            let mut cpu_ctrl = new_cpu(&mem_ctrl, FaultPolicy::Wrap);
+            step(&mut cpu_ctrl, &mut mem_ctrl, 6);
+            assert_wrapped(&mut cpu_ctrl, CpuFault::MemoryOutOfBounds(0x1001));
+            assert_eq!(mem_ctrl.get_ram()[0xffe..], [1, 2]);
+            assert_eq!(mem_ctrl.get_ram()[..2], [3, 4]);
+            // The I increment quirk wraps too.
+            assert_eq!(mem_ctrl.get_i(), 0x1002);
+
+            let mut mem_ctrl = ram_with_rom(&[
+                0xaf, 0xfe, // 200: LD I, FFE
+                0xf3, 0x65, // 202: LD V3, [I]
+            ]);
+            mem_ctrl.set_ram(0xffe, 1);
+            mem_ctrl.set_ram(0xfff, 2);
+            let mut cpu_ctrl = new_cpu(&mem_ctrl, FaultPolicy::Wrap);
+            step(&mut cpu_ctrl, &mut mem_ctrl, 2);
+            assert_wrapped(&mut cpu_ctrl, CpuFault::MemoryOutOfBounds(0x1001));
+            assert_eq!(mem_ctrl.get_v_regs()[..4], [1, 2, 0xf0, 0x90]);
+        }
+
+        #[test]
+        fn decodes_code_written_over_by_fx55() {
+            let mut mem_ctrl = ram_with_rom(&[
+                0x22, 0x10, // 200: CALL 210
+                0x60, 0x7a, // 202: LD V0, 7A
+                0x61, 0x05, // 204: LD V1, 5
+                0xa2, 0x10, // 206: LD I, 210
+                0xf1, 0x55, // 208: LD [I], V1 (210: ADD VA, 5)
+                0x22, 0x10, // 20A: CALL 210
+                0x12, 0x0c, // 20C: JP 20C
+                0x00, 0x00,
+                0x7a, 0x01, // 210: ADD VA, 1
+                0x00, 0xee, // 212: RET
+            ]);
+            let mut cpu_ctrl = new_cpu(&mem_ctrl, FaultPolicy::Halt);
+
+            step(&mut cpu_ctrl, &mut mem_ctrl, 10);
+            assert_eq!(mem_ctrl.get_pc(), 0x20c);
+            assert_eq!(mem_ctrl.get_v(0xa), 1 + 5);
+        }
+
+        #[test]
+        fn decodes_again_on_another_ram() {
+            // Neither RAM had code written over, so both have the same count of code writes.
+            let mut first_mem_ctrl = ram_with_rom(&[0x7a, 0x01]); // 200: ADD VA, 1
+            let mut second_mem_ctrl = ram_with_rom(&[0x7a, 0x05]); // 200: ADD VA, 5
+            let mut cpu_ctrl = new_cpu(&first_mem_ctrl, FaultPolicy::Halt);
+
+            step(&mut cpu_ctrl, &mut first_mem_ctrl, 1);
+            step(&mut cpu_ctrl, &mut second_mem_ctrl, 1);
+            assert_eq!(first_mem_ctrl.get_v(0xa), 1);
+            assert_eq!(second_mem_ctrl.get_v(0xa), 5);
+        }
+    }
 }
@@ -1,10 +1,34 @@
 // SPDX-License-Identifier: MIT
 // SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
 
+#[allow(clippy::module_inception)]
 pub mod memory {
-    use std::fs::File;
-    use std::io::Read;
+    use crate::error::emu_error::EmuError;
+
+    use core::ops::Range;
+    use core::sync::atomic::{ AtomicUsize, Ordering };
+    #[cfg(feature = "std")]
+    use sha1_smol::Sha1;
+    #[cfg(feature = "desktop")]
+    use std::fs;
+    #[cfg(feature = "desktop")]
+    use std::path::{ Path, PathBuf };
+
+    // From 0x200 up to 0xe9f, since the last 352 bytes were used by the interpreter's stack,
+    // work area and display buffer on the original machine.
+    pub const MAX_ROM_SIZE: usize = 3232;
+
+    pub const RAM_SIZE: usize = 4096;
+    // Where the ROM is loaded and the execution starts.
+    pub const PROGRAM_START_ADDR: u16 = 0x200;
+    pub const STACK_SIZE: usize = 16;
+    // Size of the state of a Memory as bytes (see Memory::to_bytes).
+    pub const MEMORY_BYTES: usize = RAM_SIZE + STACK_SIZE * 2 + 16 + 7;
     
+    // The id of the next MemoryController, so the CodeVersion of one never matches another's.
+    static NEXT_RAM_ID: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Clone)]
     pub struct Memory {
         // Addresses from 0x000 (0) to 0x1ff (511) were originally occupied by the interpreter,
         // which means most common Chip-8 programs start at address 0x200 (512).
@@ -13,11 +37,8 @@ pub mod memory {
         // The stack allows for up to 16 nested subroutines.
         stack: [u16;16],
 
-        // General purpose registers.
-        v0: u8, v1: u8, v2: u8, v3: u8,
-        v4: u8, v5: u8, v6: u8, v7: u8,
-        v8: u8, v9: u8, va: u8, vb: u8,
-        vc: u8, vd: u8, ve: u8, vf: u8,
+        // General purpose registers (V0 to VF).
+        v: [u8;16],
 
         // Delay timer: If non-zero (activated), it will be decremented by 1 at 60Hz (tied to the screen's refresh rate)
         // until it reaches zero (deactivated).
@@ -37,88 +58,243 @@ pub mod memory {
         i: u16,
     }
 
+    impl Default for Memory {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
     impl Memory {
         pub fn new() -> Self {
             Self {
                 ram:   [0;4096],
                 stack: [0;16],
-                v0: 0, v1: 0, v2: 0, v3: 0,
-                v4: 0, v5: 0, v6: 0, v7: 0,
-                v8: 0, v9: 0, va: 0, vb: 0,
-                vc: 0, vd: 0, ve: 0, vf: 0,
+                v:     [0;16],
                 dt: 0,
                 st: 0,
-                pc: 0x200, // Default initial address for the program counter.
+                pc: PROGRAM_START_ADDR,
                 sp: 0,
                 i:  0,
             }
         }
+
+        // The whole state as bytes, e.g. for save states kept by a frontend:
+        // the RAM, the stack, V0 to VF, DT, ST, PC, SP and I (the 16-bit values are big-endian).
+        pub fn to_bytes(&self) -> [u8;MEMORY_BYTES] {
+            let mut bytes = [0;MEMORY_BYTES];
+            let mut offset = 0;
+            let mut put = |data: &[u8]| {
+                bytes[offset..offset + data.len()].copy_from_slice(data);
+                offset += data.len();
+            };
+
+            put(&self.ram);
+            for addr in self.stack {
+                put(&addr.to_be_bytes());
+            }
+            put(&self.v);
+            put(&[self.dt, self.st]);
+            put(&self.pc.to_be_bytes());
+            put(&[self.sp]);
+            put(&self.i.to_be_bytes());
+            bytes
+        }
+
+        // Reads a state written by to_bytes(). Returns None if it has another size or an invalid stack pointer.
+        pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+            if bytes.len() != MEMORY_BYTES {
+                return None;
+            }
+
+            let mut offset = 0;
+            let mut take = |len: usize| {
+                offset += len;
+                &bytes[offset - len..offset]
+            };
+
+            let mut mem = Memory::new();
+            mem.ram.copy_from_slice(take(RAM_SIZE));
+            for addr in mem.stack.iter_mut() {
+                *addr = u16::from_be_bytes([take(1)[0], take(1)[0]]);
+            }
+            mem.v.copy_from_slice(take(16));
+            mem.dt = take(1)[0];
+            mem.st = take(1)[0];
+            mem.pc = u16::from_be_bytes([take(1)[0], take(1)[0]]);
+            mem.sp = take(1)[0];
+            mem.i = u16::from_be_bytes([take(1)[0], take(1)[0]]);
+
+            // The CPU relies on the stack pointer being inside the stack.
+            if mem.sp as usize > STACK_SIZE {
+                return None;
+            }
+            Some(mem)
+        }
+    }
+
+    // Identifies the code in a RAM: the MemoryController holding it and how many times it was written over.
+    // Decoded (or compiled) instructions are valid while the CodeVersion they were taken at is the current one.
+    #[derive(Clone, Copy, PartialEq)]
+    pub struct CodeVersion {
+        ram_id: usize,
+        code_writes: u64,
     }
 
+    // The RAM and registers of the machine. It doesn't hold decoded instructions: a CPU keeps its own and drops them
+    // when the CodeVersion changes, so any CpuController can run with it (see CpuController::decode_instr_at).
     pub struct MemoryController {
         mem: Memory,
+        // SHA-1 (hex) of the loaded ROM, used to identify it regardless of the file name.
+        #[cfg(feature = "std")]
+        rom_sha1: String,
+        // The ROM file, read again by a hard reset.
+        #[cfg(feature = "desktop")]
+        rom_path: PathBuf,
+        rom_size: usize,
+        // The addresses whose instruction was decoded since the last write over code, a bit per address.
+        // The decoded instructions themselves (and whether they're valid) are kept by the CPU: these bits only tell
+        // which writes are over code, so the CodeVersion can signal the CPU (and the JIT) to decode them again.
+        code_addrs: [u64;RAM_SIZE / 64],
+        ram_id: usize,
+        code_writes: u64,
     }
 
     impl MemoryController {
         pub fn new(mem: Memory) -> Self {
-            Self { mem, }
+            Self {
+                mem,
+                #[cfg(feature = "std")]
+                rom_sha1: String::new(),
+                #[cfg(feature = "desktop")]
+                rom_path: PathBuf::new(),
+                rom_size: 0,
+                code_addrs: [0;RAM_SIZE / 64],
+                ram_id: NEXT_RAM_ID.fetch_add(1, Ordering::Relaxed),
+                code_writes: 0,
+            }
+        }
+
+        #[cfg(feature = "desktop")]
+        pub fn init_ram(&mut self, rom_path: &Path) -> Result<(), EmuError> {
+            let rom = MemoryController::read_rom(rom_path)?;
+            self.rom_path = rom_path.to_path_buf();
+            self.load_rom(&rom);
+            self.load_hex_digits();
+            Ok(())
+        }
+
+        // Loads a ROM that is already in memory, e.g. when embedding the emulator.
+        // The RAM and registers are reset. Since there's no file, the ROM can't be hard reset afterwards.
+        pub fn init_ram_from_bytes(&mut self, rom: &[u8]) -> Result<(), EmuError> {
+            if rom.len() > MAX_ROM_SIZE {
+                return Err(EmuError::RomTooLarge {
+                    #[cfg(feature = "std")]
+                    path: None,
+                    size: rom.len(),
+                    max_size: MAX_ROM_SIZE
+                });
+            }
+
+            self.mem = Memory::new();
+            #[cfg(feature = "desktop")]
+            {
+                self.rom_path = PathBuf::new();
+            }
+            self.load_rom(rom);
+            self.load_hex_digits();
+            Ok(())
+        }
+
+        // Checks that the ROM file can be loaded, without loading it.
+        #[cfg(feature = "desktop")]
+        pub fn check_rom(rom_path: &Path) -> Result<(), EmuError> {
+            MemoryController::read_rom(rom_path).map(|_| ())
         }
 
-        pub fn init_ram(&mut self, rom_path: &str) {
-            self.load_rom(rom_path);
+        // Like the reset of the original machine, the RAM (and so the ROM) is kept,
+        // while the registers, stack and timers go back to their initial values.
+        pub fn soft_reset(&mut self) {
+            let ram = self.mem.ram;
+            self.mem = Memory::new();
+            self.mem.ram = ram;
+        }
+
+        // Clears the RAM and reads the ROM file again, picking up any change made to it.
+        // If the file can't be loaded, the machine is left untouched.
+        #[cfg(feature = "desktop")]
+        pub fn hard_reset(&mut self) -> Result<(), EmuError> {
+            let rom = MemoryController::read_rom(&self.rom_path)?;
+            self.mem = Memory::new();
+            self.load_rom(&rom);
             self.load_hex_digits();
+            Ok(())
+        }
+
+        // Copy of the whole machine state (RAM, stack, registers and timers), e.g. for save states.
+        pub fn snapshot(&self) -> Memory {
+            self.mem.clone()
         }
 
-        pub fn get_ram(&self) -> [u8;4096] {
-            self.mem.ram
+        pub fn restore(&mut self, mem: &Memory) {
+            self.mem = mem.clone();
+            self.set_code_written();
+        }
+
+        #[cfg(feature = "std")]
+        pub fn get_rom_sha1(&self) -> &str {
+            &self.rom_sha1
+        }
+
+        #[cfg(feature = "desktop")]
+        pub fn get_rom_path(&self) -> &Path {
+            &self.rom_path
+        }
+
+        // The addresses where the ROM was loaded.
+        pub fn get_rom_range(&self) -> Range<usize> {
+            PROGRAM_START_ADDR as usize..PROGRAM_START_ADDR as usize + self.rom_size
+        }
+
+        pub fn get_ram(&self) -> &[u8;4096] {
+            &self.mem.ram
         }
 
         pub fn set_ram(&mut self, index: usize, val: u8) {
             self.mem.ram[index] = val;
+            // The instructions that start at the address or right before it (at 0xfff when it's 0x000) aren't the same anymore.
+            let prev_index = (index + RAM_SIZE - 1) % RAM_SIZE;
+            if self.is_code_addr(index) || self.is_code_addr(prev_index) {
+                self.set_code_written();
+            }
         }
 
-        pub fn get_v(&mut self, nibble: u8) -> u8 {
-            match nibble {
-                0 =>   self.mem.v0,
-                1 =>   self.mem.v1,
-                2 =>   self.mem.v2,
-                3 =>   self.mem.v3,
-                4 =>   self.mem.v4,
-                5 =>   self.mem.v5,
-                6 =>   self.mem.v6,
-                7 =>   self.mem.v7,
-                8 =>   self.mem.v8,
-                9 =>   self.mem.v9,
-                0xa => self.mem.va,
-                0xb => self.mem.vb,
-                0xc => self.mem.vc,
-                0xd => self.mem.vd,
-                0xe => self.mem.ve,
-                0xf => self.mem.vf,
-                _ => 0
-            }
+        // Changes when decoded instructions are written over (or the whole RAM is replaced), and differs between RAMs.
+        // When it changes, the CPU decodes every instruction again and the JIT checks whether its blocks are still the same.
+        pub fn get_code_version(&self) -> CodeVersion {
+            CodeVersion { ram_id: self.ram_id, code_writes: self.code_writes }
+        }
+
+        fn is_code_addr(&self, addr: usize) -> bool {
+            self.code_addrs[addr / 64] & (1 << (addr % 64)) != 0
+        }
+
+        // Called by the CPU when it decodes the instruction at the address, so writes over it are signaled.
+        pub fn set_code_addr(&mut self, addr: usize) {
+            self.code_addrs[addr / 64] |= 1 << (addr % 64);
+        }
+
+        // Every decoded instruction is stale: the CPU decodes (and marks) them again as they're executed.
+        fn set_code_written(&mut self) {
+            self.code_addrs = [0;RAM_SIZE / 64];
+            self.code_writes += 1;
+        }
+
+        pub fn get_v(&self, nibble: u8) -> u8 {
+            self.mem.v[(nibble & 0xf) as usize]
         }
 
         pub fn set_v(&mut self, nibble: u8, val: u8) {
-            match nibble {
-                0 =>   self.mem.v0 = val,
-                1 =>   self.mem.v1 = val,
-                2 =>   self.mem.v2 = val,
-                3 =>   self.mem.v3 = val,
-                4 =>   self.mem.v4 = val,
-                5 =>   self.mem.v5 = val,
-                6 =>   self.mem.v6 = val,
-                7 =>   self.mem.v7 = val,
-                8 =>   self.mem.v8 = val,
-                9 =>   self.mem.v9 = val,
-                0xa => self.mem.va = val,
-                0xb => self.mem.vb = val,
-                0xc => self.mem.vc = val,
-                0xd => self.mem.vd = val,
-                0xe => self.mem.ve = val,
-                0xf => self.mem.vf = val,
-                _ => ()
-            }
+            self.mem.v[(nibble & 0xf) as usize] = val;
         }
 
         pub fn get_dt(&self) -> u8 {
@@ -163,7 +339,7 @@ pub mod memory {
         }
 
         pub fn inc_pc_by(&mut self, val: u16) {
-            self.mem.pc += val;
+            self.mem.pc = self.mem.pc.wrapping_add(val);
         }
 
         pub fn get_i(&self) -> u16 {
@@ -175,21 +351,54 @@ pub mod memory {
         }
 
         pub fn inc_i_by(&mut self, val:u16) {
-            self.mem.i += val;
+            self.mem.i = self.mem.i.wrapping_add(val);
+        }
+
+        // The amount of return addresses in the stack (0 to 16).
+        pub fn get_sp(&self) -> u8 {
+            self.mem.sp
         }
 
+        pub fn set_sp(&mut self, val: u8) {
+            self.mem.sp = val;
+        }
+
+        // The stack must have room for the address (sp < 16), which is checked by the CPU.
         pub fn stack_push(&mut self, new_pc_addr: u16) {
             self.mem.sp += 1;
             self.mem.stack[(self.mem.sp - 1) as usize] = self.mem.pc;
             self.mem.pc = new_pc_addr;
         }
 
+        // The stack must not be empty (sp > 0), which is checked by the CPU.
         pub fn stack_pop(&mut self) {
             self.mem.pc = self.mem.stack[(self.mem.sp - 1) as usize];
             self.mem.stack[(self.mem.sp - 1) as usize] = 0;
             self.mem.sp -= 1;
         }
 
+        // The registers V0 to VF.
+        pub fn get_v_regs(&self) -> [u8;16] {
+            self.mem.v
+        }
+
+        pub fn set_v_regs(&mut self, val: [u8;16]) {
+            self.mem.v = val;
+        }
+
+        // The registers formatted for display, e.g. in a fault report or in the debugger.
+        #[cfg(feature = "std")]
+        pub fn dump_registers(&self) -> String {
+            let v_regs: Vec<String> = self.get_v_regs().iter()
+                .enumerate()
+                .map(|(i, val)| format!("V{i:X}={val:02X}"))
+                .collect();
+            format!("{}\n{}\nPC={:04X} I={:04X} SP={} DT={:02X} ST={:02X}",
+                v_regs[..8].join(" "),
+                v_regs[8..].join(" "),
+                self.mem.pc, self.mem.i, self.mem.sp, self.mem.dt, self.mem.st)
+        }
+
         // Loads the default sprites for the hexadecimal digits (0 to f) into memory starting at address 0,
         // with each bit of the byte representing the state of a pixel (ON/OFF).
         //
@@ -218,26 +427,31 @@ pub mod memory {
                 0xF0, 0x80, 0xF0, 0x80, 0xF0,
                 0xF0, 0x80, 0xF0, 0x80, 0x80];
             
-            for i in 0..hex_digits.len() {
-                self.mem.ram[i] = hex_digits[i];
-            }
+            self.mem.ram[..hex_digits.len()].copy_from_slice(&hex_digits);
         }
 
-        fn load_rom(&mut self, path: &str) {
-            let mut byte_vec = Vec::new();
-            File::open(path).unwrap()
-                .read_to_end(&mut byte_vec).unwrap();
+        // Reads a ROM file, checking that it fits in the RAM.
+        #[cfg(feature = "desktop")]
+        pub fn read_rom(path: &Path) -> Result<Vec<u8>, EmuError> {
+            let rom = fs::read(path)
+                .map_err(|source| EmuError::RomRead { path: path.to_path_buf(), source })?;
 
-            
-            if byte_vec.len() > 3232 {
-                panic!("Selected ROM size is greater than the available RAM!");
+            if rom.len() > MAX_ROM_SIZE {
+                return Err(EmuError::RomTooLarge { path: Some(path.to_path_buf()), size: rom.len(), max_size: MAX_ROM_SIZE });
             }
+            Ok(rom)
+        }
 
-            let mut address = self.mem.pc as usize;
-            for byte in byte_vec {
-                self.mem.ram[address] = byte;
-                address += 1;
+        fn load_rom(&mut self, rom: &[u8]) {
+            #[cfg(feature = "std")]
+            {
+                self.rom_sha1 = Sha1::from(rom).digest().to_string();
             }
+            self.rom_size = rom.len();
+
+            let address = PROGRAM_START_ADDR as usize;
+            self.mem.ram[address..address + rom.len()].copy_from_slice(rom);
+            self.set_code_written();
         }
     }
 }
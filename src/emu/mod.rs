@@ -1,9 +1,46 @@
 // SPDX-License-Identifier: MIT
 // SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
 
+// The modules of the SDL frontend (and the ones that need files or the terminal) are only built with the "desktop" feature,
+// the ones that need an allocator with the "std" feature and the JIT with the "jit" feature.
+pub mod api;
+#[cfg(feature = "desktop")]
 pub mod audio;
+pub mod backend;
+#[cfg(feature = "desktop")]
+pub mod batch;
+#[cfg(feature = "desktop")]
 pub mod config;
+#[cfg(feature = "desktop")]
 pub mod core_emu;
+#[cfg(feature = "std")]
+pub mod coverage;
+pub mod cycles;
+#[cfg(feature = "desktop")]
+pub mod database;
+#[cfg(feature = "desktop")]
+pub mod debugger;
+#[cfg(feature = "std")]
+pub mod disasm;
+pub mod display;
+pub mod font;
+#[cfg(feature = "std")]
+pub mod gym;
 pub mod input;
+#[cfg(feature = "jit")]
+pub mod jit;
 pub mod logic;
 pub mod memory;
+#[cfg(feature = "desktop")]
+pub mod overlay;
+#[cfg(feature = "desktop")]
+pub mod profile;
+#[cfg(feature = "std")]
+pub mod profiler;
+pub mod sys_call;
+#[cfg(feature = "desktop")]
+pub mod timing;
+#[cfg(feature = "desktop")]
+pub mod trace;
+#[cfg(feature = "desktop")]
+pub mod watcher;
@@ -0,0 +1,300 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// On-screen menu shown on top of the game (Esc or the gamepad's Start button).
+// It only keeps the state of the menu: the actions are carried out by the emulator loop
+// and the lines are drawn by the SDL wrapper with the built-in bitmap font.
+pub mod menu {
+    use crate::emu::input::keyboard::CustomKeyEvent;
+    use crate::util::utilities::FileSelectionUtil;
+
+    use std::path::PathBuf;
+
+    // Amount of ROMs listed at once in the "Load ROM" page.
+    const VISIBLE_ROMS: usize = 15;
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum MenuItem {
+        Resume,
+        SoftReset,
+        HardReset,
+        LoadRom,
+        Speed,
+        Palette,
+        ShiftQuirk,
+        LoadStoreQuirk,
+        SaveState,
+        LoadState,
+        ShowPerf,
+        Quit,
+    }
+
+    const MENU_ITEMS: [MenuItem;12] = [
+        MenuItem::Resume,
+        MenuItem::SoftReset,
+        MenuItem::HardReset,
+        MenuItem::LoadRom,
+        MenuItem::Speed,
+        MenuItem::Palette,
+        MenuItem::ShiftQuirk,
+        MenuItem::LoadStoreQuirk,
+        MenuItem::SaveState,
+        MenuItem::LoadState,
+        MenuItem::ShowPerf,
+        MenuItem::Quit];
+
+    #[derive(PartialEq)]
+    enum MenuPage {
+        Main,
+        LoadRom,
+    }
+
+    // What the emulator loop must do after a menu interaction.
+    pub enum MenuAction {
+        SoftReset,
+        HardReset,
+        LoadRom(PathBuf),
+        SlowDown,
+        SpeedUp,
+        // Moves through the built-in palettes (-1 or 1).
+        CyclePalette(i32),
+        ToggleShiftQuirk,
+        ToggleLoadStoreQuirk,
+        SaveState,
+        LoadState,
+        Quit,
+    }
+
+    // The current values shown by the menu.
+    pub struct MenuStatus {
+        pub speed_name: String,
+        pub palette_name: String,
+        pub bit_shift_instructions_use_vy: bool,
+        pub store_read_instructions_change_i: bool,
+        pub has_save_state: bool,
+        pub fps: f64,
+        pub ips: f64,
+    }
+
+    pub struct Overlay {
+        is_open: bool,
+        page: MenuPage,
+        selected_item: usize,
+        // The "Load ROM" page lists the files of this folder (read when the page is opened).
+        roms_folder: PathBuf,
+        roms: Vec<String>,
+        selected_rom: usize,
+        show_perf: bool,
+        // Feedback of the last action, e.g. "State saved.".
+        message: Option<String>,
+    }
+
+    impl Overlay {
+        pub fn new(roms_folder: PathBuf) -> Self {
+            Self {
+                is_open: false,
+                page: MenuPage::Main,
+                selected_item: 0,
+                roms_folder,
+                roms: Vec::new(),
+                selected_rom: 0,
+                show_perf: false,
+                message: None,
+            }
+        }
+
+        // Handles a menu event, returning the action the emulator must carry out (if any).
+        // While the menu is closed, only the event that opens it is handled.
+        pub fn handle_event(&mut self, event: &CustomKeyEvent) -> Option<MenuAction> {
+            if let CustomKeyEvent::ToggleMenu = event {
+                self.is_open = !self.is_open;
+                self.page = MenuPage::Main;
+                self.message = None;
+                return None;
+            }
+
+            if !self.is_open {
+                return None;
+            }
+
+            match self.page {
+                MenuPage::Main => self.handle_main_page_event(event),
+                MenuPage::LoadRom => self.handle_load_rom_page_event(event)
+            }
+        }
+
+        pub fn is_open(&self) -> bool {
+            self.is_open
+        }
+
+        pub fn get_show_perf(&self) -> bool {
+            self.show_perf
+        }
+
+        pub fn set_message(&mut self, message: &str) {
+            self.message = Some(String::from(message));
+        }
+
+        // The lines of text of the current page, where the selected line starts with ">".
+        pub fn get_lines(&self, status: &MenuStatus) -> Vec<String> {
+            let mut lines = vec![
+                String::from("RUSTED CHIP-8"),
+                Overlay::get_perf_line(status.fps, status.ips),
+                String::new()];
+
+            match self.page {
+                MenuPage::Main => {
+                    for (i, item) in MENU_ITEMS.iter().enumerate() {
+                        let cursor = if i == self.selected_item { ">" } else { " " };
+                        lines.push(format!("{cursor} {}", Overlay::get_item_text(*item, status, self.show_perf)));
+                    }
+                },
+                MenuPage::LoadRom => {
+                    lines.push(format!("LOAD ROM FROM '{}'", self.roms_folder.display()));
+                    if self.roms.is_empty() {
+                        lines.push(String::from("  NO ROMS FOUND."));
+                    }
+
+                    let first_rom = self.selected_rom.saturating_sub(VISIBLE_ROMS - 1);
+                    for (i, rom) in self.roms.iter().enumerate().skip(first_rom).take(VISIBLE_ROMS) {
+                        let cursor = if i == self.selected_rom { ">" } else { " " };
+                        lines.push(format!("{cursor} {rom}"));
+                    }
+                }
+            }
+
+            if let Some(message) = &self.message {
+                lines.push(String::new());
+                lines.push(message.clone());
+            }
+            lines
+        }
+
+        pub fn get_perf_line(fps: f64, ips: f64) -> String {
+            format!("FPS: {fps:.1}  IPS: {ips:.0}")
+        }
+
+        fn get_item_text(item: MenuItem, status: &MenuStatus, show_perf: bool) -> String {
+            let on_off = |val: bool| if val { "ON" } else { "OFF" };
+
+            match item {
+                MenuItem::Resume => String::from("RESUME"),
+                MenuItem::SoftReset => String::from("SOFT RESET"),
+                MenuItem::HardReset => String::from("HARD RESET (RELOAD ROM FILE)"),
+                MenuItem::LoadRom => String::from("LOAD ROM..."),
+                MenuItem::Speed => format!("SPEED: < {} >", status.speed_name),
+                MenuItem::Palette => format!("PALETTE: < {} >", status.palette_name),
+                MenuItem::ShiftQuirk =>
+                    format!("BIT SHIFT USES VY: < {} >", on_off(status.bit_shift_instructions_use_vy)),
+                MenuItem::LoadStoreQuirk =>
+                    format!("STORE/READ CHANGES I: < {} >", on_off(status.store_read_instructions_change_i)),
+                MenuItem::SaveState => String::from("SAVE STATE"),
+                MenuItem::LoadState =>
+                    format!("LOAD STATE{}", if status.has_save_state { "" } else { " (EMPTY)" }),
+                MenuItem::ShowPerf => format!("SHOW FPS/IPS: < {} >", on_off(show_perf)),
+                MenuItem::Quit => String::from("QUIT")
+            }
+        }
+
+        fn handle_main_page_event(&mut self, event: &CustomKeyEvent) -> Option<MenuAction> {
+            let item = MENU_ITEMS[self.selected_item];
+            match event {
+                CustomKeyEvent::MenuUp => {
+                    self.selected_item = (self.selected_item + MENU_ITEMS.len() - 1) % MENU_ITEMS.len();
+                    None
+                },
+                CustomKeyEvent::MenuDown => {
+                    self.selected_item = (self.selected_item + 1) % MENU_ITEMS.len();
+                    None
+                },
+                CustomKeyEvent::MenuBack => {
+                    self.is_open = false;
+                    None
+                },
+                CustomKeyEvent::MenuLeft | CustomKeyEvent::MenuRight => {
+                    let forward = matches!(event, CustomKeyEvent::MenuRight);
+                    match item {
+                        MenuItem::Speed if forward => Some(MenuAction::SpeedUp),
+                        MenuItem::Speed => Some(MenuAction::SlowDown),
+                        MenuItem::Palette => Some(MenuAction::CyclePalette(if forward { 1 } else { -1 })),
+                        MenuItem::ShiftQuirk => Some(MenuAction::ToggleShiftQuirk),
+                        MenuItem::LoadStoreQuirk => Some(MenuAction::ToggleLoadStoreQuirk),
+                        MenuItem::ShowPerf => {
+                            self.show_perf = !self.show_perf;
+                            None
+                        },
+                        _ => None
+                    }
+                },
+                CustomKeyEvent::MenuSelect => match item {
+                    MenuItem::Resume => {
+                        self.is_open = false;
+                        None
+                    },
+                    MenuItem::SoftReset => {
+                        self.is_open = false;
+                        Some(MenuAction::SoftReset)
+                    },
+                    MenuItem::HardReset => {
+                        self.is_open = false;
+                        Some(MenuAction::HardReset)
+                    },
+                    MenuItem::LoadRom => {
+                        self.open_load_rom_page();
+                        None
+                    },
+                    MenuItem::Speed => Some(MenuAction::SpeedUp),
+                    MenuItem::Palette => Some(MenuAction::CyclePalette(1)),
+                    MenuItem::ShiftQuirk => Some(MenuAction::ToggleShiftQuirk),
+                    MenuItem::LoadStoreQuirk => Some(MenuAction::ToggleLoadStoreQuirk),
+                    MenuItem::SaveState => Some(MenuAction::SaveState),
+                    MenuItem::LoadState => Some(MenuAction::LoadState),
+                    MenuItem::ShowPerf => {
+                        self.show_perf = !self.show_perf;
+                        None
+                    },
+                    MenuItem::Quit => Some(MenuAction::Quit)
+                },
+                _ => None
+            }
+        }
+
+        fn handle_load_rom_page_event(&mut self, event: &CustomKeyEvent) -> Option<MenuAction> {
+            match event {
+                CustomKeyEvent::MenuUp | CustomKeyEvent::MenuLeft if !self.roms.is_empty() => {
+                    self.selected_rom = (self.selected_rom + self.roms.len() - 1) % self.roms.len();
+                    None
+                },
+                CustomKeyEvent::MenuDown | CustomKeyEvent::MenuRight if !self.roms.is_empty() => {
+                    self.selected_rom = (self.selected_rom + 1) % self.roms.len();
+                    None
+                },
+                CustomKeyEvent::MenuSelect => {
+                    let rom = self.roms.get(self.selected_rom)?;
+                    self.is_open = false;
+                    self.page = MenuPage::Main;
+                    Some(MenuAction::LoadRom(self.roms_folder.join(rom)))
+                },
+                CustomKeyEvent::MenuBack => {
+                    self.page = MenuPage::Main;
+                    None
+                },
+                _ => None
+            }
+        }
+
+        fn open_load_rom_page(&mut self) {
+            self.page = MenuPage::LoadRom;
+            self.selected_rom = 0;
+            self.message = None;
+            self.roms = match FileSelectionUtil::get_files_in_directory(&self.roms_folder) {
+                Ok(files) => files.into_iter().filter(|file| file.to_lowercase().contains(".ch8")).collect(),
+                Err(err) => {
+                    self.message = Some(format!("COULDN'T READ THE FOLDER: {err}"));
+                    Vec::new()
+                }
+            };
+            self.roms.sort();
+        }
+    }
+}
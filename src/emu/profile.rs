@@ -0,0 +1,75 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+pub mod rom_profile {
+    use crate::emu::config::settings::EmuSettings;
+    use crate::emu::logic::cpu::CpuController;
+
+    use std::fs;
+    use std::path::{ Path, PathBuf };
+    use toml_edit::{ table, value, DocumentMut };
+
+    // The settings of a single ROM, stored as "<profile_folder>/<sha1>.toml".
+    // The file uses the same sections and keys as the settings file, so any setting can be overridden per ROM,
+    // but only the speed and quirk settings are written when it's saved from within the emulator.
+    pub struct RomProfile {
+        path: PathBuf,
+        rom_name: String,
+    }
+
+    impl RomProfile {
+        pub fn new(profile_folder: &Path, rom_sha1: &str, rom_name: &str) -> Self {
+            Self {
+                path: profile_folder.join(format!("{rom_sha1}.toml")),
+                rom_name: String::from(rom_name),
+            }
+        }
+
+        // Applies the settings stored for the ROM (if any) on top of the current ones.
+        pub fn load_into(&self, emu_settings: &mut EmuSettings) {
+            let file_str = match fs::read_to_string(&self.path) {
+                Ok(file_str) => file_str,
+                Err(_) => return
+            };
+
+            println!("Using the profile '{}'.", self.path.display());
+            for error in emu_settings.apply_toml(&file_str) {
+                println!("{}:{error}", self.path.display());
+            }
+        }
+
+        // Stores the current speed and quirk settings, keeping any other setting already in the file.
+        pub fn save(&self, cpu_ctrl: &CpuController) -> std::io::Result<()> {
+            let mut doc: DocumentMut = fs::read_to_string(&self.path).ok()
+                .and_then(|file_str| file_str.parse().ok())
+                .unwrap_or_default();
+
+            if doc.is_empty() {
+                let mut cpu_table = table();
+                if let Some(cpu_table) = cpu_table.as_table_mut() {
+                    cpu_table.decor_mut().set_prefix(format!("# Profile of '{}'.\n", self.rom_name));
+                }
+                doc["cpu"] = cpu_table;
+            }
+
+            for section_name in ["cpu", "quirks"] {
+                if !doc.contains_table(section_name) {
+                    doc[section_name] = table();
+                }
+            }
+
+            doc["cpu"]["cycles_per_frame"] = value(cpu_ctrl.get_cycles_per_frame() as i64);
+            doc["quirks"]["bit_shift_instructions_use_vy"] = value(cpu_ctrl.get_bit_shift_instructions_use_vy());
+            doc["quirks"]["store_read_instructions_change_i"] = value(cpu_ctrl.get_store_read_instructions_change_i());
+
+            if let Some(profile_folder) = self.path.parent() {
+                fs::create_dir_all(profile_folder)?;
+            }
+            fs::write(&self.path, doc.to_string())
+        }
+
+        pub fn get_path(&self) -> &PathBuf {
+            &self.path
+        }
+    }
+}
@@ -0,0 +1,242 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// Execution profiler: counts how many times each address and each kind of instruction is executed,
+// how long each subroutine takes and how many frames are spent waiting for a key (Fx0A).
+// Besides the executed instructions, the costs are given in estimated machine cycles (see cycles.rs),
+// which roughly tell what limits the speed of a ROM on the original hardware, whatever cycles_per_frame is.
+pub mod exec_profiler {
+    use crate::emu::disasm::disassembler;
+    use crate::emu::memory::memory::{ MemoryController, RAM_SIZE };
+    use crate::emu::cycles::cycle_estimates;
+
+    use std::collections::{ BTreeMap, HashMap };
+
+    // Amount of addresses listed in the hot spots of the report.
+    const MAX_HOT_SPOTS: usize = 30;
+
+    // Instructions and machine cycles spent by an address, a kind of instruction or a subroutine.
+    #[derive(Clone, Copy, Default)]
+    struct Cost {
+        count: u64,
+        instructions: u64,
+        cycles: u64,
+    }
+
+    // A subroutine that is running, tracked alongside the stack of the memory.
+    struct Call {
+        addr: u16,
+        start_instructions: u64,
+        start_cycles: u64,
+    }
+
+    pub struct Profiler {
+        // Indexed by address.
+        addr_costs: Vec<Cost>,
+        // The last opcode executed at each address (for the disassembly, since the code can change).
+        addr_opcodes: Vec<u16>,
+        // Indexed by the opcode without its operands (see get_opcode_class).
+        class_costs: HashMap<u16, Cost>,
+        // Indexed by the address of the subroutine, with the costs of the nested calls included.
+        subroutine_costs: BTreeMap<u16, Cost>,
+        calls: Vec<Call>,
+        // Frames spent waiting in Fx0A, by the address of the instruction.
+        key_wait_frames: BTreeMap<u16, u64>,
+        key_wait_addr: Option<u16>,
+        instructions: u64,
+        cycles: u64,
+        frames: u64,
+    }
+
+    impl Default for Profiler {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Profiler {
+        pub fn new() -> Self {
+            Self {
+                addr_costs: vec![Cost::default(); RAM_SIZE],
+                addr_opcodes: vec![0; RAM_SIZE],
+                class_costs: HashMap::new(),
+                subroutine_costs: BTreeMap::new(),
+                calls: Vec::new(),
+                key_wait_frames: BTreeMap::new(),
+                key_wait_addr: None,
+                instructions: 0,
+                cycles: 0,
+                frames: 0,
+            }
+        }
+
+        // Records an instruction that was just executed, given its address and opcode.
+        pub fn record(&mut self, pc: u16, opcode: u16, mem_ctrl: &MemoryController) {
+            let next_pc = mem_ctrl.get_pc();
+            let vx = mem_ctrl.get_v_regs()[((opcode >> 8) & 0xf) as usize];
+            // A jump or call can also land 4 bytes ahead, so only the skip instructions skipped the next one.
+            let skipped = Profiler::is_skip(opcode) && next_pc == pc.wrapping_add(4);
+            let cycles = cycle_estimates::instr_cycles(opcode, skipped, vx) as u64;
+
+            self.instructions += 1;
+            self.cycles += cycles;
+
+            let addr_cost = &mut self.addr_costs[pc as usize % RAM_SIZE];
+            addr_cost.count += 1;
+            addr_cost.cycles += cycles;
+            self.addr_opcodes[pc as usize % RAM_SIZE] = opcode;
+
+            let class_cost = self.class_costs.entry(Profiler::get_opcode_class(opcode)).or_default();
+            class_cost.count += 1;
+            class_cost.cycles += cycles;
+
+            // Fx0A keeps the PC in place while no key is pressed.
+            if opcode & 0xf0ff == 0xf00a && next_pc == pc {
+                self.key_wait_addr = Some(pc);
+            }
+
+            self.track_calls(mem_ctrl);
+        }
+
+        // Must be called at the end of each (60hz) frame.
+        pub fn end_frame(&mut self) {
+            self.frames += 1;
+            if let Some(addr) = self.key_wait_addr.take() {
+                *self.key_wait_frames.entry(addr).or_insert(0) += 1;
+            }
+        }
+
+        // The report of the whole run, sorted by hotness and annotated with the disassembly.
+        pub fn get_report(&self) -> String {
+            let total_key_wait_frames: u64 = self.key_wait_frames.values().sum();
+            let mut lines = vec![
+                format!("Profile: {} instructions, {} estimated machine cycles, {} frames ({} waiting for a key in Fx0A).",
+                    self.instructions, self.cycles, self.frames, total_key_wait_frames),
+                String::new(),
+                String::from("Hot spots (by estimated machine cycles):"),
+                String::from("ADDR  COUNT        CYCLES       %CYCLES DISASSEMBLY")];
+
+            let mut hot_spots: Vec<(usize, &Cost)> = self.addr_costs.iter()
+                .enumerate()
+                .filter(|(_, cost)| cost.count > 0)
+                .collect();
+            hot_spots.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(b.1.count.cmp(&a.1.count)).then(a.0.cmp(&b.0)));
+            for (addr, cost) in hot_spots.iter().take(MAX_HOT_SPOTS) {
+                lines.push(format!("{addr:04X}  {:<12} {:<12} {:>6.2}% {}",
+                    cost.count, cost.cycles, self.get_percentage(cost.cycles),
+                    disassembler::disassemble(self.addr_opcodes[*addr])));
+            }
+            if hot_spots.len() > MAX_HOT_SPOTS {
+                lines.push(format!("... and {} more addresses.", hot_spots.len() - MAX_HOT_SPOTS));
+            }
+
+            lines.push(String::new());
+            lines.push(String::from("Instructions (by estimated machine cycles):"));
+            lines.push(String::from("CLASS COUNT        CYCLES       %CYCLES"));
+            let mut classes: Vec<(&u16, &Cost)> = self.class_costs.iter().collect();
+            classes.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+            for (class, cost) in classes {
+                lines.push(format!("{:<5} {:<12} {:<12} {:>6.2}%",
+                    Profiler::get_opcode_class_name(*class), cost.count, cost.cycles, self.get_percentage(cost.cycles)));
+            }
+
+            lines.push(String::new());
+            lines.push(String::from("Subroutines (by estimated machine cycles, including nested calls):"));
+            if self.subroutine_costs.is_empty() {
+                lines.push(String::from("No subroutine returned."));
+            } else {
+                lines.push(String::from("ADDR  CALLS        INSTRUCTIONS CYCLES       %CYCLES CYCLES/CALL"));
+                let mut subroutines: Vec<(&u16, &Cost)> = self.subroutine_costs.iter().collect();
+                subroutines.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+                for (addr, cost) in subroutines {
+                    lines.push(format!("{addr:04X}  {:<12} {:<12} {:<12} {:>6.2}% {}",
+                        cost.count, cost.instructions, cost.cycles, self.get_percentage(cost.cycles),
+                        cost.cycles / cost.count));
+                }
+            }
+
+            if !self.key_wait_frames.is_empty() {
+                lines.push(String::new());
+                lines.push(String::from("Frames waiting for a key (Fx0A):"));
+                lines.push(String::from("ADDR  FRAMES"));
+                for (addr, frames) in &self.key_wait_frames {
+                    lines.push(format!("{addr:04X}  {frames}"));
+                }
+            }
+
+            lines.join("\n")
+        }
+
+        // Follows the stack pointer: a call pushes a return address (2nnn) and a return pops it (00EE).
+        // Comparing the stack pointer instead of decoding the instructions keeps the calls in step with the stack,
+        // even after a fault or a change made in the debugger.
+        fn track_calls(&mut self, mem_ctrl: &MemoryController) {
+            let sp = mem_ctrl.get_sp() as usize;
+
+            while self.calls.len() > sp {
+                if let Some(call) = self.calls.pop() {
+                    let subroutine_cost = self.subroutine_costs.entry(call.addr).or_default();
+                    subroutine_cost.count += 1;
+                    subroutine_cost.instructions += self.instructions - call.start_instructions;
+                    subroutine_cost.cycles += self.cycles - call.start_cycles;
+                }
+            }
+
+            while self.calls.len() < sp {
+                self.calls.push(Call {
+                    addr: mem_ctrl.get_pc(),
+                    start_instructions: self.instructions,
+                    start_cycles: self.cycles,
+                });
+            }
+        }
+
+        fn get_percentage(&self, cycles: u64) -> f64 {
+            if self.cycles == 0 {
+                return 0.0;
+            }
+            cycles as f64 * 100.0 / self.cycles as f64
+        }
+
+        // The opcode without its operands, e.g. 0x8004 for 8xy4 or 0xd000 for Dxyn.
+        // 3xkk, 4xkk, 5xy0, 9xy0, Ex9E and ExA1.
+        fn is_skip(opcode: u16) -> bool {
+            match opcode >> 12 {
+                3 | 4 => true,
+                5 | 9 => opcode & 0xf == 0,
+                0xe => opcode & 0xff == 0x9e || opcode & 0xff == 0xa1,
+                _ => false
+            }
+        }
+
+        fn get_opcode_class(opcode: u16) -> u16 {
+            match opcode >> 12 {
+                0 if opcode == 0x00e0 || opcode == 0x00ee => opcode,
+                8 => opcode & 0xf00f,
+                0xe | 0xf => opcode & 0xf0ff,
+                _ => opcode & 0xf000
+            }
+        }
+
+        fn get_opcode_class_name(class: u16) -> String {
+            match class >> 12 {
+                0 if class == 0x00e0 || class == 0x00ee => format!("{class:04X}"),
+                0 => String::from("0nnn"),
+                1 => String::from("1nnn"),
+                2 => String::from("2nnn"),
+                3 => String::from("3xkk"),
+                4 => String::from("4xkk"),
+                5 => String::from("5xy0"),
+                6 => String::from("6xkk"),
+                7 => String::from("7xkk"),
+                8 => format!("8xy{:X}", class & 0xf),
+                9 => String::from("9xy0"),
+                0xa => String::from("Annn"),
+                0xb => String::from("Bnnn"),
+                0xc => String::from("Cxkk"),
+                0xd => String::from("Dxyn"),
+                _ => format!("{:X}x{:02X}", class >> 12, class & 0xff)
+            }
+        }
+    }
+}
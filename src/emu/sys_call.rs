@@ -0,0 +1,59 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// Legacy 0nnn (SYS addr) instructions, which called 1802 machine-code routines on the COSMAC VIP.
+// The machine code itself can't be run, but a handler can skip the calls or emulate the routines a ROM relies on.
+pub mod sys_handler {
+    use crate::emu::display::screen::ScreenController;
+    use crate::emu::memory::memory::MemoryController;
+
+    // Hook for the 0nnn instructions, set with CpuController::set_sys_handler.
+    // It's Send so the whole machine can be moved between threads (e.g. by a libretro frontend).
+    pub trait SysHandler: Send {
+        // Returns true if the call was handled, otherwise it's treated as an unknown opcode.
+        fn call(&mut self, addr: u16, mem_ctrl: &mut MemoryController, screen_ctrl: &mut ScreenController) -> bool;
+    }
+
+    // How the 0nnn instructions are handled when there's no custom handler.
+    #[derive(Clone, Copy, PartialEq)]
+    pub enum SysCallMode {
+        // Treated as unknown opcodes (see UnknownOpcodePolicy).
+        Unknown,
+        // Skipped, like most modern interpreters do.
+        Ignore,
+    }
+
+    impl SysCallMode {
+        pub fn from_name(name: &str) -> Option<Self> {
+            match name {
+                "unknown" => Some(SysCallMode::Unknown),
+                "ignore" => Some(SysCallMode::Ignore),
+                _ => None
+            }
+        }
+
+        pub fn get_name(&self) -> &str {
+            match self {
+                SysCallMode::Unknown => "unknown",
+                SysCallMode::Ignore => "ignore"
+            }
+        }
+
+        // The built-in handler of the mode (if any).
+        #[cfg(feature = "std")]
+        pub fn create_handler(&self) -> Option<Box<dyn SysHandler>> {
+            match self {
+                SysCallMode::Unknown => None,
+                SysCallMode::Ignore => Some(Box::new(IgnoreSysCalls))
+            }
+        }
+    }
+
+    pub struct IgnoreSysCalls;
+
+    impl SysHandler for IgnoreSysCalls {
+        fn call(&mut self, _addr: u16, _mem_ctrl: &mut MemoryController, _screen_ctrl: &mut ScreenController) -> bool {
+            true
+        }
+    }
+}
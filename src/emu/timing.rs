@@ -0,0 +1,218 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+pub mod scheduler {
+    use std::time::{ Duration, Instant };
+
+    // The emulated machine runs at 60hz: the CPU cycles of a frame, the timers and the buzzer are all tied to it.
+    pub const TICK_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+    // If the host falls behind by more than this amount of ticks (e.g. the window was being dragged),
+    // the missed ticks are dropped instead of being emulated all at once.
+    const MAX_TICKS_BEHIND: u32 = 6;
+
+    // std::thread::sleep() may oversleep by a few milliseconds depending on the OS,
+    // so the last part of the wait is done by yielding the thread instead.
+    const SPIN_THRESHOLD: Duration = Duration::from_millis(2);
+
+    // Speeds that can be selected while running, relative to the 60hz of the emulated machine.
+    // The last one is unlimited: the ticks are emulated as fast as the host can.
+    const SPEEDS: [f64;6] = [0.25, 0.5, 1.0, 2.0, 4.0, f64::INFINITY];
+    const NORMAL_SPEED_INDEX: usize = 2;
+
+    // At unlimited speed, the most ticks emulated between two rendered frames.
+    const MAX_UNLIMITED_TICKS: u32 = 4096;
+
+    // Schedules the 60hz ticks based on the real elapsed time.
+    // Every tick has an absolute deadline, so the time spent executing and rendering is compensated
+    // and small delays (oversleeping, slow frames) don't accumulate into drift.
+    pub struct FrameScheduler {
+        next_tick: Instant,
+        // When the canvas is synchronized to the host refresh rate (vsync), presenting it already blocks,
+        // so the scheduler never sleeps and only counts the ticks that are due.
+        vsync: bool,
+        speed_index: usize,
+        // The real duration of a tick at the current speed.
+        tick_duration: Duration,
+        // At unlimited speed, the amount of ticks emulated per rendered frame,
+        // adjusted so that the frames are still rendered at about 60hz.
+        unlimited_ticks: u32,
+        last_batch: Instant,
+    }
+
+    impl FrameScheduler {
+        pub fn new(vsync: bool) -> Self {
+            Self {
+                next_tick: Instant::now(),
+                vsync,
+                speed_index: NORMAL_SPEED_INDEX,
+                tick_duration: TICK_DURATION,
+                unlimited_ticks: 1,
+                last_batch: Instant::now(),
+            }
+        }
+
+        // Waits (unless vsync is on) until at least one tick is due
+        // and returns the amount of ticks that must be emulated before the next frame is rendered.
+        // With vsync on, this may be 0 (host refresh rate above 60hz) or more than 1 (below 60hz).
+        pub fn wait_for_ticks(&mut self) -> u32 {
+            if self.is_unlimited() {
+                return self.next_unlimited_batch();
+            }
+
+            if !self.vsync {
+                self.wait_until(self.next_tick);
+            }
+
+            let now = Instant::now();
+            let mut ticks = 0;
+            while self.next_tick <= now {
+                self.next_tick += self.tick_duration;
+                ticks += 1;
+
+                if ticks > MAX_TICKS_BEHIND {
+                    self.next_tick = now + self.tick_duration;
+                    ticks = 1;
+                    break;
+                }
+            }
+            ticks
+        }
+
+        // Waits for a 60hz tick (unless vsync is on) without scheduling any, used while the emulation is paused.
+        pub fn wait_while_paused(&mut self) {
+            if !self.vsync {
+                self.wait_until(Instant::now() + TICK_DURATION);
+            }
+            self.reset();
+        }
+
+        // Restarts the schedule from now, e.g. after the emulation was paused.
+        pub fn reset(&mut self) {
+            self.next_tick = Instant::now();
+            self.last_batch = Instant::now();
+        }
+
+        pub fn speed_up(&mut self) {
+            self.set_speed_index((self.speed_index + 1).min(SPEEDS.len() - 1));
+        }
+
+        pub fn slow_down(&mut self) {
+            self.set_speed_index(self.speed_index.saturating_sub(1));
+        }
+
+        pub fn reset_speed(&mut self) {
+            self.set_speed_index(NORMAL_SPEED_INDEX);
+        }
+
+        pub fn is_normal_speed(&self) -> bool {
+            self.speed_index == NORMAL_SPEED_INDEX
+        }
+
+        // E.g. "0.25x", "2x" or "unlimited".
+        pub fn get_speed_name(&self) -> String {
+            if self.is_unlimited() {
+                String::from("unlimited")
+            } else {
+                format!("{}x", SPEEDS[self.speed_index])
+            }
+        }
+
+        fn set_speed_index(&mut self, speed_index: usize) {
+            self.speed_index = speed_index;
+            if !self.is_unlimited() {
+                self.tick_duration = TICK_DURATION.div_f64(SPEEDS[speed_index]);
+            }
+            self.unlimited_ticks = 1;
+            self.reset();
+        }
+
+        fn is_unlimited(&self) -> bool {
+            SPEEDS[self.speed_index].is_infinite()
+        }
+
+        // Doubles the batch while the previous one (emulation and rendering) took less than 1.5 ticks
+        // and halves it when it took more than 2.
+        fn next_unlimited_batch(&mut self) -> u32 {
+            let elapsed = self.last_batch.elapsed();
+            if elapsed < TICK_DURATION.mul_f64(1.5) {
+                self.unlimited_ticks = (self.unlimited_ticks * 2).min(MAX_UNLIMITED_TICKS);
+            } else if elapsed > TICK_DURATION * 2 {
+                self.unlimited_ticks = (self.unlimited_ticks / 2).max(1);
+            }
+
+            self.last_batch = Instant::now();
+            self.unlimited_ticks
+        }
+
+        fn wait_until(&self, deadline: Instant) {
+            loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    return;
+                }
+
+                let remaining = deadline - now;
+                if remaining > SPIN_THRESHOLD {
+                    std::thread::sleep(remaining - SPIN_THRESHOLD);
+                } else {
+                    std::thread::yield_now();
+                }
+            }
+        }
+    }
+
+    // Measures the real amount of rendered frames (FPS) and executed instructions (IPS) per second.
+    pub struct PerfCounter {
+        window_start: Instant,
+        frames: u32,
+        instructions: u64,
+        fps: f64,
+        ips: f64,
+    }
+
+    impl Default for PerfCounter {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl PerfCounter {
+        pub fn new() -> Self {
+            Self {
+                window_start: Instant::now(),
+                frames: 0,
+                instructions: 0,
+                fps: 0.0,
+                ips: 0.0,
+            }
+        }
+
+        // Registers a rendered frame and the instructions executed for it.
+        // Returns true whenever the measurements are updated (once per second).
+        pub fn add_frame(&mut self, instructions: u64) -> bool {
+            self.frames += 1;
+            self.instructions += instructions;
+
+            let elapsed = self.window_start.elapsed();
+            if elapsed < Duration::from_secs(1) {
+                return false;
+            }
+
+            self.fps = self.frames as f64 / elapsed.as_secs_f64();
+            self.ips = self.instructions as f64 / elapsed.as_secs_f64();
+            self.frames = 0;
+            self.instructions = 0;
+            self.window_start = Instant::now();
+            true
+        }
+
+        pub fn get_fps(&self) -> f64 {
+            self.fps
+        }
+
+        pub fn get_ips(&self) -> f64 {
+            self.ips
+        }
+    }
+}
@@ -0,0 +1,128 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// Execution trace: one line per executed instruction, with the registers after its execution.
+// The columns have a fixed width and the values are always hex (except the cycle), so traces can be compared
+// line by line with diff, e.g. against another emulator's trace of the same ROM:
+// CYCLE      PC   OP   DISASSEMBLY    V0 V1 V2 V3 V4 V5 V6 V7 V8 V9 VA VB VC VD VE VF I    SP DT ST
+// 0000000001 0200 6A02 LD VA, #02     00 00 00 00 00 00 00 00 00 00 02 00 00 00 00 00 0000 00 00 00
+pub mod tracer {
+    use crate::emu::disasm::disassembler;
+    use crate::emu::memory::memory::MemoryController;
+    use crate::error::emu_error::EmuError;
+
+    use std::collections::VecDeque;
+    use std::fs::File;
+    use std::io::{ BufWriter, Write };
+    use std::ops::RangeInclusive;
+    use std::path::{ Path, PathBuf };
+
+    // Width of the disassembly column (the longest one is "DRW VF, VF, 15").
+    const DISASSEMBLY_W: usize = 14;
+
+    pub struct Tracer {
+        // Where the lines (or the dumps of the ring buffer) are written.
+        // Without a file, the dumps are printed in the terminal.
+        writer: Option<BufWriter<File>>,
+        path: Option<PathBuf>,
+        // Only the instructions at these addresses are traced (all of them if there are no ranges).
+        ranges: Vec<RangeInclusive<u16>>,
+        // With a size, only the last instructions are kept, and they're written when a fault happens.
+        ring: VecDeque<String>,
+        ring_size: Option<usize>,
+        // Instructions executed since the ROM was loaded, including the ones filtered out.
+        cycle: u64,
+    }
+
+    impl Tracer {
+        pub fn new(path: Option<&Path>, ranges: Vec<RangeInclusive<u16>>, ring_size: Option<usize>) -> Result<Self, EmuError> {
+            let mut tracer = Self {
+                writer: None,
+                path: path.map(Path::to_path_buf),
+                ranges,
+                ring: VecDeque::with_capacity(ring_size.unwrap_or(0)),
+                ring_size,
+                cycle: 0,
+            };
+
+            if let Some(path) = path {
+                let file = File::create(path)
+                    .map_err(|source| EmuError::TraceCreate { path: path.to_path_buf(), source })?;
+                tracer.writer = Some(BufWriter::new(file));
+
+                if tracer.ring_size.is_none() {
+                    tracer.write_line(&Tracer::get_header());
+                }
+            }
+            Ok(tracer)
+        }
+
+        // Traces an instruction that was just executed, given its address and opcode.
+        pub fn trace(&mut self, pc: u16, opcode: u16, mem_ctrl: &MemoryController) {
+            self.cycle += 1;
+
+            if !self.ranges.is_empty() && !self.ranges.iter().any(|range| range.contains(&pc)) {
+                return;
+            }
+
+            let v_regs: Vec<String> = mem_ctrl.get_v_regs().iter().map(|val| format!("{val:02X}")).collect();
+            let line = format!("{:010} {pc:04X} {opcode:04X} {:<DISASSEMBLY_W$} {} {:04X} {:02X} {:02X} {:02X}",
+                self.cycle,
+                disassembler::disassemble(opcode),
+                v_regs.join(" "),
+                mem_ctrl.get_i(),
+                mem_ctrl.get_sp(),
+                mem_ctrl.get_dt(),
+                mem_ctrl.get_st());
+
+            match self.ring_size {
+                Some(ring_size) => {
+                    if self.ring.len() == ring_size {
+                        self.ring.pop_front();
+                    }
+                    self.ring.push_back(line);
+                },
+                None => self.write_line(&line)
+            }
+        }
+
+        // Writes the last instructions kept in the ring buffer (if it's used), e.g. after a fault.
+        pub fn dump_ring(&mut self, reason: &str) {
+            if self.ring_size.is_none() {
+                return;
+            }
+
+            let mut lines = vec![format!("Last {} instructions before: {reason}", self.ring.len()), Tracer::get_header()];
+            lines.extend(self.ring.drain(..));
+
+            if self.writer.is_some() {
+                for line in &lines {
+                    self.write_line(line);
+                }
+                if let Some(path) = &self.path {
+                    println!("Trace of the last instructions written to '{}'.", path.display());
+                }
+            } else {
+                println!("{}", lines.join("\n"));
+            }
+        }
+
+        fn get_header() -> String {
+            let v_regs: Vec<String> = (0..16).map(|i| format!("V{i:X}")).collect();
+            format!("{:<10} {:<4} {:<4} {:<DISASSEMBLY_W$} {} {:<4} {:<2} {:<2} {:<2}",
+                "CYCLE", "PC", "OP", "DISASSEMBLY", v_regs.join(" "), "I", "SP", "DT", "ST")
+        }
+
+        // If the file can't be written, the trace stops (the emulation goes on).
+        fn write_line(&mut self, line: &str) {
+            let Some(writer) = &mut self.writer else {
+                return;
+            };
+
+            if let Err(err) = writeln!(writer, "{line}") {
+                println!("\nWarning: couldn't write the trace, it was stopped: {err}.");
+                self.writer = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+pub mod rom_watcher {
+    use std::fs;
+    use std::path::{ Path, PathBuf };
+    use std::time::{ Duration, Instant, SystemTime };
+
+    // How often the ROM file is checked for changes.
+    const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+    // What happens to the settings when the watched ROM changes.
+    #[derive(Clone, Copy, PartialEq)]
+    pub enum WatchMode {
+        // The current speed and quirks are kept, only the ROM is reloaded.
+        KeepSettings,
+        // The ROM is loaded as if it was a new one, applying the database, profile and command-line settings again.
+        ReloadSettings,
+    }
+
+    // Polls the ROM file for changes (modification time and size), so it can be reloaded as soon as it's rebuilt.
+    pub struct RomWatcher {
+        path: PathBuf,
+        // The state of the file when it was last loaded.
+        loaded_stamp: Option<(SystemTime, u64)>,
+        // The state of the file in the previous poll.
+        last_stamp: Option<(SystemTime, u64)>,
+        last_poll: Instant,
+    }
+
+    impl RomWatcher {
+        pub fn new(path: &Path) -> Self {
+            let stamp = RomWatcher::get_stamp(path);
+            Self {
+                path: path.to_path_buf(),
+                loaded_stamp: stamp,
+                last_stamp: stamp,
+                last_poll: Instant::now(),
+            }
+        }
+
+        // Returns true once the file changed since it was last loaded.
+        // A change is only reported after the file exists and stayed the same for a whole poll interval,
+        // so a ROM that is still being written (or was deleted to be rebuilt) isn't loaded.
+        pub fn poll(&mut self) -> bool {
+            if self.last_poll.elapsed() < POLL_INTERVAL {
+                return false;
+            }
+            self.last_poll = Instant::now();
+
+            let stamp = RomWatcher::get_stamp(&self.path);
+            let changed = stamp.is_some() && stamp == self.last_stamp && stamp != self.loaded_stamp;
+            self.last_stamp = stamp;
+
+            if changed {
+                self.loaded_stamp = stamp;
+            }
+            changed
+        }
+
+        fn get_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+            let metadata = fs::metadata(path).ok()?;
+            Some((metadata.modified().ok()?, metadata.len()))
+        }
+    }
+}
@@ -0,0 +1,108 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+pub mod emu_error {
+    use core::fmt;
+    #[cfg(feature = "std")]
+    use std::io;
+    #[cfg(feature = "std")]
+    use std::path::PathBuf;
+
+    // The failures the emulator can recover from (or at least report clearly) instead of panicking.
+    // Without the standard library, only the ones of the core are left.
+    #[derive(Debug)]
+    pub enum EmuError {
+        // The ROM file couldn't be opened or read.
+        #[cfg(feature = "std")]
+        RomRead { path: PathBuf, source: io::Error },
+        // The ROM doesn't fit in the RAM available to programs (from 0x200 to 0xfff).
+        // There's no path when the ROM is loaded from memory (see Emulator::load_rom).
+        RomTooLarge {
+            #[cfg(feature = "std")]
+            path: Option<PathBuf>,
+            size: usize,
+            max_size: usize
+        },
+        // The ROM folder couldn't be listed.
+        #[cfg(feature = "std")]
+        FolderRead { path: PathBuf, source: io::Error },
+        // The ROM folder has no CH8 files.
+        #[cfg(feature = "std")]
+        NoRomsFound { path: PathBuf },
+        // The trace file couldn't be created.
+        #[cfg(feature = "std")]
+        TraceCreate { path: PathBuf, source: io::Error },
+        // A report (e.g. the profile) couldn't be written to its file.
+        #[cfg(feature = "std")]
+        ReportWrite { path: PathBuf, source: io::Error },
+        // Reading the terminal input failed.
+        #[cfg(feature = "std")]
+        Input(io::Error),
+        // SDL couldn't be initialized or a window/renderer couldn't be created.
+        #[cfg(feature = "std")]
+        Sdl(String),
+        // The audio subsystem or playback device isn't available.
+        #[cfg(feature = "std")]
+        Audio(String),
+        // The executable memory of the JIT couldn't be allocated.
+        #[cfg(feature = "jit")]
+        Jit(io::Error),
+    }
+
+    impl fmt::Display for EmuError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                #[cfg(feature = "std")]
+                EmuError::RomRead { path, source } =>
+                    write!(f, "couldn't read the ROM '{}': {source}", path.display()),
+                #[cfg(feature = "std")]
+                EmuError::RomTooLarge { path: Some(path), size, max_size } =>
+                    write!(f, "the ROM '{}' has {size} bytes, but only {max_size} bytes fit in the RAM", path.display()),
+                EmuError::RomTooLarge { size, max_size, .. } =>
+                    write!(f, "the ROM has {size} bytes, but only {max_size} bytes fit in the RAM"),
+                #[cfg(feature = "std")]
+                EmuError::FolderRead { path, source } =>
+                    write!(f, "couldn't read the ROM folder '{}' (see 'default_ch8_folder'): {source}", path.display()),
+                #[cfg(feature = "std")]
+                EmuError::NoRomsFound { path } =>
+                    write!(f, "couldn't find any .ch8 file in the ROM folder '{}' (see 'default_ch8_folder')", path.display()),
+                #[cfg(feature = "std")]
+                EmuError::TraceCreate { path, source } =>
+                    write!(f, "couldn't create the trace file '{}': {source}", path.display()),
+                #[cfg(feature = "std")]
+                EmuError::ReportWrite { path, source } =>
+                    write!(f, "couldn't write the report '{}': {source}", path.display()),
+                #[cfg(feature = "std")]
+                EmuError::Input(source) =>
+                    write!(f, "couldn't read the input: {source}"),
+                #[cfg(feature = "std")]
+                EmuError::Sdl(reason) =>
+                    write!(f, "SDL error: {reason}"),
+                #[cfg(feature = "std")]
+                EmuError::Audio(reason) =>
+                    write!(f, "audio error: {reason}"),
+                #[cfg(feature = "jit")]
+                EmuError::Jit(source) =>
+                    write!(f, "couldn't allocate the executable memory of the JIT: {source}")
+            }
+        }
+    }
+
+    impl core::error::Error for EmuError {
+        fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+            match self {
+                #[cfg(feature = "std")]
+                EmuError::RomRead { source, .. }
+                | EmuError::FolderRead { source, .. }
+                | EmuError::TraceCreate { source, .. }
+                | EmuError::ReportWrite { source, .. }
+                | EmuError::Input(source) =>
+                    Some(source),
+                #[cfg(feature = "jit")]
+                EmuError::Jit(source) =>
+                    Some(source),
+                _ => None
+            }
+        }
+    }
+}
@@ -0,0 +1,29 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+//! Rusted - Chip-8 Emulator/Interpreter.
+//!
+//! The emulator core, usable from any frontend through [`Emulator`].
+//! The SDL frontend (window, audio, input and overlay menu) is the `rusted-chip8` binary built on top of it.
+//!
+//! Without the default `desktop` feature, only the core is built (no SDL and no files),
+//! which compiles to `wasm32-unknown-unknown` (see the `wasm` module and the web folder).
+//! Without the `std` feature either, the core is `no_std` and doesn't allocate, so it runs on microcontrollers:
+//! the frontend connects it to its hardware through the traits of [`emu::backend::backends`].
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub mod emu;
+pub mod error;
+#[cfg(feature = "desktop")]
+pub mod sdl;
+pub mod util;
+#[cfg(all(target_arch = "wasm32", feature = "std"))]
+pub mod wasm;
+
+pub use emu::api::emulator_api::{ Emulator, EmulatorState, SCREEN_H, SCREEN_W, STATE_SIZE };
+#[cfg(feature = "std")]
+pub use emu::gym::gym_env::{ DoneCondition, Env, EnvState, Observation, RamValue };
+pub use emu::logic::cpu::JitMode;
+#[cfg(feature = "jit")]
+pub use emu::jit::jit_compiler::Jit;
+pub use error::emu_error::EmuError;
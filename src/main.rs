@@ -1,69 +1,425 @@
 // SPDX-License-Identifier: MIT
 // SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
 
-pub mod emu;
-pub mod sdl;
-pub mod util;
+mod cli;
 
-use emu::{
+use cli::args::{ CliArgs, USAGE };
+use rusted_chip8::Emulator;
+use rusted_chip8::emu::{
     audio::buzzer::BuzzerController,
-    config::settings::EmuSettings ,
-    core_emu::emulator::EmuController,
+    batch::batch_run::{ BatchJob, BatchRunner, DEFAULT_BATCH_FRAMES },
+    config::settings::EmuSettings,
+    core_emu::emulator::{ EmuController, EmuExit },
+    coverage::coverage_map::Coverage,
+    database::chip8_db::{ Chip8Database, Platform, RomInfo },
+    display::screen::{ Screen, ScreenController, SCREEN_H, SCREEN_W },
     input::keyboard::{ Keyboard, KeyboardController },
     logic::cpu::CpuController,
-    memory::memory::{ Memory, MemoryController }
+    memory::memory::{ Memory, MemoryController },
+    overlay::menu::Overlay,
+    profile::rom_profile::RomProfile,
+    profiler::exec_profiler::Profiler,
+    timing::scheduler::FrameScheduler,
+    trace::tracer::Tracer
 };
-use sdl::wrapper::*;
-use std::env;
-use util::utilities::FileSelectionUtil;
+#[cfg(feature = "jit")]
+use rusted_chip8::emu::jit::jit_compiler::Jit;
+use rusted_chip8::error::emu_error::EmuError;
+use rusted_chip8::sdl::wrapper::*;
+use rusted_chip8::util::utilities::FileSelectionUtil;
+use sdl2::EventPump;
+use sha1_smol::Sha1;
+use std::{ env, fs, io, thread };
+use std::path::{ Path, PathBuf };
+use std::time::{ SystemTime, UNIX_EPOCH };
 
 fn main() {
+    let cli_args = match CliArgs::parse(env::args().skip(1)) {
+        Ok(cli_args) => cli_args,
+        Err(err) => {
+            eprintln!("Error: {err}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    if cli_args.get_help() {
+        println!("{USAGE}");
+        return;
+    }
+
+    if let Err(err) = run(&cli_args) {
+        eprintln!("Error: {err}.");
+        std::process::exit(1);
+    }
+}
+
+fn run(cli_args: &CliArgs) -> Result<(), EmuError> {
     println!("Rusted - Chip-8 Emulator/Interpreter");
     println!("2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>");
     println!("Repository: https://github.com/rodrigoCucick/rusted-chip8");
-    println!("Settings can be adjusted via 'config.txt'.");
 
-    let emu_settings = EmuSettings::new();
+    let config_path = match cli_args.get_config_path() {
+        Some(config_path) => config_path.to_path_buf(),
+        None => EmuSettings::find_config_file()
+    };
+    println!("Settings can be adjusted via '{}'.", config_path.display());
+
+    let base_settings = EmuSettings::from_file(&config_path);
 
-    let mut roms_path = String::from(env::current_dir().unwrap().to_str().unwrap());
-    roms_path.push_str(emu_settings.get_default_ch8_folder());
+    let chip8_db = Chip8Database::load(&base_settings.get_database_folder());
 
-    let roms = match FileSelectionUtil::get_files_in_directory(&roms_path) {
-        Ok(file_names) => file_names,
-        Err(_) => panic!("Invalid folder provided to 'default_ch8_folder' in 'config.txt'!")
+    if let Some(batch_folder) = cli_args.get_batch_folder() {
+        return run_batch(batch_folder, &base_settings, chip8_db.as_ref(), cli_args);
+    }
+
+    let roms_folder = base_settings.get_default_ch8_folder();
+    let mut rom_path = match cli_args.get_rom_path() {
+        Some(rom_path) => rom_path.to_path_buf(),
+        None => select_rom(&roms_folder, chip8_db.as_ref())?
     };
 
-    roms_path.push_str(roms.get(FileSelectionUtil::file_selection_menu(&roms)).unwrap());
-
-    let mut sdl_ctrl =
-        SDLController::new(CustomWindow::new(
-            "Rusted - Chip-8 Emulator/Interpreter",
-            64,
-            32,
-            emu_settings.get_scale(),
-            emu_settings.get_bg_color(),
-            emu_settings.get_pixel_color()
-        ));
-
-    let mut mem_ctrl = MemoryController::new(Memory::new());
-    mem_ctrl.init_ram(&roms_path);
-
-    let mut cpu_ctrl = CpuController::new(
-        &mem_ctrl,
-        emu_settings.get_cycles_per_frame(),
-        emu_settings.get_bit_shift_instructions_use_vy(),
-        emu_settings.get_store_read_instructions_change_i());
-
-    let mut keyboard_ctrl = KeyboardController::new(Keyboard::new());
-
-    let mut buzzer_ctrl = BuzzerController::new_square_wave_buzzer(
-        sdl_ctrl.get_audio_subsystem(),
-        emu_settings.get_st_equals_buzzer());
-
-    EmuController::run_emulator(
-        &mut sdl_ctrl,
-        &mut mem_ctrl,
-        &mut cpu_ctrl,
-        &mut keyboard_ctrl,
-        &mut buzzer_ctrl);
+    // SDL is initialized once (when the first window is opened) and shared by all the windows,
+    // while the window and the overlay menu are kept when another ROM is loaded.
+    let mut sdl: Option<(SdlContext, EventPump)> = None;
+    let mut sdl_ctrl: Option<SDLController> = None;
+    let mut overlay = Overlay::new(roms_folder);
+
+    loop {
+        let mut mem_ctrl = MemoryController::new(Memory::new());
+        mem_ctrl.init_ram(&rom_path)?;
+
+        let rom_info = chip8_db.as_ref().and_then(|chip8_db| chip8_db.find(mem_ctrl.get_rom_sha1()));
+        if let Some(rom_info) = &rom_info {
+            println!("ROM: {} [{}]", rom_info.get_description(), rom_info.get_platform().get_name());
+            if !rom_info.get_keys().is_empty() {
+                println!("Keys: {}", rom_info.get_key_hints());
+            }
+            if rom_info.get_platform() != Platform::Chip8 {
+                println!("Warning: only the original CHIP-8 instruction set is supported, this ROM may not run correctly.");
+            }
+        }
+
+        let (emu_settings, rom_profile) =
+            get_rom_settings(&base_settings, rom_info.as_ref(), mem_ctrl.get_rom_sha1(), &rom_path, cli_args);
+
+        let mut cpu_ctrl = CpuController::new(
+            &mem_ctrl,
+            emu_settings.get_cycles_per_frame(),
+            emu_settings.get_bit_shift_instructions_use_vy(),
+            emu_settings.get_store_read_instructions_change_i(),
+            emu_settings.get_fault_policy(),
+            cli_args.get_seed());
+        cpu_ctrl.set_unknown_opcode_policy(emu_settings.get_unknown_opcode_policy());
+        cpu_ctrl.set_sys_handler(emu_settings.get_sys_calls().create_handler());
+        if cli_args.get_trace() {
+            cpu_ctrl.set_tracer(Some(Tracer::new(
+                cli_args.get_trace_path(),
+                cli_args.get_trace_ranges().to_vec(),
+                cli_args.get_trace_last())?));
+        }
+        if cli_args.get_profile() {
+            cpu_ctrl.set_profiler(Some(Profiler::new()));
+        }
+        if cli_args.get_coverage() {
+            cpu_ctrl.set_coverage(Some(Coverage::new()));
+        }
+        #[cfg(feature = "jit")]
+        if let Some(jit_mode) = cli_args.get_jit() {
+            cpu_ctrl.set_jit(Some(Jit::new(jit_mode)?));
+        }
+
+        let mut screen_ctrl = ScreenController::new(Screen::new());
+
+        let mut keyboard_ctrl = KeyboardController::with_key_bindings(Keyboard::new(), emu_settings.get_key_bindings());
+
+        if cli_args.get_headless() {
+            EmuController::run_headless(
+                &mut mem_ctrl,
+                &mut cpu_ctrl,
+                &mut screen_ctrl,
+                &mut keyboard_ctrl,
+                cli_args.get_frames());
+
+            print!("{}", screen_ctrl.to_ascii());
+            print_run_reports(&cpu_ctrl, &mem_ctrl, cli_args)?;
+            return Ok(());
+        }
+
+        let win_title = match &rom_info {
+            Some(rom_info) => format!("{} - Rusted - Chip-8 Emulator/Interpreter", rom_info.get_description()),
+            None => String::from("Rusted - Chip-8 Emulator/Interpreter")
+        };
+
+        let (sdl_ctx, event_pump) = match sdl {
+            Some((ref sdl_ctx, ref mut event_pump)) => (sdl_ctx, event_pump),
+            None => {
+                let sdl_ctx = SdlContext::new()?;
+                let event_pump = sdl_ctx.get_event_pump()?;
+                let (sdl_ctx, event_pump) = sdl.insert((sdl_ctx, event_pump));
+                (&*sdl_ctx, event_pump)
+            }
+        };
+
+        if !cli_args.get_compare().is_empty() {
+            return run_compare(sdl_ctx, event_pump, &rom_path, &emu_settings, &win_title, cli_args);
+        }
+
+        if let Some(sdl_ctrl) = &mut sdl_ctrl {
+            sdl_ctrl.set_title(&win_title);
+            sdl_ctrl.set_scale(emu_settings.get_scale());
+            sdl_ctrl.set_colors(emu_settings.get_bg_color(), emu_settings.get_pixel_color());
+        }
+
+        let sdl_ctrl = match sdl_ctrl {
+            Some(ref mut sdl_ctrl) => sdl_ctrl,
+            None => sdl_ctrl.insert(SDLController::new(sdl_ctx, CustomWindow::new(
+                sdl_ctx,
+                &win_title,
+                SCREEN_W,
+                SCREEN_H,
+                emu_settings.get_scale(),
+                emu_settings.get_bg_color(),
+                emu_settings.get_pixel_color(),
+                emu_settings.get_vsync()
+            )?))
+        };
+
+        let mut buzzer_ctrl = create_buzzer(sdl_ctx, &emu_settings);
+
+        let emu_exit = EmuController::run_emulator(
+            sdl_ctrl,
+            event_pump,
+            &mut mem_ctrl,
+            &mut cpu_ctrl,
+            &mut screen_ctrl,
+            &mut keyboard_ctrl,
+            &mut buzzer_ctrl,
+            &rom_profile,
+            &mut FrameScheduler::new(emu_settings.get_vsync()),
+            &mut overlay,
+            cli_args.get_watch(),
+            cli_args.get_frames())?;
+
+        // Moves past the line of the frame rate.
+        println!();
+        print_run_reports(&cpu_ctrl, &mem_ctrl, cli_args)?;
+
+        match emu_exit {
+            EmuExit::Quit => return Ok(()),
+            EmuExit::LoadRom(new_rom_path) => rom_path = new_rom_path
+        }
+    }
+}
+
+// The settings a ROM runs with, where each source overrides the previous one:
+// settings file < CHIP-8 database < ROM profile < command line.
+fn get_rom_settings(
+    base_settings: &EmuSettings,
+    rom_info: Option<&RomInfo>,
+    rom_sha1: &str,
+    rom_path: &Path,
+    cli_args: &CliArgs) -> (EmuSettings, RomProfile) {
+
+    let mut emu_settings = base_settings.clone();
+    if let Some(rom_info) = rom_info {
+        rom_info.apply_to(&mut emu_settings);
+    }
+
+    let rom_name = rom_path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+    let rom_profile = RomProfile::new(&emu_settings.get_profile_folder(), rom_sha1, &rom_name);
+    rom_profile.load_into(&mut emu_settings);
+    cli_args.apply_to(&mut emu_settings);
+    (emu_settings, rom_profile)
+}
+
+// Without an audio device, the emulator still runs (silently).
+fn create_buzzer(sdl_ctx: &SdlContext, emu_settings: &EmuSettings) -> BuzzerController {
+    let buzzer_result = sdl_ctx.get_audio_subsystem().and_then(|audio_subsystem| {
+        BuzzerController::new_square_wave_buzzer(
+            audio_subsystem,
+            emu_settings.get_st_equals_buzzer(),
+            emu_settings.get_buzzer_frequency(),
+            emu_settings.get_buzzer_volume())
+    });
+
+    match buzzer_result {
+        Ok(buzzer_ctrl) => buzzer_ctrl,
+        Err(err) => {
+            println!("Warning: {err}, running without sound.");
+            BuzzerController::new_silent()
+        }
+    }
+}
+
+// Runs the ROM in a window per set of quirks (the ones of the settings and the ones given with --compare), side by side.
+// All the machines use the same seed, so the differences only come from the quirks.
+fn run_compare(
+    sdl_ctx: &SdlContext,
+    event_pump: &mut EventPump,
+    rom_path: &Path,
+    emu_settings: &EmuSettings,
+    win_title: &str,
+    cli_args: &CliArgs) -> Result<(), EmuError> {
+
+    let rom = MemoryController::read_rom(rom_path)?;
+    let seed = cli_args.get_seed().unwrap_or_else(|| {
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos() as u64)
+    });
+
+    let mut compared_settings = vec![(String::from("settings"), emu_settings.clone())];
+    for (quirks_name, quirks) in cli_args.get_compare() {
+        let mut quirk_settings = emu_settings.clone();
+        quirks.apply_to(&mut quirk_settings);
+        compared_settings.push((quirks_name.clone(), quirk_settings));
+    }
+
+    let mut machines = Vec::new();
+    for (column, (quirks_name, quirk_settings)) in compared_settings.iter().enumerate() {
+        let mut emulator = Emulator::with_settings(quirk_settings);
+        emulator.set_seed(seed);
+        emulator.load_rom(&rom)?;
+
+        // Without vsync, since every window would wait for its own refresh.
+        let mut sdl_ctrl = SDLController::new(sdl_ctx, CustomWindow::new(
+            sdl_ctx,
+            &format!("[{quirks_name}] {win_title}"),
+            SCREEN_W,
+            SCREEN_H,
+            quirk_settings.get_scale(),
+            quirk_settings.get_bg_color(),
+            quirk_settings.get_pixel_color(),
+            false)?);
+        sdl_ctrl.set_canvas_scale();
+        sdl_ctrl.set_column(column as u32, compared_settings.len() as u32);
+        println!("Window {}: {quirks_name} (bit_shift_instructions_use_vy={}, store_read_instructions_change_i={}).",
+            column + 1,
+            quirk_settings.get_bit_shift_instructions_use_vy(),
+            quirk_settings.get_store_read_instructions_change_i());
+        machines.push((emulator, sdl_ctrl));
+    }
+
+    EmuController::run_side_by_side(
+        &mut machines,
+        event_pump,
+        &mut KeyboardController::with_key_bindings(Keyboard::new(), emu_settings.get_key_bindings()),
+        &mut create_buzzer(sdl_ctx, emu_settings),
+        &mut FrameScheduler::new(false),
+        cli_args.get_frames());
+    Ok(())
+}
+
+// Runs every ROM of the folder headless, in parallel threads, and prints how each one ran.
+// Each ROM runs with its own settings, like it would be run alone.
+fn run_batch(
+    batch_folder: &Path,
+    base_settings: &EmuSettings,
+    chip8_db: Option<&Chip8Database>,
+    cli_args: &CliArgs) -> Result<(), EmuError> {
+
+    let mut roms = FileSelectionUtil::get_files_in_directory(batch_folder)
+        .map_err(|source| EmuError::FolderRead { path: batch_folder.to_path_buf(), source })?;
+    roms.sort();
+
+    let mut jobs = Vec::new();
+    for rom in roms {
+        let rom_path = batch_folder.join(rom);
+        let rom_bytes = match MemoryController::read_rom(&rom_path) {
+            Ok(rom_bytes) => rom_bytes,
+            Err(err) => {
+                println!("Skipping '{}': {err}.", rom_path.display());
+                continue;
+            }
+        };
+
+        let rom_sha1 = Sha1::from(&rom_bytes).digest().to_string();
+        let rom_info = chip8_db.and_then(|chip8_db| chip8_db.find(&rom_sha1));
+        let (emu_settings, _) = get_rom_settings(base_settings, rom_info.as_ref(), &rom_sha1, &rom_path, cli_args);
+        #[cfg_attr(not(feature = "jit"), allow(unused_mut))]
+        let mut job = BatchJob::new(rom_path, rom_info.map(|rom_info| rom_info.get_description()), rom_bytes, emu_settings);
+        #[cfg(feature = "jit")]
+        job.set_jit_mode(cli_args.get_jit());
+        jobs.push(job);
+    }
+
+    if jobs.is_empty() {
+        return Err(EmuError::NoRomsFound { path: batch_folder.to_path_buf() });
+    }
+
+    let frames = cli_args.get_frames().unwrap_or(DEFAULT_BATCH_FRAMES);
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+    println!("Running {} ROMs for {frames} frames each ({threads} threads).", jobs.len());
+
+    let results = BatchRunner::run(&jobs, frames, cli_args.get_seed(), threads);
+    for result in &results {
+        println!("{}", result.get_line());
+    }
+    let ok_count = results.iter().filter(|result| result.is_ok()).count();
+    println!("{ok_count} of {} ROMs ran without faults or unknown instructions.", results.len());
+    Ok(())
+}
+
+// Prints what was gathered while the ROM was running: the unknown opcodes and, if enabled, the profile
+// and the coverage (which are written to their files instead, if they were given).
+fn print_run_reports(cpu_ctrl: &CpuController, mem_ctrl: &MemoryController, cli_args: &CliArgs) -> Result<(), EmuError> {
+    if let Some(summary) = cpu_ctrl.get_unknown_opcode_summary() {
+        println!("{summary}");
+    }
+
+    #[cfg(feature = "jit")]
+    if let Some(report) = cpu_ctrl.get_jit().and_then(|jit| jit.get_lockstep_report()) {
+        println!("{report}");
+    }
+
+    if let Some(profiler) = cpu_ctrl.get_profiler() {
+        match cli_args.get_profile_path() {
+            Some(profile_path) => {
+                fs::write(profile_path, profiler.get_report() + "\n")
+                    .map_err(|source| EmuError::ReportWrite { path: profile_path.to_path_buf(), source })?;
+                println!("Profile written to '{}'.", profile_path.display());
+            },
+            None => println!("{}", profiler.get_report())
+        }
+    }
+
+    if let Some(coverage) = cpu_ctrl.get_coverage() {
+        let rom_range = mem_ctrl.get_rom_range();
+        let report = coverage.get_report(&rom_range);
+        match cli_args.get_coverage_path() {
+            Some(coverage_path) if coverage_path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) => {
+                coverage.save_png(coverage_path, &rom_range)
+                    .map_err(|reason| EmuError::ReportWrite {
+                        path: coverage_path.to_path_buf(),
+                        source: io::Error::other(reason)
+                    })?;
+                println!("{report}\nCoverage map written to '{}'.", coverage_path.display());
+            },
+            Some(coverage_path) => {
+                fs::write(coverage_path, format!("{report}\n\n{}\n", coverage.get_ascii_map(&rom_range)))
+                    .map_err(|source| EmuError::ReportWrite { path: coverage_path.to_path_buf(), source })?;
+                println!("Coverage written to '{}'.", coverage_path.display());
+            },
+            None => println!("{report}\n\n{}", coverage.get_ascii_map(&rom_range))
+        }
+    }
+    Ok(())
+}
+
+// Lists the ROMs of the folder in the terminal (with their titles, if they're in the CHIP-8 database)
+// and returns the path of the selected one.
+fn select_rom(roms_folder: &Path, chip8_db: Option<&Chip8Database>) -> Result<PathBuf, EmuError> {
+    let roms = FileSelectionUtil::get_files_in_directory(roms_folder)
+        .map_err(|source| EmuError::FolderRead { path: roms_folder.to_path_buf(), source })?;
+
+    let rom_titles: Vec<Option<String>> = roms.iter()
+        .map(|rom| {
+            let rom_bytes = fs::read(roms_folder.join(rom)).ok()?;
+            let rom_info = chip8_db?.find(&Sha1::from(rom_bytes).digest().to_string())?;
+            Some(rom_info.get_description())
+        })
+        .collect();
+
+    match FileSelectionUtil::file_selection_menu(&roms, &rom_titles)? {
+        Some(choice) => Ok(roms_folder.join(&roms[choice])),
+        None => Err(EmuError::NoRomsFound { path: roms_folder.to_path_buf() })
+    }
 }
@@ -2,125 +2,222 @@
 // SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
 
 pub mod wrapper {
-    use crate::emu::memory::memory::MemoryController;
-    use crate::util::utilities::Math2d;
-
-    use sdl2::{ AudioSubsystem, EventPump, Sdl };
+    use crate::emu::backend::backends::DisplayBackend;
+    use crate::emu::display::screen::{ ScreenController, SCREEN_H, SCREEN_W };
+    use crate::emu::font::bitmap_font::{ self, GLYPH_H, GLYPH_W };
+    use crate::error::emu_error::EmuError;
+    use crate::util::utilities::PathUtil;
+
+    use sdl2::{ AudioSubsystem, EventPump, GameControllerSubsystem, Sdl };
+    use sdl2::controller::GameController;
     use sdl2::image::LoadSurface;
     use sdl2::pixels::Color;
-    use sdl2::rect::Point;
-    use sdl2::render::Canvas;
+    use sdl2::rect::{ Point, Rect };
+    use sdl2::render::{ BlendMode, Canvas };
     use sdl2::surface::Surface;
-    use sdl2::video::Window;
+    use sdl2::video::{ Window, WindowPos };
 
-    pub struct CustomWindow {
+    // The SDL library, initialized once per process and shared by all the windows (and the audio and input).
+    // SDL must be used from the thread that created it, so the machines running in other threads are headless.
+    pub struct SdlContext {
         sdl_context: Sdl,
+    }
+
+    impl SdlContext {
+        pub fn new() -> Result<Self, EmuError> {
+            Ok(Self { sdl_context: sdl2::init().map_err(EmuError::Sdl)?, })
+        }
+
+        pub fn get_audio_subsystem(&self) -> Result<AudioSubsystem, EmuError> {
+            self.sdl_context.audio().map_err(EmuError::Audio)
+        }
+
+        // There can only be one event pump, which receives the events of all the windows.
+        pub fn get_event_pump(&self) -> Result<EventPump, EmuError> {
+            self.sdl_context.event_pump().map_err(EmuError::Sdl)
+        }
+
+        // None if the host doesn't support gamepads.
+        pub fn get_game_controller_subsystem(&self) -> Option<GameControllerSubsystem> {
+            self.sdl_context.game_controller().ok()
+        }
+    }
+
+    pub struct CustomWindow {
         win_w: u32,
         win_h: u32,
         scale: u32,
         canvas: Canvas<Window>,
-        // pixel_vec is used to represent the pixels on the screen
-        // for extremely fast collision checking (renderer independent).
-        // Since it's a 1d vector and the canvas is 2d, it's always indexed with the following formula:
-        // window width * y + x
-        pixel_vec: Vec<u8>,
         bg_color: Color,
         pixel_color: Color,
     }
 
     impl CustomWindow {
+        #[allow(clippy::too_many_arguments)]
         pub fn new(
+            sdl_ctx: &SdlContext,
             win_title: &str,
             win_w: u32,
             win_h: u32,
             scale: u32,
             bg_color: Color,
-            pixel_color: Color) -> Self {
-            let sdl_context = sdl2::init().unwrap();
+            pixel_color: Color,
+            vsync: bool) -> Result<Self, EmuError> {
             let win_w_scaled = win_w * scale;
             let win_h_scaled = win_h * scale;
 
-            let mut canvas = sdl_context.video().unwrap()
-                .window(win_title, win_w_scaled, win_h_scaled).position_centered().build().unwrap()
-                .into_canvas().build().unwrap();
+            let canvas_builder = sdl_ctx.sdl_context.video().map_err(EmuError::Sdl)?
+                .window(win_title, win_w_scaled, win_h_scaled).position_centered().build()
+                .map_err(|err| EmuError::Sdl(err.to_string()))?
+                .into_canvas();
 
-            if let Ok(win_icon) = Surface::from_file(".\\assets\\img\\icon-64x64.png") {
+            let canvas_builder = if vsync { canvas_builder.present_vsync() } else { canvas_builder };
+            let mut canvas = canvas_builder.build().map_err(|err| EmuError::Sdl(err.to_string()))?;
+
+            if let Some(Ok(win_icon)) = PathUtil::find_asset("img/icon-64x64.png").map(Surface::from_file) {
                 canvas.window_mut().set_icon(win_icon);
             }
             
-            Self {
-                sdl_context,
+            Ok(Self {
                 win_w,
                 win_h,
                 scale,
                 canvas,
-                pixel_vec: {
-                    let mut vec: Vec<u8> = Vec::new();
-                    vec.resize((win_w * win_h) as usize, 0);
-                    vec.fill(0);
-                    vec
-                },
                 bg_color,
                 pixel_color,
-            }
+            })
         }
     }
 
     pub struct SDLController {
         window: CustomWindow,
+        // None if the host doesn't support gamepads.
+        game_controller_subsystem: Option<GameControllerSubsystem>,
+        // The opened gamepads, which only send events while they're kept open.
+        gamepads: Vec<GameController>,
     }
 
     impl SDLController {
-        pub fn new(window: CustomWindow) -> Self {
-            Self { window }
-        }
-
-        // put_pixel() is called by the Dxyn instruction.
-        pub fn put_pixel(&mut self, x: u8, y: u8, mem_ctrl: &mut MemoryController){
-            let corrected_x = Math2d::wrap_coord(x, self.window.win_w);
-            let corrected_y = Math2d::wrap_coord(y, self.window.win_h);
-            let pixel_vec_i = (self.window.win_w * corrected_y as u32 + corrected_x as u32) as usize;
-
-            // Drawing color is white if drawing on top of an OFF pixel, black otherwise.
-            let draw_color: Color =
-                if self.window.pixel_vec[pixel_vec_i] == 0 {
-                    self.window.pixel_color
-                } else {
-                    if mem_ctrl.get_v(0xf) == 0 {
-                        mem_ctrl.set_v(0xf, 1); // vf = collision flag
-                    }
-                    self.window.bg_color
-                };
+        pub fn new(sdl_ctx: &SdlContext, window: CustomWindow) -> Self {
+            Self {
+                window,
+                game_controller_subsystem: sdl_ctx.get_game_controller_subsystem(),
+                gamepads: Vec::new(),
+            }
+        }
 
-            self.window.canvas.set_draw_color(draw_color);
-            self.window.canvas.draw_point(Point::new(corrected_x as i32, corrected_y as i32)).unwrap();
+        // Draws the current state of the emulator screen into the canvas.
+        pub fn render(&mut self, screen_ctrl: &ScreenController) {
+            self.present(screen_ctrl.get_pixels());
+        }
 
-            self.window.canvas.set_draw_color(self.window.bg_color);
+        // Draws lines of text with the built-in font over the emulator screen.
+        // With dim_background, the whole screen is darkened (e.g. for a menu),
+        // otherwise only the area behind the text is covered.
+        pub fn render_text(&mut self, lines: &[String], dim_background: bool) {
+            let win_w = self.window.win_w * self.window.scale;
+            let win_h = self.window.win_h * self.window.scale;
+
+            // The text grows with the window, fitting about 80 characters per line.
+            let px = (win_w / 320).max(1);
+            let char_w = (GLYPH_W + 1) * px;
+            let line_h = (GLYPH_H + 2) * px;
+            let margin = 2 * px;
+
+            let canvas = &mut self.window.canvas;
+            let _ = canvas.set_scale(1.0, 1.0);
+
+            let bg_color = self.window.bg_color;
+            if dim_background {
+                canvas.set_blend_mode(BlendMode::Blend);
+                canvas.set_draw_color(Color::RGBA(bg_color.r, bg_color.g, bg_color.b, 220));
+                let _ = canvas.fill_rect(Rect::new(0, 0, win_w, win_h));
+                canvas.set_blend_mode(BlendMode::None);
+            } else {
+                let text_w = lines.iter().map(|line| line.chars().count() as u32).max().unwrap_or(0) * char_w;
+                canvas.set_draw_color(bg_color);
+                let _ = canvas.fill_rect(Rect::new(0, 0, text_w + 2 * margin, lines.len() as u32 * line_h + 2 * margin));
+            }
 
-            // Flips the current pixel's virtual representation.
-            self.window.pixel_vec[pixel_vec_i] = if self.window.pixel_vec[pixel_vec_i] == 0 { 1 } else { 0 };
-        }
+            let mut on_pixels: Vec<Rect> = Vec::new();
+            for (row, line) in lines.iter().enumerate() {
+                let line_y = (margin + row as u32 * line_h) as i32;
+                for (col, c) in line.chars().enumerate() {
+                    let char_x = (margin + col as u32 * char_w) as i32;
+                    for (glyph_y, glyph_row) in bitmap_font::get_glyph(c).iter().enumerate() {
+                        for glyph_x in 0..GLYPH_W {
+                            if glyph_row & (0b100 >> glyph_x) != 0 {
+                                on_pixels.push(Rect::new(
+                                    char_x + (glyph_x * px) as i32,
+                                    line_y + (glyph_y as u32 * px) as i32,
+                                    px,
+                                    px));
+                            }
+                        }
+                    }
+                }
+            }
 
-        pub fn clear_screen(&mut self) {
-            self.window.pixel_vec.fill(0);
-            self.window.canvas.set_draw_color(self.window.bg_color);
-            self.window.canvas.clear();
+            canvas.set_draw_color(self.window.pixel_color);
+            let _ = canvas.fill_rects(on_pixels.as_slice());
+
+            self.set_canvas_scale();
         }
 
         pub fn display_canvas(&mut self) {
             self.window.canvas.present();
         }
 
-        pub fn get_audio_subsystem(&self) ->AudioSubsystem {
-            self.window.sdl_context.audio().unwrap()
+        pub fn get_window(&self) -> &CustomWindow {
+            &self.window
         }
 
-        pub fn get_event_pump(&self) -> EventPump {
-            self.window.sdl_context.event_pump().unwrap()
+        pub fn get_bg_color(&self) -> Color {
+            self.window.bg_color
         }
 
-        pub fn get_window(&self) -> &CustomWindow {
-            &self.window
+        pub fn get_pixel_color(&self) -> Color {
+            self.window.pixel_color
+        }
+
+        pub fn set_colors(&mut self, bg_color: Color, pixel_color: Color) {
+            self.window.bg_color = bg_color;
+            self.window.pixel_color = pixel_color;
+        }
+
+        pub fn set_title(&mut self, win_title: &str) {
+            let _ = self.window.canvas.window_mut().set_title(win_title);
+        }
+
+        // Places the window in a column of windows shown side by side (0 is the leftmost), vertically centered.
+        pub fn set_column(&mut self, column: u32, columns: u32) {
+            let window = self.window.canvas.window_mut();
+            let (win_w, _) = window.size();
+            let gap = 16;
+            let total_w = columns * win_w + (columns - 1) * gap;
+            let x = match window.subsystem().display_bounds(0) {
+                Ok(bounds) => bounds.x() + (bounds.width() as i32 - total_w as i32).max(0) / 2,
+                Err(_) => 0
+            };
+            window.set_position(WindowPos::Positioned(x + (column * (win_w + gap)) as i32), WindowPos::Centered);
+        }
+
+        // Resizes the window to the new video resolution scale.
+        pub fn set_scale(&mut self, scale: u32) {
+            self.window.scale = scale;
+            let _ = self.window.canvas.window_mut().set_size(self.window.win_w * scale, self.window.win_h * scale);
+            self.set_canvas_scale();
+        }
+
+        // Opens a connected gamepad (by its joystick index) so its events are received.
+        pub fn open_gamepad(&mut self, joystick_index: u32) {
+            let gamepad = self.game_controller_subsystem.as_ref()
+                .and_then(|subsystem| subsystem.open(joystick_index).ok());
+
+            if let Some(gamepad) = gamepad {
+                println!("\nGamepad connected: {}", gamepad.name());
+                self.gamepads.push(gamepad);
+            }
         }
 
         pub fn set_canvas_scale(&mut self) {
@@ -130,4 +227,23 @@ pub mod wrapper {
                 .unwrap()
         }
     }    
+
+    impl DisplayBackend for SDLController {
+        // The points of the pixels that are on are gathered in a fixed-size buffer, instead of a new Vec every frame.
+        fn present(&mut self, pixels: &[u8]) {
+            self.window.canvas.set_draw_color(self.window.bg_color);
+            self.window.canvas.clear();
+
+            let mut on_pixels = [Point::new(0, 0);(SCREEN_W * SCREEN_H) as usize];
+            let mut on_pixel_count = 0;
+            for (i, _) in pixels.iter().enumerate().filter(|(_, pixel)| **pixel == 1) {
+                on_pixels[on_pixel_count] = Point::new((i as u32 % SCREEN_W) as i32, (i as u32 / SCREEN_W) as i32);
+                on_pixel_count += 1;
+            }
+
+            self.window.canvas.set_draw_color(self.window.pixel_color);
+            // A frame that fails to be drawn is simply skipped.
+            let _ = self.window.canvas.draw_points(&on_pixels[..on_pixel_count]);
+        }
+    }
 }
@@ -2,7 +2,17 @@
 // SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
 
 pub mod utilities {
+    #[cfg(feature = "desktop")]
+    use crate::error::emu_error::EmuError;
+
+    #[cfg(feature = "desktop")]
+    use sdl2::pixels::Color;
+    #[cfg(feature = "desktop")]
+    use std::env;
+    #[cfg(feature = "desktop")]
     use std::io::Result;
+    #[cfg(feature = "desktop")]
+    use std::path::{ Path, PathBuf };
 
     pub struct BitUtil;
 
@@ -26,12 +36,10 @@ pub mod utilities {
                 hundreds = decimal / 100;
                 let hundreds_mod = decimal % 100;
                 tens = hundreds_mod / 10;
-                let tens_mod = hundreds_mod % 10;
-                ones = tens_mod / 1;
-            } else if decimal >= 10 && decimal <= 99 {
+                ones = hundreds_mod % 10;
+            } else if (10..=99).contains(&decimal) {
                 tens = decimal / 10;
-                let tens_mod = decimal % 10;
-                ones = tens_mod / 1;
+                ones = decimal % 10;
             } else if decimal <= 9 {
                 ones = decimal;
             }
@@ -50,6 +58,23 @@ pub mod utilities {
         }
     }
 
+    #[cfg(feature = "desktop")]
+    pub struct ColorUtil;
+
+    #[cfg(feature = "desktop")]
+    impl ColorUtil {
+        // Parses colors written as "RRGGBB" or "#RRGGBB".
+        pub fn parse_hex_color(hex: &str) -> Option<Color> {
+            let hex = hex.trim().trim_start_matches('#');
+            if hex.len() != 6 {
+                return None;
+            }
+
+            let rgb = u32::from_str_radix(hex, 16).ok()?;
+            Some(Color::RGB((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+        }
+    }
+
     pub struct Math2d;
 
     impl Math2d {
@@ -58,10 +83,91 @@ pub mod utilities {
         }
     }
 
+    #[cfg(feature = "desktop")]
+    pub struct PathUtil;
+
+    #[cfg(feature = "desktop")]
+    impl PathUtil {
+        // Name of the folder used inside the user's config and data directories.
+        const APP_FOLDER: &'static str = "rusted-chip8";
+
+        // Expands a leading "~" into the user's home directory.
+        pub fn expand_home(path: &str) -> PathBuf {
+            match (path.strip_prefix('~'), PathUtil::home_dir()) {
+                (Some(""), Some(home_dir)) => home_dir,
+                (Some(rest), Some(home_dir)) if rest.starts_with(['/', '\\']) =>
+                    home_dir.join(&rest[1..]),
+                _ => PathBuf::from(path)
+            }
+        }
+
+        // Absolute paths (and paths starting with "~") are kept as they are,
+        // while relative paths are resolved from the given base folder.
+        pub fn resolve(path: &str, base_folder: &Path) -> PathBuf {
+            let expanded_path = PathUtil::expand_home(path);
+            if expanded_path.is_absolute() {
+                expanded_path
+            } else {
+                base_folder.join(expanded_path)
+            }
+        }
+
+        pub fn home_dir() -> Option<PathBuf> {
+            let home_var = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+            env::var_os(home_var).filter(|home| !home.is_empty()).map(PathBuf::from)
+        }
+
+        // Directory for the user's settings:
+        // $XDG_CONFIG_HOME/rusted-chip8 (or ~/.config/rusted-chip8) on Linux,
+        // ~/Library/Application Support/rusted-chip8 on macOS and %APPDATA%\rusted-chip8 on Windows.
+        pub fn config_dir() -> Option<PathBuf> {
+            PathUtil::user_dir("XDG_CONFIG_HOME", ".config")
+        }
+
+        // Directory for the user's data (profiles, database, etc.):
+        // $XDG_DATA_HOME/rusted-chip8 (or ~/.local/share/rusted-chip8) on Linux,
+        // ~/Library/Application Support/rusted-chip8 on macOS and %APPDATA%\rusted-chip8 on Windows.
+        pub fn data_dir() -> Option<PathBuf> {
+            PathUtil::user_dir("XDG_DATA_HOME", ".local/share")
+        }
+
+        // Directory where the emulator's executable is located.
+        pub fn exe_dir() -> Option<PathBuf> {
+            env::current_exe().ok()?.parent().map(Path::to_path_buf)
+        }
+
+        // Looks for an asset (e.g. "img/icon-64x64.png") in the "assets" folder located next to the executable,
+        // in the user's data directory or in the current directory, in this order.
+        pub fn find_asset(asset: &str) -> Option<PathBuf> {
+            [PathUtil::exe_dir(), PathUtil::data_dir(), env::current_dir().ok()]
+                .into_iter()
+                .flatten()
+                .map(|folder| folder.join("assets").join(asset))
+                .find(|path| path.is_file())
+        }
+
+        fn user_dir(xdg_var: &str, home_fallback: &str) -> Option<PathBuf> {
+            let base_dir = if cfg!(windows) {
+                env::var_os("APPDATA").filter(|dir| !dir.is_empty()).map(PathBuf::from)?
+            } else if cfg!(target_os = "macos") {
+                PathUtil::home_dir()?.join("Library/Application Support")
+            } else {
+                match env::var_os(xdg_var).map(PathBuf::from) {
+                    // The XDG specification says relative paths must be ignored.
+                    Some(xdg_dir) if xdg_dir.is_absolute() => xdg_dir,
+                    _ => PathUtil::home_dir()?.join(home_fallback)
+                }
+            };
+            Some(base_dir.join(PathUtil::APP_FOLDER))
+        }
+    }
+
+    #[cfg(feature = "desktop")]
     pub struct FileSelectionUtil;
 
+    #[cfg(feature = "desktop")]
     impl FileSelectionUtil {
-        pub fn get_files_in_directory(path: &str) -> Result<Vec<String>> {
+        pub fn get_files_in_directory(path: &Path) -> Result<Vec<String>> {
             let entries = std::fs::read_dir(path)?;
         
             let file_names: Vec<String> = entries
@@ -78,28 +184,29 @@ pub mod utilities {
             Ok(file_names)
         }
 
-        pub fn file_selection_menu(roms: &Vec<String>) -> usize {
-            if roms.len() == 0 {
-                panic!("Couldn't find any .ch8 file in the folder provided to 'default_ch8_folder' in 'config.txt'!");
+        // rom_titles holds an optional description of each ROM (e.g. its title and authors), shown next to its name.
+        // Returns the index of the selected ROM, or None if there's no CH8 file to select.
+        pub fn file_selection_menu(roms: &[String], rom_titles: &[Option<String>]) -> std::result::Result<Option<usize>, EmuError> {
+            if !roms.iter().any(|rom| rom.to_lowercase().contains(".ch8")) {
+                return Ok(None);
             }
 
             let mut choice: usize;
             loop {
-                let mut contains_ch8 = false;
                 println!("\nSelect a file to run:");
                 for (i, rom) in roms.iter().enumerate() {
                     if rom.to_lowercase().contains(".ch8") {
-                        println!("[{i}]: {rom}");
-                        contains_ch8 = true;
+                        match rom_titles.get(i) {
+                            Some(Some(rom_title)) => println!("[{i}]: {rom} - {rom_title}"),
+                            _ => println!("[{i}]: {rom}")
+                        }
                     }
                 }
 
-                if !contains_ch8 {
-                    panic!("Couldn't find any .ch8 file in the folder provided to 'default_ch8_folder' in 'config.txt'!");
-                }
-
                 let mut input_str = String::new();
-                std::io::stdin().read_line(&mut input_str).unwrap();
+                if std::io::stdin().read_line(&mut input_str).map_err(EmuError::Input)? == 0 {
+                    return Err(EmuError::Input(std::io::ErrorKind::UnexpectedEof.into()));
+                }
             
                 choice = match input_str.trim().parse() {
                     Ok(choice) => choice,
@@ -118,7 +225,7 @@ pub mod utilities {
 
             println!();
 
-            choice
+            Ok(Some(choice))
         }
     }
 }
@@ -0,0 +1,123 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// Bindings for JavaScript when the core is built for wasm32-unknown-unknown (without the "desktop" feature).
+// They're plain C functions, so no binding generator is needed: web/rusted-chip8.js wraps them in a class.
+// The emulators are handed to JavaScript as pointers, which must be given back to the other functions.
+pub mod js_bindings {
+    use crate::emu::api::emulator_api::{ Emulator, SCREEN_H, SCREEN_W };
+
+    use std::alloc::{ self, Layout };
+
+    /// Creates an emulator, seeding its random numbers (Cxkk) with `seed` (e.g. from `Math.random()`).
+    #[no_mangle]
+    pub extern "C" fn chip8_new(seed: u32) -> *mut Emulator {
+        let mut emulator = Emulator::new();
+        emulator.set_seed(seed as u64);
+        Box::into_raw(Box::new(emulator))
+    }
+
+    /// # Safety
+    /// `emulator` must come from `chip8_new()` and must not be used afterwards.
+    #[no_mangle]
+    pub unsafe extern "C" fn chip8_free(emulator: *mut Emulator) {
+        drop(Box::from_raw(emulator));
+    }
+
+    /// Reserves `len` bytes in the WebAssembly memory, where JavaScript copies the ROM before loading it.
+    #[no_mangle]
+    pub extern "C" fn chip8_alloc(len: usize) -> *mut u8 {
+        match Layout::array::<u8>(len) {
+            Ok(layout) if len > 0 => unsafe { alloc::alloc(layout) },
+            _ => std::ptr::null_mut()
+        }
+    }
+
+    /// # Safety
+    /// `ptr` and `len` must come from `chip8_alloc()`.
+    #[no_mangle]
+    pub unsafe extern "C" fn chip8_dealloc(ptr: *mut u8, len: usize) {
+        if let Ok(layout) = Layout::array::<u8>(len) {
+            if !ptr.is_null() {
+                alloc::dealloc(ptr, layout);
+            }
+        }
+    }
+
+    /// Loads the `len` bytes of ROM at `rom` and restarts the machine.
+    /// Returns false if the ROM doesn't fit in the RAM.
+    ///
+    /// # Safety
+    /// `emulator` must come from `chip8_new()` and `rom` must point to `len` readable bytes.
+    #[no_mangle]
+    pub unsafe extern "C" fn chip8_load_rom(emulator: *mut Emulator, rom: *const u8, len: usize) -> bool {
+        (*emulator).load_rom(std::slice::from_raw_parts(rom, len)).is_ok()
+    }
+
+    /// # Safety
+    /// `emulator` must come from `chip8_new()`.
+    #[no_mangle]
+    pub unsafe extern "C" fn chip8_reset(emulator: *mut Emulator) {
+        (*emulator).reset();
+    }
+
+    /// Executes a single instruction.
+    ///
+    /// # Safety
+    /// `emulator` must come from `chip8_new()`.
+    #[no_mangle]
+    pub unsafe extern "C" fn chip8_step(emulator: *mut Emulator) {
+        (*emulator).step();
+    }
+
+    /// Runs a (60hz) frame and returns how many instructions were executed.
+    ///
+    /// # Safety
+    /// `emulator` must come from `chip8_new()`.
+    #[no_mangle]
+    pub unsafe extern "C" fn chip8_run_frame(emulator: *mut Emulator) -> u32 {
+        (*emulator).run_frame() as u32
+    }
+
+    /// Sets the keys that are held down, as a bit per key (bit 0 is the key 0 and bit 15 is the key F).
+    ///
+    /// # Safety
+    /// `emulator` must come from `chip8_new()`.
+    #[no_mangle]
+    pub unsafe extern "C" fn chip8_set_keys(emulator: *mut Emulator, key_bits: u32) {
+        let mut keys = [false;16];
+        for (i, key) in keys.iter_mut().enumerate() {
+            *key = key_bits & (1 << i) != 0;
+        }
+        (*emulator).set_keys(keys);
+    }
+
+    /// The screen, as `chip8_screen_width() * chip8_screen_height()` bytes by rows (1 is on and 0 is off).
+    /// The pointer stays valid while the emulator exists, so JavaScript can keep a view of it.
+    ///
+    /// # Safety
+    /// `emulator` must come from `chip8_new()`.
+    #[no_mangle]
+    pub unsafe extern "C" fn chip8_framebuffer(emulator: *const Emulator) -> *const u8 {
+        (*emulator).framebuffer().as_ptr()
+    }
+
+    #[no_mangle]
+    pub extern "C" fn chip8_screen_width() -> u32 {
+        SCREEN_W
+    }
+
+    #[no_mangle]
+    pub extern "C" fn chip8_screen_height() -> u32 {
+        SCREEN_H
+    }
+
+    /// Whether the buzzer must sound.
+    ///
+    /// # Safety
+    /// `emulator` must come from `chip8_new()`.
+    #[no_mangle]
+    pub unsafe extern "C" fn chip8_sound_active(emulator: *const Emulator) -> bool {
+        (*emulator).sound_active()
+    }
+}
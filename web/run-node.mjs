@@ -0,0 +1,32 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// Runs a ROM on the WebAssembly build without a browser and prints the screen, like the --headless option:
+// node web/run-node.mjs <rusted_chip8.wasm> <ROM> [frames] [seed]
+import { readFile } from "node:fs/promises";
+import { Chip8 } from "./rusted-chip8.js";
+
+const [wasmPath, romPath, frames = "60", seed = "0"] = process.argv.slice(2);
+if (!wasmPath || !romPath) {
+    console.error("Usage: node web/run-node.mjs <rusted_chip8.wasm> <ROM> [frames] [seed]");
+    process.exit(2);
+}
+
+const chip8 = await Chip8.load(await readFile(wasmPath), Number(seed));
+chip8.loadRom(await readFile(romPath));
+
+let instructions = 0;
+for (let frame = 0; frame < Number(frames); frame++) {
+    instructions += chip8.runFrame();
+}
+
+const pixels = chip8.framebuffer();
+for (let y = 0; y < chip8.height; y++) {
+    let row = "";
+    for (let x = 0; x < chip8.width; x++) {
+        row += pixels[y * chip8.width + x] ? "#" : ".";
+    }
+    console.log(row);
+}
+console.log(`${frames} frames, ${instructions} instructions.`);
+chip8.free();
@@ -0,0 +1,69 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2023 Rodrigo M. Cucick <r_monfredini@hotmail.com>
+
+// JavaScript side of the WebAssembly build (see src/wasm.rs), usable from a web page or from Node.js.
+// The ROMs run on the same core as the desktop emulator, without SDL and without files.
+export class Chip8 {
+    // source: the URL of rusted_chip8.wasm (in a browser) or its bytes (e.g. read with fs.readFile in Node.js).
+    static async load(source, seed = Math.floor(Math.random() * 0x100000000)) {
+        const { instance } = (typeof source === "string" || source instanceof URL)
+            ? await WebAssembly.instantiateStreaming(fetch(source))
+            : await WebAssembly.instantiate(source);
+        return new Chip8(instance.exports, seed);
+    }
+
+    constructor(exports, seed) {
+        this.exports = exports;
+        this.emulator = exports.chip8_new(seed >>> 0);
+        this.width = exports.chip8_screen_width();
+        this.height = exports.chip8_screen_height();
+    }
+
+    // rom: the bytes of the ROM (an ArrayBuffer or a typed array). Throws if it doesn't fit in the RAM.
+    loadRom(rom) {
+        const bytes = new Uint8Array(rom.buffer ?? rom, rom.byteOffset ?? 0, rom.byteLength);
+        const ptr = this.exports.chip8_alloc(bytes.length);
+        new Uint8Array(this.exports.memory.buffer, ptr, bytes.length).set(bytes);
+        const loaded = this.exports.chip8_load_rom(this.emulator, ptr, bytes.length);
+        this.exports.chip8_dealloc(ptr, bytes.length);
+        if (!loaded) {
+            throw new Error(`the ROM has ${bytes.length} bytes, but it doesn't fit in the RAM`);
+        }
+    }
+
+    reset() {
+        this.exports.chip8_reset(this.emulator);
+    }
+
+    step() {
+        this.exports.chip8_step(this.emulator);
+    }
+
+    // Must be called 60 times per second, returns how many instructions were executed.
+    runFrame() {
+        return this.exports.chip8_run_frame(this.emulator);
+    }
+
+    // keys: 16 booleans, indexed by the hex value of the key (0 to F).
+    setKeys(keys) {
+        let keyBits = 0;
+        keys.forEach((isDown, i) => { if (isDown) keyBits |= 1 << i; });
+        this.exports.chip8_set_keys(this.emulator, keyBits);
+    }
+
+    // The pixels by rows (1 is on and 0 is off). The view is created again each time,
+    // since the WebAssembly memory may have grown (which detaches the previous views).
+    framebuffer() {
+        const ptr = this.exports.chip8_framebuffer(this.emulator);
+        return new Uint8Array(this.exports.memory.buffer, ptr, this.width * this.height);
+    }
+
+    soundActive() {
+        return this.exports.chip8_sound_active(this.emulator) !== 0;
+    }
+
+    free() {
+        this.exports.chip8_free(this.emulator);
+        this.emulator = 0;
+    }
+}